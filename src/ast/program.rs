@@ -1,4 +1,4 @@
-pub use crate::parser::token::Operator;
+pub use crate::parser::token::{Operator, Span};
 
 #[derive(Debug)]
 pub enum Item {
@@ -8,6 +8,8 @@ pub enum Item {
 #[derive(Debug)]
 
 pub struct ItemFn {
+    /// 直前の `///` のドキュメントコメント。複数行は改行でつなぐ
+    pub doc: Option<String>,
     pub signature: FnSignature,
     pub block: Vec<Statement>,
 }
@@ -15,55 +17,284 @@ pub struct ItemFn {
 #[derive(Debug)]
 pub enum Statement {
     Local(Local),
+    Assign(Assign),
     FnCall(FnCall),
     Return(Expr),
+    If(If),
+    While(While),
+    Loop(Vec<Statement>),
+    /// `{ ... }` だけのブロック。中で宣言した変数は外から見えない
+    Block(Vec<Statement>),
+    Break(Span),
+    Continue(Span),
 }
 
 #[derive(Debug)]
 pub struct Local {
     pub name: String,
-    pub var_type: String,
+    pub mutable: bool,
+    /// 型注釈が無い場合は `None`
+    pub var_type: Option<Ty>,
     pub value: Expr,
+    /// 変数名のSpan
+    pub span: Span,
+}
+
+/// `x = expr;`。`x += expr;` は `x = x + expr;` として表す
+#[derive(Debug)]
+pub struct Assign {
+    pub name: String,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// `else if` は `else_block` の中の `If` 1つとして表す
+#[derive(Debug)]
+pub struct If {
+    pub cond: Expr,
+    pub then_block: Vec<Statement>,
+    pub else_block: Option<Vec<Statement>>,
+}
+
+#[derive(Debug)]
+pub struct While {
+    pub cond: Expr,
+    pub body: Vec<Statement>,
 }
 
 #[derive(Debug)]
 pub struct FnCall {
     pub name: String,
     pub args: Vec<Expr>,
+    /// 関数名のSpan
+    pub span: Span,
 }
 
+/// `x.wrapping_add(1)` のようなメソッド呼び出し
+#[derive(Debug)]
+pub struct MethodCall {
+    pub receiver: Box<Expr>,
+    pub name: String,
+    pub args: Vec<Expr>,
+    /// メソッド名のSpan
+    pub span: Span,
+}
+
+/// `wrapping_add` や `checked_neg` のように、桁あふれした時の扱いを名前で選ぶ整数のメソッド
+#[derive(Debug, PartialEq, Clone)]
+pub struct IntMethod {
+    /// `checked_*` なら結果を `Option` で返し、`wrapping_*` なら切り詰める
+    pub checked: bool,
+    /// 演算の演算子。`neg` は `None`
+    pub op: Option<Operator>,
+}
+
+impl IntMethod {
+    pub fn from_name(name: &str) -> Option<IntMethod> {
+        let (checked, op) = match name.strip_prefix("wrapping_") {
+            Some(op) => (false, op),
+            None => (true, name.strip_prefix("checked_")?),
+        };
+        let op = match op {
+            "add" => Some(Operator::Plus),
+            "sub" => Some(Operator::Minus),
+            "mul" => Some(Operator::Asterisk),
+            "div" => Some(Operator::Slash),
+            "rem" => Some(Operator::Percent),
+            "shl" => Some(Operator::Shl),
+            "shr" => Some(Operator::Shr),
+            "neg" => None,
+            _ => return None,
+        };
+        Some(IntMethod { checked, op })
+    }
+}
+
+/// `checked_*` の結果の `Option` に使えるメソッド。`Option` の値そのものはまだ扱えない
+pub const OPTION_METHODS: [&str; 4] = ["unwrap", "unwrap_or", "is_some", "is_none"];
+
 #[derive(Debug)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
     ExprLit(String),
+    /// `1.5` や `2e10`
+    ExprFloatLit(String),
+    /// エスケープを解いた文字列リテラルの中身
+    ExprStrLit(String),
+    /// `'a'`
+    ExprCharLit(char),
+    /// `b"abc"`
+    ExprByteStrLit(Vec<u8>),
     ExprBinaryOp {
         left: Box<Expr>,
         op: Operator,
         right: Box<Expr>,
     },
+    /// `-x` と `!x`
+    ExprUnary {
+        op: Operator,
+        operand: Box<Expr>,
+    },
+    /// `true` と `false`
+    ExprBoolLit(bool),
+    /// `x as u8`
+    ExprCast {
+        expr: Box<Expr>,
+        ty: Ty,
+    },
     ExprVariable(String),
     ExprFnCall(FnCall),
+    ExprMethodCall(MethodCall),
 }
 
 #[derive(Debug)]
 pub struct FnSignature {
     pub ident: String,
     pub args: Vec<FnParams>,
-    pub output: Option<String>,
+    pub output: Option<Ty>,
+    /// 関数名のSpan
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct FnParams {
     pub name: String,
-    pub arg_type: String,
+    pub arg_type: Ty,
+    pub span: Span,
 }
 
+/// `const NAME: T = expr;`
 #[derive(Debug)]
 pub struct ItemConst {
-    name: String,
-    value: String,
+    /// 直前の `///` のドキュメントコメント
+    pub doc: Option<String>,
+    pub name: String,
+    pub const_type: Ty,
+    pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct Program {
     pub items: Vec<Item>,
+    /// 入力の終端。`main` が無い時はここを指す
+    pub end: Span,
+}
+
+/// 式や変数の型
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Ty {
+    I8,
+    I16,
+    I32,
+    I64,
+    Isize,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    F64,
+    /// 比較演算や論理演算の結果
+    Bool,
+    /// Unicode のスカラー値
+    Char,
+    /// 文字列リテラル `&str`
+    Str,
+    /// バイト文字列リテラル `&[u8; N]`
+    ByteStr(usize),
+    /// 戻り値の無い関数の型 `()`
+    Unit,
+}
+
+impl Ty {
+    /// 整数型。リテラルのサフィックスにも使える
+    pub const INTEGERS: [Ty; 10] = [
+        Ty::I8,
+        Ty::I16,
+        Ty::I32,
+        Ty::I64,
+        Ty::Isize,
+        Ty::U8,
+        Ty::U16,
+        Ty::U32,
+        Ty::U64,
+        Ty::Usize,
+    ];
+
+    pub fn is_integer(self) -> bool {
+        Ty::INTEGERS.contains(&self)
+    }
+
+    pub fn is_numeric(self) -> bool {
+        self.is_integer() || self == Ty::F64
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            Ty::I8 | Ty::I16 | Ty::I32 | Ty::I64 | Ty::Isize | Ty::F64
+        )
+    }
+
+    /// 値の大きさ (ビット)。`isize` と `usize` は x86-64 なので 64 ビット
+    pub fn bits(self) -> u32 {
+        match self {
+            Ty::I8 | Ty::U8 | Ty::Bool => 8,
+            Ty::I16 | Ty::U16 => 16,
+            Ty::I32 | Ty::U32 | Ty::Char => 32,
+            Ty::I64 | Ty::Isize | Ty::U64 | Ty::Usize | Ty::F64 | Ty::Str | Ty::ByteStr(_) => 64,
+            Ty::Unit => 0,
+        }
+    }
+
+    /// 整数型の最小値と最大値
+    pub fn int_range(self) -> (i128, i128) {
+        let bits = self.bits();
+        if self.is_signed() {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
+        }
+    }
+
+    /// 整数を型の大きさで切り詰める。`300` を `u8` にすると `44`、`255` を `i8` にすると `-1` になる
+    pub fn wrap(self, value: i128) -> i128 {
+        let bits = self.bits();
+        let truncated = value & ((1 << bits) - 1);
+        if self.is_signed() && truncated >> (bits - 1) == 1 {
+            truncated - (1 << bits)
+        } else {
+            truncated
+        }
+    }
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Ty::I8 => "i8",
+            Ty::I16 => "i16",
+            Ty::I32 => "i32",
+            Ty::I64 => "i64",
+            Ty::Isize => "isize",
+            Ty::U8 => "u8",
+            Ty::U16 => "u16",
+            Ty::U32 => "u32",
+            Ty::U64 => "u64",
+            Ty::Usize => "usize",
+            Ty::F64 => "f64",
+            Ty::Bool => "bool",
+            Ty::Char => "char",
+            Ty::Str => "&str",
+            Ty::ByteStr(len) => return write!(f, "&[u8; {}]", len),
+            Ty::Unit => "()",
+        };
+        f.write_str(s)
+    }
 }
@@ -1,264 +1,2130 @@
+use super::const_eval::{
+    ConstTable, ConstValue, evaluate_consts, float_bits, parse_float_literal, parse_int_literal,
+};
+use super::encoder::{Encoder, MachineCode, Object, parse_number};
+use super::frame::{Frame, StackSlot};
+use super::gas;
+use super::instruction::{Cond, Instruction, Operand, RegClass, Serialize, VReg};
+use super::regalloc::{RegAlloc, allocate};
 use super::rst::*;
 use super::syscall::*;
-use crate::ast::program::{Expr, Item, ItemFn, Program, Statement};
+use super::target::Target;
+use crate::ast::program::Ty;
+use crate::ast::program::{
+    Expr, ExprKind, If, IntMethod, Item, ItemFn, MethodCall, OPTION_METHODS, Operator, Program,
+    Span, Statement, While,
+};
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::parser::format::{FormatPiece, parse_format};
+use crate::resolve::resolve::{DefId, DefKind, Resolutions};
+use crate::thir::typeck::TypeckResults;
 
-pub fn generate_code(program: &Program) -> AsmCode {
-    let mut asm_code = AsmCode::new();
+/// コード生成の設定
+pub struct CodegenOptions<'a> {
+    pub target: &'a dyn Target,
+    pub regalloc: RegAlloc,
+    /// パニックのメッセージに書くソースファイルの名前
+    pub source_name: &'a str,
+    /// 整数の演算の桁あふれを実行時に確かめてパニックするか (`-C overflow-checks`)
+    pub overflow_checks: bool,
+    /// C のランタイムを使わずにリンクする時に、`main` を呼んで終了する `_start` を出力するか
+    pub start_routine: bool,
+}
+
+pub fn generate_code(
+    program: &Program,
+    resolutions: &Resolutions,
+    types: &TypeckResults,
+    options: &CodegenOptions,
+) -> Result<AsmCode, Vec<Diagnostic>> {
+    let consts = evaluate_consts(program)?;
+    let mut asm_code = AsmCode::new(options.target);
+    let mut diagnostics = Vec::new();
+    let mut strings = StrTable::default();
     for item in &program.items {
         match item {
             Item::ItemFn(item_fn) => {
-                handle_fn(&mut asm_code, item_fn);
+                let ctx =
+                    FnContext::new(item_fn, &consts, resolutions, types, options, &mut strings);
+                diagnostics.extend(handle_fn(&mut asm_code, item_fn, ctx, options.regalloc));
             }
-            _ => { /* Handle other item types if necessary */ }
+            // 定数は参照している箇所に値を埋め込むので、コードは生成しない
+            Item::ItemConst(_) => (),
         }
     }
-    asm_code
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    asm_code.rodata_sec.extend(strings.directives);
+    // 実行時の処理は、呼ばれている時だけ出力する
+    if asm_code.calls(PRINT_INT_FN) {
+        asm_code.text_sec.push(print_int_fn(options.target));
+    }
+    if asm_code.calls(PRINT_CHAR_FN) {
+        asm_code.text_sec.push(print_char_fn(options.target));
+    }
+    if asm_code.calls(PANIC_FN) {
+        asm_code.text_sec.push(panic_fn(options.target));
+    }
+    if options.start_routine {
+        asm_code.directives.push(format!("global {}", START_FN));
+        asm_code.text_sec.push(start_fn(options.target));
+    }
+    Ok(asm_code)
 }
 
-struct RstManagerInFn {
-    val_register_map: std::collections::HashMap<String, Rst>,
-    rsts_for_return: Vec<Rst>,
-    rsts_for_arguments: Vec<Rst>,
-    rsts_for_general: Vec<Rst>,
+/// `return` の飛び先。エピローグの先頭に置く
+const RETURN_LABEL: &str = ".fn_return";
+
+/// パニックのメッセージを標準エラー出力に書いて終了する関数。`rdi` にメッセージ、`rsi` に長さを渡す
+const PANIC_FN: &str = "__likerustc_panic";
+
+/// 整数を10進数で書き出す関数。`rdi` に値、`rsi` にファイルディスクリプタ、
+/// `rdx` に符号付きなら 1、符号無しなら 0 を渡す
+const PRINT_INT_FN: &str = "__likerustc_print_int";
+
+/// `char` を UTF-8 で書き出す関数。`rdi` に文字、`rsi` にファイルディスクリプタを渡す
+const PRINT_CHAR_FN: &str = "__likerustc_print_char";
+
+/// C のランタイムを使わない時の実行の開始位置。リンカの既定の開始位置と同じ名前にする
+pub const START_FN: &str = "_start";
+
+/// パニックした時の終了コード。Rustと同じにする
+const PANIC_EXIT_CODE: &str = "101";
+
+/// 関数1つ分のコード生成の状態
+struct FnContext<'a> {
+    instructions: Vec<Instruction>,
+    data_directives: Vec<DataDirective>,
+    /// パニックのメッセージなど、書き換えないデータ
+    rodata_directives: Vec<DataDirective>,
+    vreg_count: usize,
+    /// 変数の値を入れておく仮想レジスタ
+    variables: std::collections::HashMap<DefId, VReg>,
+    consts: &'a ConstTable,
+    resolutions: &'a Resolutions,
+    types: &'a TypeckResults,
+    target: &'a dyn Target,
+    source_name: &'a str,
+    overflow_checks: bool,
+    label_count: usize,
+    /// パニックする箇所ごとの (飛び先のラベル, メッセージのラベル)。エピローグの後ろに置く
+    panic_stubs: Vec<(String, String)>,
+    fn_name: String,
+    diagnostics: Vec<Diagnostic>,
+    /// 囲んでいるループの (continueの飛び先, breakの飛び先)。内側のループほど後ろにある
+    loop_labels: Vec<(String, String)>,
+    /// データセクションに置いた浮動小数点数のビット列とそのラベル。同じ値は1つにまとめる
+    float_constants: std::collections::HashMap<u64, String>,
+    /// プログラム全体で共有する文字列の置き場所
+    strings: &'a mut StrTable,
 }
 
-impl RstManagerInFn {
-    fn new(args: &Vec<crate::ast::program::FnParams>) -> Self {
-        let mut val_register_map = std::collections::HashMap::<String, Rst>::new();
-        for (i, arg) in args.iter().enumerate() {
-            let rst = match i {
-                0 => Rst::RDI,
-                1 => Rst::RSI,
-                2 => Rst::RDX,
-                3 => Rst::RCX,
-                4 => Rst::R8,
-                5 => Rst::R9,
-                _ => break,
+impl<'a> FnContext<'a> {
+    fn new(
+        item_fn: &ItemFn,
+        consts: &'a ConstTable,
+        resolutions: &'a Resolutions,
+        types: &'a TypeckResults,
+        options: &CodegenOptions<'a>,
+        strings: &'a mut StrTable,
+    ) -> Self {
+        let mut ctx = FnContext {
+            instructions: Vec::new(),
+            data_directives: Vec::new(),
+            rodata_directives: Vec::new(),
+            vreg_count: 0,
+            variables: std::collections::HashMap::new(),
+            consts,
+            resolutions,
+            types,
+            target: options.target,
+            source_name: options.source_name,
+            overflow_checks: options.overflow_checks,
+            label_count: 0,
+            panic_stubs: Vec::new(),
+            fn_name: item_fn.signature.ident.clone(),
+            diagnostics: Vec::new(),
+            loop_labels: Vec::new(),
+            float_constants: std::collections::HashMap::new(),
+            strings,
+        };
+        // 引数は関数の先頭で仮想レジスタに移し、呼び出しで壊れないようにする
+        let classes: Vec<RegClass> = item_fn
+            .signature
+            .args
+            .iter()
+            .map(|arg| reg_class(arg.arg_type))
+            .collect();
+        for (arg, src) in item_fn
+            .signature
+            .args
+            .iter()
+            .zip(argument_locations(&classes))
+        {
+            let src = match src {
+                ArgLocation::Rst(rst) => Operand::Rst(rst),
+                ArgLocation::Stack(index) => Operand::Stack(StackSlot::incoming_argument(index)),
             };
-            val_register_map.insert(arg.name.clone(), rst);
+            let vreg = ctx.new_vreg_of(reg_class(arg.arg_type));
+            ctx.push_move(vreg.into(), src, vreg.1);
+            ctx.variables.insert(resolutions.decl(arg.span), vreg);
         }
-        RstManagerInFn {
-            val_register_map: val_register_map,
-            rsts_for_general: vec![Rst::R10, Rst::R11, Rst::R12, Rst::R13, Rst::R14, Rst::R15]
-                .into_iter()
-                .rev()
-                .collect(),
-            rsts_for_arguments: vec![Rst::RDI, Rst::RSI, Rst::RDX, Rst::RCX, Rst::R8, Rst::R9]
-                .into_iter()
-                .rev()
-                .collect(),
-            rsts_for_return: vec![Rst::RAX, Rst::RDX].into_iter().rev().collect(),
+        ctx
+    }
+
+    fn new_vreg(&mut self) -> VReg {
+        self.new_vreg_of(RegClass::Int)
+    }
+
+    fn new_vreg_of(&mut self, class: RegClass) -> VReg {
+        let vreg = VReg(self.vreg_count, class);
+        self.vreg_count += 1;
+        vreg
+    }
+
+    /// 式の値を入れる仮想レジスタの種類
+    fn expr_class(&self, expr: &Expr) -> RegClass {
+        match self.types.expr_ty(expr) {
+            Some(ty) => reg_class(ty),
+            None => RegClass::Int,
         }
     }
 
-    fn get_rst_from_map(&self, name: String) -> Rst {
-        *self.val_register_map.get(&name).unwrap()
+    /// 値の種類に合った `mov` を積む
+    fn push_move(&mut self, dest: Operand, src: Operand, class: RegClass) {
+        self.push(match class {
+            RegClass::Int => Instruction::MOVE { dest, src },
+            RegClass::Float => Instruction::MOVSD { dest, src },
+        });
     }
 
-    fn pop_general_rsts(&mut self, name: String) -> Rst {
-        let gst = self.rsts_for_general.pop().unwrap();
-        self.val_register_map.insert(name, gst);
-        gst
+    /// 浮動小数点数の定数をデータセクションに置き、そのメモリを返す。
+    /// SSE の命令は即値を取れないので、定数もメモリから読む
+    fn float_constant(&mut self, value: f64) -> Operand {
+        let bits = value.to_bits();
+        if let Some(label) = self.float_constants.get(&bits) {
+            return Operand::Mem(label.clone());
+        }
+        let label = format!("{}.f64_{}", self.fn_name, self.float_constants.len());
+        self.data_directives.push(DataDirective::DQ {
+            left: Some(label.clone()),
+            right: float_bits(value),
+        });
+        self.float_constants.insert(bits, label.clone());
+        Operand::Mem(label)
     }
 
-    fn pop_argument_rsts(&mut self) -> Rst {
-        self.rsts_for_arguments.pop().unwrap()
+    /// 文字列を読み取り専用のデータセクションに置き、そのラベルを返す。長さは `{label}_len` で参照する
+    fn str_constant(&mut self, text: &str) -> String {
+        self.strings.intern(text.as_bytes())
     }
 
-    fn init_argument_rsts(&mut self) {
-        self.rsts_for_arguments = vec![Rst::RDI, Rst::RSI, Rst::RDX, Rst::RCX, Rst::R8, Rst::R9]
-            .into_iter()
-            .rev()
-            .collect();
+    /// 関数内で一意なローカルラベル (`.while_start_0` など) を作る
+    fn new_label(&mut self, kind: &str) -> String {
+        let label = format!(".{}_{}", kind, self.label_count);
+        self.label_count += 1;
+        label
     }
 
-    fn pop_return_rsts(&mut self) -> Rst {
-        self.rsts_for_return.pop().unwrap()
+    fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
     }
 
-    fn init_return_rsts(&mut self) {
-        self.rsts_for_return = vec![Rst::RAX, Rst::RDX].into_iter().rev().collect();
+    /// `span` の位置で `message` を出してパニックする処理を作り、その飛び先を返す
+    fn new_panic_stub(&mut self, span: Span, message: &str) -> String {
+        let index = self.panic_stubs.len();
+        let label = format!(".panic_{}", index);
+        // 関数名に使えない `.` を入れて、他の関数のメッセージと区別する
+        let msg_label = format!("{}.panic_msg_{}", self.fn_name, index);
+        self.rodata_directives.push(DataDirective::DB {
+            left: msg_label.clone(),
+            right: format!(
+                "thread 'main' panicked at {}:{}:{}:\n{}\n\
+                 note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n",
+                self.source_name, span.line, span.column, message
+            )
+            .into_bytes(),
+        });
+        self.rodata_directives.push(DataDirective::EQUE {
+            left: format!("{}_len", msg_label),
+            right: vec![format!("$ - {}", msg_label)],
+        });
+        self.panic_stubs.push((label.clone(), msg_label));
+        label
     }
-}
 
-fn handle_fn(asm_code: &mut AsmCode, item_fn: &ItemFn) {
-    let mut instructions = Vec::<Instruction>::new();
-    let mut data_directives = Vec::<DataDirective>::new();
-    let mut rgt_manager = RstManagerInFn::new(item_fn.signature.args.as_ref());
-    item_fn.block.iter().for_each(|stmt| {
-        match stmt {
-            Statement::FnCall(fn_call) => {
-                handle_fn_call(
-                    &mut instructions,
-                    fn_call,
-                    &mut rgt_manager,
-                    &mut data_directives,
-                );
-            }
-            Statement::Local(local) => {
-                let rst = handle_experession(&mut instructions, &local.value, &mut rgt_manager);
-                rgt_manager.val_register_map.insert(local.name.clone(), rst);
-            }
-            Statement::Return(ret) => {
-                let ret_rst = handle_experession(&mut instructions, ret, &mut rgt_manager);
-                instructions.push(Instruction::MOVE {
-                    dest: rgt_manager.pop_return_rsts().to_string(),
-                    src: ret_rst.to_string(),
-                });
-                instructions.push(Instruction::RET);
-                rgt_manager.init_return_rsts();
+    /// 式の型。型検査で決まらなかった整数は `i32` とみなす
+    fn expr_ty(&self, expr: &Expr) -> Ty {
+        self.types.expr_ty(expr).unwrap_or(Ty::I32)
+    }
+
+    /// 64 ビットのレジスタに入った `ty` の値の上位ビットを、符号付きなら符号拡張、
+    /// 符号無しならゼロ拡張して揃える。演算の後に呼び、はみ出した桁を捨てる
+    fn normalize(&mut self, vreg: VReg, ty: Ty) {
+        if ty == Ty::Bool || ty.bits() == 64 {
+            return;
+        }
+        let (dest, src, width) = (vreg.into(), vreg.into(), exact_width(ty));
+        self.push(if ty.is_signed() {
+            Instruction::MOVSX { dest, src, width }
+        } else {
+            Instruction::MOVZX { dest, src, width }
+        });
+    }
+
+    /// `-C overflow-checks` が有効なら、`span` の位置で桁あふれのパニックをする処理を作る
+    fn overflow_panic(&mut self, span: Span, action: &str) -> Option<String> {
+        self.overflow_checks
+            .then(|| self.new_panic_stub(span, &format!("attempt to {} with overflow", action)))
+    }
+
+    /// コンパイル時に値が分かる整数の式ならその値
+    fn constant_value(&self, expr: &Expr) -> Option<i128> {
+        match &expr.kind {
+            ExprKind::ExprLit(lit) => parse_int_literal(lit),
+            ExprKind::ExprVariable(name) => {
+                let def = self.resolutions.def(self.resolutions.res(expr.span));
+                match (def.kind, self.consts.get(name)) {
+                    (DefKind::Const, Some(ConstValue::Int(value))) => Some(*value),
+                    _ => None,
+                }
             }
+            _ => None,
         }
-    });
+    }
+
+    /// 変数なら値の入った仮想レジスタ、定数なら即値。浮動小数点数の定数はデータセクションのメモリ
+    fn variable_operand(&mut self, span: crate::ast::program::Span) -> Operand {
+        let def_id = self.resolutions.res(span);
+        let def = self.resolutions.def(def_id);
+        match def.kind {
+            DefKind::Const => match self.consts[&def.name] {
+                ConstValue::Float(value) => self.float_constant(value),
+                value => Operand::Imm(value.to_immediate()),
+            },
+            _ => self.variables[&def_id].into(),
+        }
+    }
+}
+
+/// `ty` の値を演算する大きさ。32 ビットより小さい型も 32 ビットで計算する
+fn op_width(ty: Ty) -> Width {
+    if ty.bits() == 64 {
+        Width::B64
+    } else {
+        Width::B32
+    }
+}
+
+/// `ty` の値がちょうど収まる大きさ
+fn exact_width(ty: Ty) -> Width {
+    match ty.bits() {
+        8 => Width::B8,
+        16 => Width::B16,
+        32 => Width::B32,
+        _ => Width::B64,
+    }
+}
+
+fn reg_class(ty: Ty) -> RegClass {
+    match ty {
+        Ty::F64 => RegClass::Float,
+        _ => RegClass::Int,
+    }
+}
+
+/// System V ABI で引数を渡す場所
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ArgLocation {
+    Rst(Rst),
+    /// レジスタに入りきらず、スタックで渡す何番目の引数か
+    Stack(usize),
+}
+
+/// 引数の種類ごとに渡す場所を決める。整数と浮動小数点数はそれぞれ別のレジスタを前から使い、
+/// 入りきらなかったものは引数の順にスタックに積む
+fn argument_locations(classes: &[RegClass]) -> Vec<ArgLocation> {
+    let mut ints = ARGUMENT_RSTS.iter();
+    let mut floats = FLOAT_ARGUMENT_RSTS.iter();
+    let mut stack_count = 0;
+    classes
+        .iter()
+        .map(|class| {
+            let rst = match class {
+                RegClass::Int => ints.next(),
+                RegClass::Float => floats.next(),
+            };
+            match rst {
+                Some(rst) => ArgLocation::Rst(*rst),
+                None => {
+                    stack_count += 1;
+                    ArgLocation::Stack(stack_count - 1)
+                }
+            }
+        })
+        .collect()
+}
+
+/// 関数のコードを生成し、生成中に見つかったエラーを返す
+fn handle_fn(
+    asm_code: &mut AsmCode,
+    item_fn: &ItemFn,
+    mut ctx: FnContext,
+    regalloc: RegAlloc,
+) -> Vec<Diagnostic> {
+    handle_block(&mut ctx, &item_fn.block);
     if item_fn.signature.ident == "main" {
-        handle_exit(&mut instructions);
+        handle_exit(&mut ctx);
+    }
+    // 最後まで実行された時も、次の関数へ落ちていかずにエピローグで戻る
+    ctx.push(Instruction::LABEL {
+        name: RETURN_LABEL.to_string(),
+    });
+    // `--emit=ir` 用に、レジスタ割り当て前の命令列を残しておく
+    asm_code.ir.push(FnCode {
+        label: item_fn.signature.ident.clone(),
+        instructions: ctx.instructions.clone(),
+    });
+    let mut frame = Frame::default();
+    let (body, used_callee_saved) =
+        allocate(std::mem::take(&mut ctx.instructions), regalloc, &mut frame);
+    let (saved_rsts, frame_size) = frame.layout(&used_callee_saved);
+
+    ctx.push(Instruction::PUSH {
+        src: Rst::RBP.into(),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RBP.into(),
+        src: Rst::RSP.into(),
+    });
+    if frame_size > 0 {
+        ctx.push(Instruction::SUB {
+            dest: Rst::RSP.into(),
+            src: Operand::Imm(frame_size.to_string()),
+            width: Width::B64,
+        });
+    }
+    for (rst, slot) in &saved_rsts {
+        ctx.push(Instruction::MOVE {
+            dest: Operand::Stack(*slot),
+            src: (*rst).into(),
+        });
     }
+    ctx.instructions.extend(body);
+    for (rst, slot) in &saved_rsts {
+        ctx.push(Instruction::MOVE {
+            dest: (*rst).into(),
+            src: Operand::Stack(*slot),
+        });
+    }
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RSP.into(),
+        src: Rst::RBP.into(),
+    });
+    ctx.push(Instruction::POP {
+        dest: Rst::RBP.into(),
+    });
+    ctx.push(Instruction::RET);
+    for (label, msg_label) in std::mem::take(&mut ctx.panic_stubs) {
+        ctx.push(Instruction::LABEL { name: label });
+        ctx.push(Instruction::LOAD {
+            dest: Rst::RDI.into(),
+            addr: msg_label.clone(),
+        });
+        ctx.push(Instruction::MOVE {
+            dest: Rst::RSI.into(),
+            src: Operand::Imm(format!("{}_len", msg_label)),
+        });
+        ctx.push(Instruction::CALL {
+            func: PANIC_FN.to_string(),
+        });
+    }
+
     asm_code.text_sec.push(FnCode {
-        label: convert_to_asm_fn_name(&item_fn.signature.ident),
-        instructions,
+        label: ctx.target.symbol_name(&item_fn.signature.ident),
+        instructions: ctx.instructions,
     });
-    asm_code.data_sec.extend(data_directives);
+    asm_code.data_sec.extend(ctx.data_directives);
+    asm_code.rodata_sec.extend(ctx.rodata_directives);
+    ctx.diagnostics
 }
 
-fn handle_experession(
-    instructions: &mut Vec<Instruction>,
-    expr: &Expr,
-    rst_manager: &mut RstManagerInFn,
-) -> Rst {
-    match expr {
-        Expr::ExprLit(lit) => {
-            let rst = rst_manager.pop_general_rsts("tmp".to_string());
-            instructions.push(Instruction::MOVE {
-                dest: rst.to_string(),
-                src: lit.clone(),
+fn handle_block(ctx: &mut FnContext, statements: &[Statement]) {
+    for stmt in statements {
+        handle_statement(ctx, stmt);
+    }
+}
+
+fn handle_statement(ctx: &mut FnContext, stmt: &Statement) {
+    match stmt {
+        Statement::FnCall(fn_call) => {
+            handle_fn_call(ctx, fn_call);
+        }
+        Statement::Local(local) => {
+            let vreg = handle_experession(ctx, &local.value);
+            let def_id = ctx.resolutions.decl(local.span);
+            ctx.variables.insert(def_id, vreg);
+        }
+        Statement::Assign(assign) => {
+            let vreg = handle_experession(ctx, &assign.value);
+            let var = ctx.variables[&ctx.resolutions.res(assign.span)];
+            ctx.push_move(var.into(), vreg.into(), var.1);
+        }
+        Statement::Return(ret) => {
+            let vreg = handle_experession(ctx, ret);
+            let dest = match vreg.1 {
+                RegClass::Int => Rst::RAX,
+                RegClass::Float => Rst::XMM0,
+            };
+            ctx.push_move(dest.into(), vreg.into(), vreg.1);
+            ctx.push(Instruction::JMP {
+                label: RETURN_LABEL.to_string(),
             });
-            rst
-        }
-        Expr::ExprVariable(var_name) => {
-            let rst = rst_manager.get_rst_from_map(var_name.clone());
-            rst
-        }
-        Expr::ExprFnCall(fn_call) => {
-            handle_fn_call(instructions, fn_call, rst_manager, &mut Vec::new());
-            let rst = rst_manager.pop_general_rsts(fn_call.name.clone());
-            instructions.push(Instruction::MOVE {
-                dest: rst.to_string(),
-                src: rst_manager.pop_return_rsts().to_string(),
+        }
+        Statement::If(if_stmt) => handle_if(ctx, if_stmt),
+        Statement::While(while_stmt) => handle_while(ctx, while_stmt),
+        Statement::Loop(body) => {
+            let start = ctx.new_label("loop_start");
+            let end = ctx.new_label("loop_end");
+            ctx.push(Instruction::LABEL {
+                name: start.clone(),
             });
-            rst_manager.init_return_rsts();
-            rst
-        }
-        Expr::ExprBinaryOp { left, op, right } => {
-            let left_rst = handle_experession(instructions, left, rst_manager);
-            let right_rst = handle_experession(instructions, right, rst_manager);
-            match op {
-                crate::ast::program::Operator::Plus => {
-                    instructions.push(Instruction::ADD {
-                        dest: left_rst.to_string(),
-                        src: right_rst.to_string(),
-                    });
-                    left_rst
-                }
-                _ => {
-                    // Handle other operators if necessary
-                    unimplemented!()
-                }
+            ctx.loop_labels.push((start.clone(), end.clone()));
+            handle_block(ctx, body);
+            ctx.loop_labels.pop();
+            ctx.push(Instruction::JMP { label: start });
+            ctx.push(Instruction::LABEL { name: end });
+        }
+        Statement::Block(block) => handle_block(ctx, block),
+        Statement::Break(_) => {
+            let (_, end) = ctx.loop_labels.last().unwrap().clone();
+            ctx.push(Instruction::JMP { label: end });
+        }
+        Statement::Continue(_) => {
+            let (start, _) = ctx.loop_labels.last().unwrap().clone();
+            ctx.push(Instruction::JMP { label: start });
+        }
+    }
+}
+
+fn handle_if(ctx: &mut FnContext, if_stmt: &If) {
+    let else_label = ctx.new_label("if_else");
+    handle_condition(ctx, &if_stmt.cond, false, &else_label);
+    handle_block(ctx, &if_stmt.then_block);
+    match &if_stmt.else_block {
+        Some(else_block) => {
+            let end_label = ctx.new_label("if_end");
+            ctx.push(Instruction::JMP {
+                label: end_label.clone(),
+            });
+            ctx.push(Instruction::LABEL { name: else_label });
+            handle_block(ctx, else_block);
+            ctx.push(Instruction::LABEL { name: end_label });
+        }
+        None => ctx.push(Instruction::LABEL { name: else_label }),
+    }
+}
+
+fn handle_while(ctx: &mut FnContext, while_stmt: &While) {
+    let start = ctx.new_label("while_start");
+    let end = ctx.new_label("while_end");
+    ctx.push(Instruction::LABEL {
+        name: start.clone(),
+    });
+    handle_condition(ctx, &while_stmt.cond, false, &end);
+    ctx.loop_labels.push((start.clone(), end.clone()));
+    handle_block(ctx, &while_stmt.body);
+    ctx.loop_labels.pop();
+    ctx.push(Instruction::JMP { label: start });
+    ctx.push(Instruction::LABEL { name: end });
+}
+
+/// 条件式を評価し、結果が `jump_if` と等しければ `label` へジャンプする。
+/// `&&` と `||` は右辺を評価せずにジャンプできる場合がある
+fn handle_condition(ctx: &mut FnContext, cond: &Expr, jump_if: bool, label: &str) {
+    match &cond.kind {
+        ExprKind::ExprBinaryOp { left, op, right }
+            if comparison_cond(op).is_some() && ctx.expr_class(left) == RegClass::Float =>
+        {
+            let left_vreg = handle_experession(ctx, left);
+            let right_vreg = handle_experession(ctx, right);
+            handle_float_comparison(ctx, op, left_vreg, right_vreg, jump_if, label);
+        }
+        ExprKind::ExprBinaryOp { left, op, right } if comparison_cond(op).is_some() => {
+            let ty = ctx.expr_ty(left);
+            let left_vreg = handle_experession(ctx, left);
+            let right_vreg = handle_experession(ctx, right);
+            ctx.push(Instruction::CMP {
+                src1: left_vreg.into(),
+                src2: right_vreg.into(),
+                width: op_width(ty),
+            });
+            let cond = comparison_cond(op).unwrap();
+            let cond = if ty.is_signed() {
+                cond
+            } else {
+                cond.unsigned()
+            };
+            ctx.push(Instruction::JCC {
+                cond: if jump_if { cond } else { cond.negate() },
+                label: label.to_string(),
+            });
+        }
+        ExprKind::ExprBinaryOp {
+            left,
+            op: op @ (Operator::AndAnd | Operator::OrOr),
+            right,
+        } => {
+            // `a && b` が偽になるのは a が偽の時点、`a || b` が真になるのは a が真の時点で決まる
+            let short_circuit = *op == Operator::OrOr;
+            if jump_if == short_circuit {
+                handle_condition(ctx, left, jump_if, label);
+                handle_condition(ctx, right, jump_if, label);
+            } else {
+                let skip = ctx.new_label("cond_skip");
+                handle_condition(ctx, left, short_circuit, &skip);
+                handle_condition(ctx, right, jump_if, label);
+                ctx.push(Instruction::LABEL { name: skip });
             }
         }
+        ExprKind::ExprUnary {
+            op: Operator::Bang,
+            operand,
+        } if is_boolean_expr(ctx, cond) => {
+            handle_condition(ctx, operand, !jump_if, label);
+        }
+        _ => {
+            let vreg = handle_experession(ctx, cond);
+            ctx.push(Instruction::CMP {
+                src1: vreg.into(),
+                src2: Operand::Imm("0".to_string()),
+                width: Width::B64,
+            });
+            ctx.push(Instruction::JCC {
+                cond: if jump_if { Cond::NE } else { Cond::E },
+                label: label.to_string(),
+            });
+        }
     }
 }
 
-fn handle_fn_call(
-    instructions: &mut Vec<Instruction>,
-    fn_call: &crate::ast::program::FnCall,
-    rst_manager: &mut RstManagerInFn,
-    data_directives: &mut Vec<DataDirective>,
+/// 浮動小数点数を比較し、結果が `jump_if` と等しければ `label` へジャンプする。
+/// NaN との比較は `!=` 以外全て偽になるように、`ucomisd` の結果を符号無しの条件で読む
+fn handle_float_comparison(
+    ctx: &mut FnContext,
+    op: &Operator,
+    left: VReg,
+    right: VReg,
+    jump_if: bool,
+    label: &str,
 ) {
-    if fn_call.name == "println!" {
-        handle_println(instructions, fn_call, data_directives)
-    }
-    for arg in fn_call.args.iter() {
-        match arg {
-            Expr::ExprLit(lit) => {
-                instructions.push(Instruction::MOVE {
-                    dest: rst_manager.pop_argument_rsts().to_string(),
-                    src: lit.clone(),
-                });
+    // `a < b` は `b > a` として比べると、NaN の時に CF が立って偽になる
+    let (src1, src2, cond) = match op {
+        Operator::Gt => (left, right, Cond::A),
+        Operator::GtEq => (left, right, Cond::AE),
+        Operator::Lt => (right, left, Cond::A),
+        Operator::LtEq => (right, left, Cond::AE),
+        _ => (left, right, Cond::E),
+    };
+    ctx.push(Instruction::UCOMISD {
+        src1: src1.into(),
+        src2: src2.into(),
+    });
+    if cond != Cond::E {
+        ctx.push(Instruction::JCC {
+            cond: if jump_if { cond } else { cond.negate() },
+            label: label.to_string(),
+        });
+        return;
+    }
+    // NaN と比べると ZF も立つので、等しいかどうかは PF も合わせて見る
+    let jump_if_equal = jump_if == (*op == Operator::EqEq);
+    if jump_if_equal {
+        let skip = ctx.new_label("cond_skip");
+        ctx.push(Instruction::JCC {
+            cond: Cond::P,
+            label: skip.clone(),
+        });
+        ctx.push(Instruction::JCC {
+            cond: Cond::E,
+            label: label.to_string(),
+        });
+        ctx.push(Instruction::LABEL { name: skip });
+    } else {
+        ctx.push(Instruction::JCC {
+            cond: Cond::P,
+            label: label.to_string(),
+        });
+        ctx.push(Instruction::JCC {
+            cond: Cond::NE,
+            label: label.to_string(),
+        });
+    }
+}
+
+fn comparison_cond(op: &Operator) -> Option<Cond> {
+    match op {
+        Operator::EqEq => Some(Cond::E),
+        Operator::NotEq => Some(Cond::NE),
+        Operator::Lt => Some(Cond::L),
+        Operator::LtEq => Some(Cond::LE),
+        Operator::Gt => Some(Cond::G),
+        Operator::GtEq => Some(Cond::GE),
+        _ => None,
+    }
+}
+
+/// 比較や論理演算のように、条件分岐で真偽値を求める式か。
+/// `!x` は `x` の型が `bool` の時だけ論理否定になる
+fn is_boolean_expr(ctx: &FnContext, expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::ExprBinaryOp { op, .. } => {
+            comparison_cond(op).is_some() || matches!(op, Operator::AndAnd | Operator::OrOr)
+        }
+        ExprKind::ExprUnary {
+            op: Operator::Bang,
+            operand,
+        } => ctx.types.expr_ty(operand) == Some(Ty::Bool),
+        _ => false,
+    }
+}
+
+/// 式を評価し、結果を入れた新しい仮想レジスタを返す
+fn handle_experession(ctx: &mut FnContext, expr: &Expr) -> VReg {
+    if is_boolean_expr(ctx, expr) {
+        // 真偽値は条件分岐で 1 か 0 をレジスタに入れる
+        let vreg = ctx.new_vreg();
+        let false_label = ctx.new_label("bool_false");
+        let end_label = ctx.new_label("bool_end");
+        handle_condition(ctx, expr, false, &false_label);
+        ctx.push(Instruction::MOVE {
+            dest: vreg.into(),
+            src: Operand::Imm("1".to_string()),
+        });
+        ctx.push(Instruction::JMP {
+            label: end_label.clone(),
+        });
+        ctx.push(Instruction::LABEL { name: false_label });
+        ctx.push(Instruction::MOVE {
+            dest: vreg.into(),
+            src: Operand::Imm("0".to_string()),
+        });
+        ctx.push(Instruction::LABEL { name: end_label });
+        return vreg;
+    }
+    match &expr.kind {
+        ExprKind::ExprLit(_) | ExprKind::ExprBoolLit(_) => {
+            let vreg = ctx.new_vreg();
+            ctx.push(Instruction::MOVE {
+                dest: vreg.into(),
+                src: int_immediate(expr),
+            });
+            vreg
+        }
+        ExprKind::ExprFloatLit(lit) => {
+            let src = ctx.float_constant(parse_float_literal(lit).unwrap());
+            let vreg = ctx.new_vreg_of(RegClass::Float);
+            ctx.push_move(vreg.into(), src, vreg.1);
+            vreg
+        }
+        ExprKind::ExprVariable(_) => {
+            let src = ctx.variable_operand(expr.span);
+            let vreg = ctx.new_vreg_of(ctx.expr_class(expr));
+            ctx.push_move(vreg.into(), src, vreg.1);
+            vreg
+        }
+        ExprKind::ExprCharLit(c) => {
+            let vreg = ctx.new_vreg();
+            ctx.push(Instruction::MOVE {
+                dest: vreg.into(),
+                src: Operand::Imm((*c as u32).to_string()),
+            });
+            vreg
+        }
+        // 文字列の値は、長さの後ろに置いた中身の先頭を指すポインタ
+        ExprKind::ExprStrLit(_) | ExprKind::ExprByteStrLit(_) => {
+            let label = ctx.strings.intern(literal_bytes(expr).unwrap());
+            let vreg = ctx.new_vreg();
+            ctx.push(Instruction::LOAD {
+                dest: vreg.into(),
+                addr: label,
+            });
+            vreg
+        }
+        ExprKind::ExprFnCall(fn_call) => {
+            handle_fn_call(ctx, fn_call);
+            let vreg = ctx.new_vreg_of(ctx.expr_class(expr));
+            let src = match vreg.1 {
+                RegClass::Int => Rst::RAX,
+                RegClass::Float => Rst::XMM0,
+            };
+            ctx.push_move(vreg.into(), src.into(), vreg.1);
+            vreg
+        }
+        ExprKind::ExprBinaryOp { left, op, right } => {
+            let left_vreg = handle_experession(ctx, left);
+            let right_vreg = handle_experession(ctx, right);
+            if left_vreg.1 == RegClass::Float {
+                handle_float_binary_op(ctx, expr, op, left_vreg, right_vreg);
+                return left_vreg;
+            }
+            let ty = ctx.expr_ty(left);
+            if matches!(op, Operator::Slash | Operator::Percent) {
+                handle_division(ctx, expr, left_vreg, right_vreg);
+                return left_vreg;
             }
-            Expr::ExprVariable(var_name) => {
-                let rst = rst_manager
-                    .val_register_map
-                    .get(var_name)
-                    .unwrap()
-                    .to_string();
-                instructions.push(Instruction::MOVE {
-                    dest: rst_manager.pop_argument_rsts().to_string(),
-                    src: rst,
+            let overflow =
+                overflow_action(op).and_then(|action| ctx.overflow_panic(expr.span, action));
+            handle_int_binary_op(ctx, op, ty, left_vreg, right_vreg, overflow.as_deref());
+            left_vreg
+        }
+        ExprKind::ExprUnary {
+            op: Operator::Minus,
+            operand,
+        } => {
+            // `-1.5` はそのまま負の定数にする
+            if let ExprKind::ExprFloatLit(lit) = &operand.kind {
+                let src = ctx.float_constant(-parse_float_literal(lit).unwrap());
+                let vreg = ctx.new_vreg_of(RegClass::Float);
+                ctx.push_move(vreg.into(), src, vreg.1);
+                return vreg;
+            }
+            // `-2147483648` のような負のリテラルも、型検査と同じく1つの定数にする
+            if let ExprKind::ExprLit(lit) = &operand.kind {
+                let value = -parse_int_literal(lit).expect("type checking validates literals");
+                let vreg = ctx.new_vreg();
+                ctx.push(Instruction::MOVE {
+                    dest: vreg.into(),
+                    src: Operand::Imm((value as i64).to_string()),
                 });
+                return vreg;
             }
-            _ => {
-                // Handle other argument expression types if necessary
-                unimplemented!()
+            let ty = ctx.expr_ty(operand);
+            let vreg = handle_experession(ctx, operand);
+            match vreg.1 {
+                RegClass::Int => {
+                    let overflow = ctx.overflow_panic(expr.span, "negate");
+                    handle_neg(ctx, vreg, ty, overflow.as_deref());
+                }
+                // -1 を掛けると、0.0 の符号も含めて符号だけが反転する
+                RegClass::Float => {
+                    let minus_one = ctx.float_constant(-1.0);
+                    ctx.push(Instruction::MULSD {
+                        dest: vreg.into(),
+                        src: minus_one,
+                    });
+                }
             }
+            vreg
+        }
+        ExprKind::ExprUnary { operand, .. } => {
+            // 真偽値の `!` は条件分岐で扱うので、ここに来るのは整数のビット反転
+            let ty = ctx.expr_ty(operand);
+            let vreg = handle_experession(ctx, operand);
+            ctx.push(Instruction::NOT {
+                dest: vreg.into(),
+                width: op_width(ty),
+            });
+            ctx.normalize(vreg, ty);
+            vreg
+        }
+        ExprKind::ExprCast { expr: operand, ty } => {
+            let from = ctx.expr_ty(operand);
+            let vreg = handle_experession(ctx, operand);
+            handle_cast(ctx, vreg, from, *ty)
+        }
+        ExprKind::ExprMethodCall(call) => handle_method_call(ctx, call),
+    }
+}
+
+/// 文字列リテラルかバイト文字列リテラルの中身
+fn literal_bytes(expr: &Expr) -> Option<&[u8]> {
+    match &expr.kind {
+        ExprKind::ExprStrLit(s) => Some(s.as_bytes()),
+        ExprKind::ExprByteStrLit(bytes) => Some(bytes),
+        _ => None,
+    }
+}
+
+/// 整数か真偽値のリテラルの即値。接尾辞や `0x` は取り除き、10 進数で書く
+fn int_immediate(expr: &Expr) -> Operand {
+    let value = match &expr.kind {
+        ExprKind::ExprLit(lit) => parse_int_literal(lit).unwrap() as i64,
+        ExprKind::ExprBoolLit(value) => *value as i64,
+        _ => unreachable!("not an integer literal"),
+    };
+    Operand::Imm(value.to_string())
+}
+
+/// 桁あふれを確かめる演算の、パニックのメッセージに書く動作
+fn overflow_action(op: &Operator) -> Option<&'static str> {
+    match op {
+        Operator::Plus => Some("add"),
+        Operator::Minus => Some("subtract"),
+        Operator::Asterisk => Some("multiply"),
+        Operator::Shl => Some("shift left"),
+        Operator::Shr => Some("shift right"),
+        _ => None,
+    }
+}
+
+/// 整数の二項演算 (割り算以外) の結果を `left` に入れる。
+/// 桁あふれしたら `overflow` へ飛び、`None` なら型の大きさで切り詰める
+fn handle_int_binary_op(
+    ctx: &mut FnContext,
+    op: &Operator,
+    ty: Ty,
+    left: VReg,
+    right: VReg,
+    overflow: Option<&str>,
+) {
+    let (dest, src, width) = (left.into(), right.into(), op_width(ty));
+    match op {
+        Operator::Plus => ctx.push(Instruction::ADD { dest, src, width }),
+        Operator::Minus => ctx.push(Instruction::SUB { dest, src, width }),
+        Operator::Asterisk => {
+            handle_multiply(ctx, ty, left, right, overflow);
+            ctx.normalize(left, ty);
+            return;
+        }
+        Operator::Ampersand => ctx.push(Instruction::AND { dest, src, width }),
+        Operator::Pipe => ctx.push(Instruction::OR { dest, src, width }),
+        Operator::Caret => ctx.push(Instruction::XOR { dest, src, width }),
+        Operator::Shl | Operator::Shr => handle_shift(ctx, op, ty, left, right, overflow),
+        _ => unreachable!("`{}` is not an arithmetic operator", op),
+    }
+    if let (Operator::Plus | Operator::Minus, Some(label)) = (op, overflow) {
+        jump_if_overflow(ctx, left, ty, width, label);
+    }
+    ctx.normalize(left, ty);
+}
+
+/// 直前に `width` で計算した `vreg` が `ty` の範囲を超えていたら `label` へ飛ぶ。
+/// `ty` より広く計算した時は、型の大きさから拡張し直しても値が変わらないかで確かめる
+fn jump_if_overflow(ctx: &mut FnContext, vreg: VReg, ty: Ty, width: Width, label: &str) {
+    if exact_width(ty) == width {
+        ctx.push(Instruction::JCC {
+            cond: if ty.is_signed() { Cond::O } else { Cond::B },
+            label: label.to_string(),
+        });
+        return;
+    }
+    let truncated = ctx.new_vreg();
+    ctx.push(Instruction::MOVE {
+        dest: truncated.into(),
+        src: vreg.into(),
+    });
+    ctx.normalize(truncated, ty);
+    ctx.push(Instruction::CMP {
+        src1: truncated.into(),
+        src2: vreg.into(),
+        width,
+    });
+    ctx.push(Instruction::JCC {
+        cond: Cond::NE,
+        label: label.to_string(),
+    });
+}
+
+/// `left` に `right` を掛ける。`imul` のフラグは符号付きの桁あふれなので、
+/// `u32` は 64 ビットで掛けてから範囲を確かめ、`u64` は `mul` で上位の桁を求める
+fn handle_multiply(ctx: &mut FnContext, ty: Ty, left: VReg, right: VReg, overflow: Option<&str>) {
+    let (dest, src) = (left.into(), right.into());
+    let Some(label) = overflow else {
+        let width = op_width(ty);
+        ctx.push(Instruction::IMUL { dest, src, width });
+        return;
+    };
+    match (ty.is_signed(), ty.bits()) {
+        (false, 64) => {
+            ctx.push(Instruction::MOVE {
+                dest: Rst::RAX.into(),
+                src: dest.clone(),
+            });
+            ctx.push(Instruction::MUL {
+                src,
+                width: Width::B64,
+            });
+            ctx.push(Instruction::JCC {
+                cond: Cond::B,
+                label: label.to_string(),
+            });
+            ctx.push(Instruction::MOVE {
+                dest,
+                src: Rst::RAX.into(),
+            });
+        }
+        (false, 32) => {
+            let width = Width::B64;
+            ctx.push(Instruction::IMUL { dest, src, width });
+            jump_if_overflow(ctx, left, ty, width, label);
+        }
+        _ => {
+            let width = op_width(ty);
+            ctx.push(Instruction::IMUL { dest, src, width });
+            jump_if_overflow(ctx, left, ty, width, label);
+        }
+    }
+}
+
+/// `vreg` の符号を反転する。符号無し整数は `wrapping_neg` と `checked_neg` の場合で、0 以外は桁あふれする
+fn handle_neg(ctx: &mut FnContext, vreg: VReg, ty: Ty, overflow: Option<&str>) {
+    let width = op_width(ty);
+    ctx.push(Instruction::NEG {
+        dest: vreg.into(),
+        width,
+    });
+    if let Some(label) = overflow {
+        jump_if_overflow(ctx, vreg, ty, width, label);
+    }
+    ctx.normalize(vreg, ty);
+}
+
+/// `left` を `right` だけシフトする。シフト量が型のビット数以上なら `overflow` へ飛び、
+/// `None` なら `wrapping_shl` と同じくビット数で割った余りだけシフトする
+fn handle_shift(
+    ctx: &mut FnContext,
+    op: &Operator,
+    ty: Ty,
+    left: VReg,
+    right: VReg,
+    overflow: Option<&str>,
+) {
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RCX.into(),
+        src: right.into(),
+    });
+    match overflow {
+        // 負のシフト量も符号無しで比べると大きな値になる
+        Some(label) => {
+            ctx.push(Instruction::CMP {
+                src1: Rst::RCX.into(),
+                src2: Operand::Imm(ty.bits().to_string()),
+                width: Width::B64,
+            });
+            ctx.push(Instruction::JCC {
+                cond: Cond::AE,
+                label: label.to_string(),
+            });
+        }
+        None => ctx.push(Instruction::AND {
+            dest: Rst::RCX.into(),
+            src: Operand::Imm((ty.bits() - 1).to_string()),
+            width: Width::B32,
+        }),
+    }
+    let (dest, src, width) = (left.into(), Rst::RCX.into(), op_width(ty));
+    // 上位ビットは拡張してあるので、右シフトは 32 ビットで計算しても小さい型の結果になる
+    ctx.push(match op {
+        Operator::Shl => Instruction::SHL { dest, src, width },
+        _ if ty.is_signed() => Instruction::SAR { dest, src, width },
+        _ => Instruction::SHR { dest, src, width },
+    });
+}
+
+/// `as` による型変換の結果を入れた仮想レジスタを返す。
+/// 整数同士は変換先の大きさで切り捨てて拡張し、浮動小数点数から整数へは範囲に収まるよう飽和させる
+fn handle_cast(ctx: &mut FnContext, vreg: VReg, from: Ty, to: Ty) -> VReg {
+    match (from, to) {
+        (Ty::F64, Ty::F64) => vreg,
+        (Ty::F64, _) => handle_float_to_int(ctx, vreg, to),
+        (_, Ty::F64) => handle_int_to_float(ctx, vreg, from),
+        _ => {
+            ctx.normalize(vreg, to);
+            vreg
+        }
+    }
+}
+
+fn handle_int_to_float(ctx: &mut FnContext, vreg: VReg, from: Ty) -> VReg {
+    let dest = ctx.new_vreg_of(RegClass::Float);
+    if from != Ty::U64 && from != Ty::Usize {
+        // 64 ビットに拡張してあるので、符号付きとして変換すればよい
+        ctx.push(Instruction::CVTSI2SD {
+            dest: dest.into(),
+            src: vreg.into(),
+        });
+        return dest;
+    }
+    // 2^63 以上の値は半分にしてから変換して 2 倍する。捨てる最下位ビットは丸めのために残す
+    let large = ctx.new_label("cast_large");
+    let end = ctx.new_label("cast_end");
+    ctx.push(Instruction::CMP {
+        src1: vreg.into(),
+        src2: Operand::Imm("0".to_string()),
+        width: Width::B64,
+    });
+    ctx.push(Instruction::JCC {
+        cond: Cond::L,
+        label: large.clone(),
+    });
+    ctx.push(Instruction::CVTSI2SD {
+        dest: dest.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::JMP { label: end.clone() });
+    ctx.push(Instruction::LABEL { name: large });
+    let low_bit = ctx.new_vreg();
+    ctx.push(Instruction::MOVE {
+        dest: low_bit.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::AND {
+        dest: low_bit.into(),
+        src: Operand::Imm("1".to_string()),
+        width: Width::B64,
+    });
+    ctx.push(Instruction::SHR {
+        dest: vreg.into(),
+        src: Operand::Imm("1".to_string()),
+        width: Width::B64,
+    });
+    ctx.push(Instruction::OR {
+        dest: vreg.into(),
+        src: low_bit.into(),
+        width: Width::B64,
+    });
+    ctx.push(Instruction::CVTSI2SD {
+        dest: dest.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::ADDSD {
+        dest: dest.into(),
+        src: dest.into(),
+    });
+    ctx.push(Instruction::LABEL { name: end });
+    dest
+}
+
+/// 浮動小数点数を整数にする。NaN は 0、範囲外の値は型の最小値か最大値になる
+fn handle_float_to_int(ctx: &mut FnContext, vreg: VReg, to: Ty) -> VReg {
+    let (min, max) = to.int_range();
+    let dest = ctx.new_vreg();
+    let end = ctx.new_label("cast_end");
+    // `mov` はフラグを変えないので、結果を先に入れてから比較の結果で抜ける
+    let bounds = [
+        (Operand::Imm("0".to_string()), Cond::P, None),
+        (Operand::Imm(min.to_string()), Cond::BE, Some(min as f64)),
+        (
+            Operand::Imm((max as i64).to_string()),
+            Cond::AE,
+            Some((max + 1) as f64),
+        ),
+    ];
+    for (value, cond, bound) in bounds {
+        let bound = match bound {
+            Some(bound) => ctx.float_constant(bound),
+            None => vreg.into(),
+        };
+        ctx.push(Instruction::MOVE {
+            dest: dest.into(),
+            src: value,
+        });
+        ctx.push(Instruction::UCOMISD {
+            src1: vreg.into(),
+            src2: bound,
+        });
+        ctx.push(Instruction::JCC {
+            cond,
+            label: end.clone(),
+        });
+    }
+    if max > i64::MAX as i128 {
+        // 2^63 以上の値は 2^63 を引いて変換し、最上位ビットを立てる
+        let small = ctx.new_label("cast_small");
+        let half = ctx.float_constant(2f64.powi(63));
+        ctx.push(Instruction::UCOMISD {
+            src1: vreg.into(),
+            src2: half.clone(),
+        });
+        ctx.push(Instruction::JCC {
+            cond: Cond::B,
+            label: small.clone(),
+        });
+        let shifted = ctx.new_vreg_of(RegClass::Float);
+        ctx.push(Instruction::MOVSD {
+            dest: shifted.into(),
+            src: vreg.into(),
+        });
+        ctx.push(Instruction::SUBSD {
+            dest: shifted.into(),
+            src: half,
+        });
+        ctx.push(Instruction::CVTTSD2SI {
+            dest: dest.into(),
+            src: shifted.into(),
+        });
+        let top_bit = ctx.new_vreg();
+        ctx.push(Instruction::MOVE {
+            dest: top_bit.into(),
+            src: Operand::Imm(i64::MIN.to_string()),
+        });
+        ctx.push(Instruction::XOR {
+            dest: dest.into(),
+            src: top_bit.into(),
+            width: Width::B64,
+        });
+        ctx.push(Instruction::JMP { label: end.clone() });
+        ctx.push(Instruction::LABEL { name: small });
+    }
+    ctx.push(Instruction::CVTTSD2SI {
+        dest: dest.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::LABEL { name: end });
+    dest
+}
+
+/// 浮動小数点数の四則演算の結果を `left` に入れる。0 で割っても無限大か NaN になるだけでパニックしない
+fn handle_float_binary_op(
+    ctx: &mut FnContext,
+    expr: &Expr,
+    op: &Operator,
+    left: VReg,
+    right: VReg,
+) {
+    let (dest, src) = (left.into(), right.into());
+    let instruction = match op {
+        Operator::Plus => Instruction::ADDSD { dest, src },
+        Operator::Minus => Instruction::SUBSD { dest, src },
+        Operator::Asterisk => Instruction::MULSD { dest, src },
+        Operator::Slash => Instruction::DIVSD { dest, src },
+        _ => {
+            ctx.diagnostics.push(
+                Diagnostic::error(
+                    format!("binary operation `{}` on `f64` is not supported yet", op),
+                    expr.span,
+                )
+                .with_label(format!("`{}` cannot be compiled for `f64`", op)),
+            );
+            return;
+        }
+    };
+    ctx.push(instruction);
+}
+
+/// `left` を `right` で割った商か余りを `left` に入れる。
+/// 割る数が 0 の時は、Rustと同じく定数ならコンパイルエラー、そうでなければ実行時にパニックする。
+/// 符号付き整数の `MIN / -1` は `-C overflow-checks` に関係なくパニックする
+fn handle_division(ctx: &mut FnContext, expr: &Expr, left: VReg, right: VReg) {
+    let ExprKind::ExprBinaryOp {
+        left: dividend,
+        op,
+        right: divisor,
+    } = &expr.kind
+    else {
+        unreachable!("division must be a binary operation");
+    };
+    let is_div = *op == Operator::Slash;
+    let ty = ctx.expr_ty(dividend);
+    let constant_divisor = ctx.constant_value(divisor);
+    match constant_divisor {
+        Some(0) => {
+            let dividend = match ctx.constant_value(dividend) {
+                Some(value) => format!("{}_{}", value, ty),
+                None => "_".to_string(),
+            };
+            let label = if is_div {
+                format!("attempt to divide `{}` by zero", dividend)
+            } else {
+                format!(
+                    "attempt to calculate the remainder of `{}` with a divisor of zero",
+                    dividend
+                )
+            };
+            ctx.diagnostics.push(
+                Diagnostic::error("this operation will panic at runtime", expr.span)
+                    .with_label(label)
+                    .with_note("`#[deny(unconditional_panic)]` on by default"),
+            );
+        }
+        // 0 でない定数で割るなら確かめなくてよい
+        Some(_) => (),
+        None => {
+            let stub = ctx.new_panic_stub(expr.span, divide_by_zero_message(is_div));
+            jump_if_zero(ctx, right, op_width(ty), &stub);
+        }
+    }
+    let may_overflow = ty.is_signed() && constant_divisor.is_none_or(|value| value == -1);
+    let overflow = may_overflow.then(|| {
+        let message = if is_div {
+            "attempt to divide with overflow"
+        } else {
+            "attempt to calculate the remainder with overflow"
         };
+        ctx.new_panic_stub(expr.span, message)
+    });
+    emit_division(
+        ctx,
+        is_div,
+        ty,
+        left,
+        right,
+        may_overflow,
+        overflow.as_deref(),
+    );
+}
+
+fn divide_by_zero_message(is_div: bool) -> &'static str {
+    if is_div {
+        "attempt to divide by zero"
+    } else {
+        "attempt to calculate the remainder with a divisor of zero"
     }
-    rst_manager.init_argument_rsts();
-    instructions.push(Instruction::CALL {
-        func: convert_to_asm_fn_name(&fn_call.name),
+}
+
+fn jump_if_zero(ctx: &mut FnContext, vreg: VReg, width: Width, label: &str) {
+    ctx.push(Instruction::CMP {
+        src1: vreg.into(),
+        src2: Operand::Imm("0".to_string()),
+        width,
+    });
+    ctx.push(Instruction::JCC {
+        cond: Cond::E,
+        label: label.to_string(),
     });
 }
 
-fn handle_println(
-    instructions: &mut Vec<Instruction>,
-    fn_call: &crate::ast::program::FnCall,
-    data_directives: &mut Vec<DataDirective>,
+/// 割る数が 0 でないと分かってから割り算をする。`may_overflow` なら `-1` で割る場合を別に扱い、
+/// `MIN / -1` は `overflow` へ飛ぶか、`None` なら `wrapping_div` と同じく `MIN` にする
+fn emit_division(
+    ctx: &mut FnContext,
+    is_div: bool,
+    ty: Ty,
+    left: VReg,
+    right: VReg,
+    may_overflow: bool,
+    overflow: Option<&str>,
 ) {
-    if let Some(arg) = fn_call.args.get(0) {
-        match arg {
-            Expr::ExprLit(lit) => {
-                instructions.push(Instruction::MOVE {
-                    dest: Rst::RAX.to_string(),
-                    src: SYSCALL::WRITE.to_string(),
+    let width = op_width(ty);
+    let mut end = None;
+    if may_overflow {
+        // `idiv` は商が収まらないと例外を起こすので、`-1` で割る時は割らずに求める
+        let divide = ctx.new_label("div");
+        ctx.push(Instruction::CMP {
+            src1: right.into(),
+            src2: Operand::Imm("-1".to_string()),
+            width,
+        });
+        ctx.push(Instruction::JCC {
+            cond: Cond::NE,
+            label: divide.clone(),
+        });
+        match overflow {
+            Some(label) => {
+                let min = ctx.new_vreg();
+                ctx.push(Instruction::MOVE {
+                    dest: min.into(),
+                    src: Operand::Imm(ty.int_range().0.to_string()),
                 });
-                instructions.push(Instruction::MOVE {
-                    dest: Rst::RDI.to_string(),
-                    src: "1".to_string(), // stdout
+                ctx.push(Instruction::CMP {
+                    src1: left.into(),
+                    src2: min.into(),
+                    width,
                 });
-                let msg = "msg";
-                let msg_len = "msg_len";
-                instructions.push(Instruction::LOAD {
-                    dest: Rst::RSI.to_string(),
-                    addr: msg.to_string(),
+                ctx.push(Instruction::JCC {
+                    cond: Cond::E,
+                    label: label.to_string(),
                 });
-                data_directives.push(DataDirective::DB {
-                    left: msg.to_string(),
-                    right: vec![lit.clone(), "0x0A".to_string()],
+            }
+            None => {
+                if is_div {
+                    ctx.push(Instruction::NEG {
+                        dest: left.into(),
+                        width,
+                    });
+                } else {
+                    ctx.push(Instruction::MOVE {
+                        dest: left.into(),
+                        src: Operand::Imm("0".to_string()),
+                    });
+                }
+                let label = ctx.new_label("div_end");
+                ctx.push(Instruction::JMP {
+                    label: label.clone(),
                 });
-                instructions.push(Instruction::MOVE {
-                    dest: Rst::RDX.to_string(),
-                    src: msg_len.to_string(),
+                end = Some(label);
+            }
+        }
+        ctx.push(Instruction::LABEL { name: divide });
+    }
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RAX.into(),
+        src: left.into(),
+    });
+    if ty.is_signed() {
+        ctx.push(if width == Width::B64 {
+            Instruction::CQO
+        } else {
+            Instruction::CDQ
+        });
+        ctx.push(Instruction::IDIV {
+            src: right.into(),
+            width,
+        });
+    } else {
+        // 符号無しの割り算では、割られる数の上位を 0 にする
+        ctx.push(Instruction::XOR {
+            dest: Rst::RDX.into(),
+            src: Rst::RDX.into(),
+            width: Width::B32,
+        });
+        ctx.push(Instruction::DIV {
+            src: right.into(),
+            width,
+        });
+    }
+    ctx.push(Instruction::MOVE {
+        dest: left.into(),
+        src: if is_div { Rst::RAX } else { Rst::RDX }.into(),
+    });
+    if let Some(end) = end {
+        ctx.push(Instruction::LABEL { name: end });
+    }
+    ctx.normalize(left, ty);
+}
+
+/// `wrapping_*` の結果か、`Option` のメソッドで取り出した `checked_*` の結果、文字列の `len()` を返す
+fn handle_method_call(ctx: &mut FnContext, call: &MethodCall) -> VReg {
+    if call.name == "len" {
+        return handle_len(ctx, &call.receiver);
+    }
+    if let ExprKind::ExprMethodCall(inner) = &call.receiver.kind
+        && OPTION_METHODS.contains(&call.name.as_str())
+        && let Some(method) = IntMethod::from_name(&inner.name)
+        && method.checked
+    {
+        return handle_checked(ctx, call, inner, &method);
+    }
+    let method =
+        IntMethod::from_name(&call.name).expect("type checking allows only integer methods");
+    let (ty, left, right) = int_method_operands(ctx, call);
+    emit_int_method(ctx, &method, ty, left, right, None, call.span);
+    left
+}
+
+/// 文字列の長さ。リテラルならその場で分かり、それ以外は中身の直前の 8 バイトから読む
+fn handle_len(ctx: &mut FnContext, receiver: &Expr) -> VReg {
+    let vreg = ctx.new_vreg();
+    if let Some(bytes) = literal_bytes(receiver) {
+        ctx.push(Instruction::MOVE {
+            dest: vreg.into(),
+            src: Operand::Imm(bytes.len().to_string()),
+        });
+        return vreg;
+    }
+    let ptr = handle_experession(ctx, receiver);
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RAX.into(),
+        src: ptr.into(),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: vreg.into(),
+        src: Operand::Mem(format!("{} - 8", Rst::RAX)),
+    });
+    vreg
+}
+
+/// `checked_*` の結果を `unwrap` などで取り出す。`None` になる場合は演算の途中で飛び出す
+fn handle_checked(
+    ctx: &mut FnContext,
+    call: &MethodCall,
+    inner: &MethodCall,
+    method: &IntMethod,
+) -> VReg {
+    let (ty, left, right) = int_method_operands(ctx, inner);
+    if call.name == "unwrap" {
+        let stub = ctx.new_panic_stub(call.span, "called `Option::unwrap()` on a `None` value");
+        emit_int_method(ctx, method, ty, left, right, Some(&stub), inner.span);
+        return left;
+    }
+    // `unwrap_or` の引数は結果に関係なく先に評価する
+    let default = match call.args.first() {
+        Some(arg) if call.name == "unwrap_or" => Some(handle_experession(ctx, arg)),
+        _ => None,
+    };
+    let none = ctx.new_label("checked_none");
+    let end = ctx.new_label("checked_end");
+    emit_int_method(ctx, method, ty, left, right, Some(&none), inner.span);
+    let (result, some_value, none_value) = match default {
+        Some(default) => (left, None, default.into()),
+        None => {
+            let is_some = call.name == "is_some";
+            let imm = |value: bool| Operand::Imm((value as i64).to_string());
+            (ctx.new_vreg(), Some(imm(is_some)), imm(!is_some))
+        }
+    };
+    if let Some(src) = some_value {
+        ctx.push(Instruction::MOVE {
+            dest: result.into(),
+            src,
+        });
+    }
+    ctx.push(Instruction::JMP { label: end.clone() });
+    ctx.push(Instruction::LABEL { name: none });
+    ctx.push(Instruction::MOVE {
+        dest: result.into(),
+        src: none_value,
+    });
+    ctx.push(Instruction::LABEL { name: end });
+    result
+}
+
+/// 整数のメソッドを受け取る値と引数を評価し、値の型と一緒に返す
+fn int_method_operands(ctx: &mut FnContext, call: &MethodCall) -> (Ty, VReg, Option<VReg>) {
+    let ty = ctx.expr_ty(&call.receiver);
+    let left = handle_experession(ctx, &call.receiver);
+    let right = call.args.first().map(|arg| handle_experession(ctx, arg));
+    (ty, left, right)
+}
+
+/// 整数のメソッドの演算結果を `left` に入れる。桁あふれした時と 0 で割った時は `overflow` へ飛び、
+/// `None` なら桁あふれは切り詰めて、0 で割った時だけパニックする
+fn emit_int_method(
+    ctx: &mut FnContext,
+    method: &IntMethod,
+    ty: Ty,
+    left: VReg,
+    right: Option<VReg>,
+    overflow: Option<&str>,
+    span: Span,
+) {
+    match (&method.op, right) {
+        (None, _) => handle_neg(ctx, left, ty, overflow),
+        (Some(op @ (Operator::Slash | Operator::Percent)), Some(right)) => {
+            let is_div = *op == Operator::Slash;
+            let zero = match overflow {
+                Some(label) => label.to_string(),
+                None => ctx.new_panic_stub(span, divide_by_zero_message(is_div)),
+            };
+            jump_if_zero(ctx, right, op_width(ty), &zero);
+            emit_division(ctx, is_div, ty, left, right, ty.is_signed(), overflow);
+        }
+        (Some(op), Some(right)) => handle_int_binary_op(ctx, op, ty, left, right, overflow),
+        (Some(_), None) => unreachable!("binary integer methods take an argument"),
+    }
+}
+
+fn handle_fn_call(ctx: &mut FnContext, fn_call: &crate::ast::program::FnCall) {
+    if fn_call.name.ends_with('!') {
+        handle_print_macro(ctx, fn_call);
+        return;
+    }
+    // 引数の中の呼び出しで引数レジスタが壊れないように、全ての引数を評価してからレジスタに入れる
+    let args: Vec<(Operand, RegClass)> = fn_call
+        .args
+        .iter()
+        .map(|arg| {
+            let operand = match &arg.kind {
+                ExprKind::ExprLit(_) | ExprKind::ExprBoolLit(_) => int_immediate(arg),
+                ExprKind::ExprFloatLit(lit) => {
+                    ctx.float_constant(parse_float_literal(lit).unwrap())
+                }
+                ExprKind::ExprVariable(_) => ctx.variable_operand(arg.span),
+                _ => handle_experession(ctx, arg).into(),
+            };
+            (operand, ctx.expr_class(arg))
+        })
+        .collect();
+    let classes: Vec<RegClass> = args.iter().map(|(_, class)| *class).collect();
+    let locations = argument_locations(&classes);
+    let stack_args: Vec<&(Operand, RegClass)> = args
+        .iter()
+        .zip(&locations)
+        .filter(|(_, location)| matches!(location, ArgLocation::Stack(_)))
+        .map(|(arg, _)| arg)
+        .collect();
+    let stack_args_len = stack_args.len();
+    // call の時点で rsp を 16 の倍数に保つ
+    let padding = if stack_args_len % 2 == 1 { 8 } else { 0 };
+    if padding > 0 {
+        ctx.push(Instruction::SUB {
+            dest: Rst::RSP.into(),
+            src: Operand::Imm(padding.to_string()),
+            width: Width::B64,
+        });
+    }
+    for (src, class) in stack_args.into_iter().rev() {
+        match class {
+            RegClass::Int => ctx.push(Instruction::PUSH { src: src.clone() }),
+            // SSE レジスタは push できないので、場所を空けてから書き込む
+            RegClass::Float => {
+                ctx.push(Instruction::SUB {
+                    dest: Rst::RSP.into(),
+                    src: Operand::Imm("8".to_string()),
+                    width: Width::B64,
                 });
-                data_directives.push(DataDirective::EQUE {
-                    left: msg_len.to_string(),
-                    right: vec!["$ - msg".to_string()],
+                ctx.push(Instruction::MOVSD {
+                    dest: Operand::Mem(Rst::RSP.to_string()),
+                    src: src.clone(),
                 });
-                instructions.push(Instruction::SYSCALL);
             }
-            _ => { /* Handle other expression types if necessary */ }
         }
     }
+    for ((src, class), location) in args.into_iter().zip(locations) {
+        if let ArgLocation::Rst(rst) = location {
+            ctx.push_move(rst.into(), src, class);
+        }
+    }
+    ctx.push(Instruction::CALL {
+        func: ctx.target.symbol_name(&fn_call.name),
+    });
+    if stack_args_len > 0 {
+        ctx.push(Instruction::ADD {
+            dest: Rst::RSP.into(),
+            src: Operand::Imm((8 * stack_args_len + padding).to_string()),
+            width: Width::B64,
+        });
+    }
+}
+
+/// `println!` などの出力のマクロ。書式文字列の `{}` の間の文字列はデータセクションに置いて
+/// そのまま書き出し、整数は `PRINT_INT_FN` で10進数に、`char` は `PRINT_CHAR_FN` で UTF-8 にして書き出す
+fn handle_print_macro(ctx: &mut FnContext, fn_call: &crate::ast::program::FnCall) {
+    let pieces = match fn_call.args.first() {
+        Some(Expr {
+            kind: ExprKind::ExprStrLit(format),
+            span,
+        }) => parse_format(format, *span).expect("type checking validates the format string"),
+        _ => Vec::new(),
+    };
+    let args = fn_call.args.get(1..).unwrap_or_default();
+    // 引数の中の呼び出しで出力が途切れないように、全ての引数を先に評価する
+    let values: Vec<Option<VReg>> = args
+        .iter()
+        .map(|arg| match arg.kind {
+            ExprKind::ExprStrLit(_) | ExprKind::ExprCharLit(_) => None,
+            _ => Some(handle_experession(ctx, arg)),
+        })
+        .collect();
+    let fd = if fn_call.name.starts_with('e') {
+        "2" // stderr
+    } else {
+        "1" // stdout
+    };
+    // 続いている文字列は、文字列と文字のリテラルの引数も含めて1回で書き出す
+    let mut text = String::new();
+    for piece in pieces {
+        match piece {
+            FormatPiece::Text(piece) => text.push_str(&piece),
+            FormatPiece::Arg(index) => match (&args[index].kind, values[index]) {
+                (ExprKind::ExprStrLit(lit), _) => text.push_str(lit),
+                (ExprKind::ExprCharLit(c), _) => text.push(*c),
+                (_, Some(vreg)) => {
+                    write_text(ctx, &std::mem::take(&mut text), fd);
+                    write_value(ctx, vreg, ctx.expr_ty(&args[index]), fd);
+                }
+                (_, None) => unreachable!("non-literal arguments are evaluated"),
+            },
+        }
+    }
+    if fn_call.name.ends_with("ln!") {
+        text.push('\n');
+    }
+    write_text(ctx, &text, fd);
+}
+
+/// `text` をファイルディスクリプタ `fd` に書き出す
+fn write_text(ctx: &mut FnContext, text: &str, fd: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let label = ctx.str_constant(text);
+    emit_write(ctx, fd, &label);
+}
+
+/// データセクションの `label` の文字列を `write` システムコールで書き出す
+fn emit_write(ctx: &mut FnContext, fd: &str, label: &str) {
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RAX.into(),
+        src: Operand::Imm(ctx.target.syscall_number(SYSCALL::WRITE).to_string()),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDI.into(),
+        src: Operand::Imm(fd.to_string()),
+    });
+    ctx.push(Instruction::LOAD {
+        dest: Rst::RSI.into(),
+        addr: label.to_string(),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDX.into(),
+        src: Operand::Imm(format!("{}_len", label)),
+    });
+    ctx.push(Instruction::SYSCALL);
+}
+
+/// `ty` の値 `vreg` を `fd` に書き出す。`bool` は `true` か `false` にする
+fn write_value(ctx: &mut FnContext, vreg: VReg, ty: Ty, fd: &str) {
+    if ty == Ty::Str {
+        write_str(ctx, vreg, fd);
+        return;
+    }
+    if ty == Ty::Char {
+        ctx.push(Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: vreg.into(),
+        });
+        ctx.push(Instruction::MOVE {
+            dest: Rst::RSI.into(),
+            src: Operand::Imm(fd.to_string()),
+        });
+        ctx.push(Instruction::CALL {
+            func: PRINT_CHAR_FN.to_string(),
+        });
+        return;
+    }
+    if ty == Ty::Bool {
+        let (true_label, false_label) = (ctx.str_constant("true"), ctx.str_constant("false"));
+        let write_false = ctx.new_label("write_false");
+        let end = ctx.new_label("write_end");
+        ctx.push(Instruction::CMP {
+            src1: vreg.into(),
+            src2: Operand::Imm("0".to_string()),
+            width: Width::B64,
+        });
+        ctx.push(Instruction::JCC {
+            cond: Cond::E,
+            label: write_false.clone(),
+        });
+        emit_write(ctx, fd, &true_label);
+        ctx.push(Instruction::JMP { label: end.clone() });
+        ctx.push(Instruction::LABEL { name: write_false });
+        emit_write(ctx, fd, &false_label);
+        ctx.push(Instruction::LABEL { name: end });
+        return;
+    }
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDI.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RSI.into(),
+        src: Operand::Imm(fd.to_string()),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDX.into(),
+        src: Operand::Imm((ty.is_signed() as i64).to_string()),
+    });
+    ctx.push(Instruction::CALL {
+        func: PRINT_INT_FN.to_string(),
+    });
 }
 
-fn handle_exit(instructions: &mut Vec<Instruction>) {
-    instructions.push(Instruction::MOVE {
-        dest: Rst::RAX.to_string(),
-        src: SYSCALL::EXIT.to_string(),
+/// 文字列の値 `vreg` を `fd` に書き出す。長さは中身の直前の 8 バイトから読む
+fn write_str(ctx: &mut FnContext, vreg: VReg, fd: &str) {
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RSI.into(),
+        src: vreg.into(),
+    });
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDX.into(),
+        src: Operand::Mem(format!("{} - 8", Rst::RSI)),
     });
-    instructions.push(Instruction::XOR {
-        src1: Rst::RDI.to_string(),
-        src2: Rst::RDI.to_string(),
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RAX.into(),
+        src: Operand::Imm(ctx.target.syscall_number(SYSCALL::WRITE).to_string()),
     });
-    instructions.push(Instruction::SYSCALL);
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RDI.into(),
+        src: Operand::Imm(fd.to_string()),
+    });
+    ctx.push(Instruction::SYSCALL);
+}
+
+/// `PRINT_INT_FN` の本体。スタック上のバッファに下の桁から書いていき、まとめて書き出す
+fn print_int_fn(target: &dyn Target) -> FnCode {
+    let imm = |value: &str| Operand::Imm(value.to_string());
+    // 負の数は絶対値を符号無しで割る。`i64::MIN` も `neg` すると符号無しの絶対値になる
+    let instructions = vec![
+        Instruction::PUSH {
+            src: Rst::RBP.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RBP.into(),
+            src: Rst::RSP.into(),
+        },
+        // 符号と 20 桁が入る大きさ
+        Instruction::SUB {
+            dest: Rst::RSP.into(),
+            src: imm("32"),
+            width: Width::B64,
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: Rst::RDI.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::R8.into(),
+            src: Rst::RSI.into(),
+        },
+        // 次の文字を書く位置の1つ後ろ
+        Instruction::MOVE {
+            dest: Rst::RSI.into(),
+            src: Rst::RBP.into(),
+        },
+        // 負の数だったか
+        Instruction::MOVE {
+            dest: Rst::R9.into(),
+            src: imm("0"),
+        },
+        Instruction::CMP {
+            src1: Rst::RDX.into(),
+            src2: imm("0"),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::E,
+            label: ".digits".to_string(),
+        },
+        Instruction::CMP {
+            src1: Rst::RAX.into(),
+            src2: imm("0"),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::GE,
+            label: ".digits".to_string(),
+        },
+        Instruction::NEG {
+            dest: Rst::RAX.into(),
+            width: Width::B64,
+        },
+        Instruction::MOVE {
+            dest: Rst::R9.into(),
+            src: imm("1"),
+        },
+        Instruction::LABEL {
+            name: ".digits".to_string(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RCX.into(),
+            src: imm("10"),
+        },
+        Instruction::LABEL {
+            name: ".next_digit".to_string(),
+        },
+        Instruction::XOR {
+            dest: Rst::RDX.into(),
+            src: Rst::RDX.into(),
+            width: Width::B32,
+        },
+        Instruction::DIV {
+            src: Rst::RCX.into(),
+            width: Width::B64,
+        },
+        Instruction::ADD {
+            dest: Rst::RDX.into(),
+            src: imm("'0'"),
+            width: Width::B32,
+        },
+        Instruction::SUB {
+            dest: Rst::RSI.into(),
+            src: imm("1"),
+            width: Width::B64,
+        },
+        Instruction::STORE {
+            dest: Operand::Mem(Rst::RSI.to_string()),
+            src: Rst::RDX.into(),
+            width: Width::B8,
+        },
+        Instruction::CMP {
+            src1: Rst::RAX.into(),
+            src2: imm("0"),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::NE,
+            label: ".next_digit".to_string(),
+        },
+        Instruction::CMP {
+            src1: Rst::R9.into(),
+            src2: imm("0"),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::E,
+            label: ".write".to_string(),
+        },
+        Instruction::SUB {
+            dest: Rst::RSI.into(),
+            src: imm("1"),
+            width: Width::B64,
+        },
+        Instruction::STORE {
+            dest: Operand::Mem(Rst::RSI.to_string()),
+            src: imm("'-'"),
+            width: Width::B8,
+        },
+        Instruction::LABEL {
+            name: ".write".to_string(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RDX.into(),
+            src: Rst::RBP.into(),
+        },
+        Instruction::SUB {
+            dest: Rst::RDX.into(),
+            src: Rst::RSI.into(),
+            width: Width::B64,
+        },
+        Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: Rst::R8.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: imm(target.syscall_number(SYSCALL::WRITE)),
+        },
+        Instruction::SYSCALL,
+        Instruction::MOVE {
+            dest: Rst::RSP.into(),
+            src: Rst::RBP.into(),
+        },
+        Instruction::POP {
+            dest: Rst::RBP.into(),
+        },
+        Instruction::RET,
+    ];
+    FnCode {
+        label: PRINT_INT_FN.to_string(),
+        instructions,
+    }
+}
+
+/// `PRINT_CHAR_FN` の本体。下位 6 ビットずつ継続バイトにしてスタック上のバッファに後ろから書き、
+/// 残りが先頭バイトに収まったら、バイト数を表す上位ビットを付けて書く
+fn print_char_fn(target: &dyn Target) -> FnCode {
+    let imm = |value: &str| Operand::Imm(value.to_string());
+    let instructions = vec![
+        Instruction::PUSH {
+            src: Rst::RBP.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RBP.into(),
+            src: Rst::RSP.into(),
+        },
+        Instruction::SUB {
+            dest: Rst::RSP.into(),
+            src: imm("16"),
+            width: Width::B64,
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: Rst::RDI.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::R8.into(),
+            src: Rst::RSI.into(),
+        },
+        // 次のバイトを書く位置の1つ後ろ
+        Instruction::MOVE {
+            dest: Rst::RSI.into(),
+            src: Rst::RBP.into(),
+        },
+        Instruction::CMP {
+            src1: Rst::RAX.into(),
+            src2: imm("0x80"),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::B,
+            label: ".lead".to_string(),
+        },
+        // 先頭バイトの上位ビットと、先頭バイトに入る値の上限。継続バイトが増えるごとに変わる
+        Instruction::MOVE {
+            dest: Rst::R9.into(),
+            src: imm("0xC0"),
+        },
+        Instruction::MOVE {
+            dest: Rst::RCX.into(),
+            src: imm("0x20"),
+        },
+        Instruction::LABEL {
+            name: ".continuation".to_string(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RDX.into(),
+            src: Rst::RAX.into(),
+        },
+        Instruction::AND {
+            dest: Rst::RDX.into(),
+            src: imm("0x3F"),
+            width: Width::B32,
+        },
+        Instruction::OR {
+            dest: Rst::RDX.into(),
+            src: imm("0x80"),
+            width: Width::B32,
+        },
+        Instruction::SUB {
+            dest: Rst::RSI.into(),
+            src: imm("1"),
+            width: Width::B64,
+        },
+        Instruction::STORE {
+            dest: Operand::Mem(Rst::RSI.to_string()),
+            src: Rst::RDX.into(),
+            width: Width::B8,
+        },
+        Instruction::SHR {
+            dest: Rst::RAX.into(),
+            src: imm("6"),
+            width: Width::B32,
+        },
+        Instruction::CMP {
+            src1: Rst::RAX.into(),
+            src2: Rst::RCX.into(),
+            width: Width::B64,
+        },
+        Instruction::JCC {
+            cond: Cond::B,
+            label: ".prefix".to_string(),
+        },
+        // 0xC0, 0xE0, 0xF0 の順に上位ビットが1つずつ増える
+        Instruction::SHR {
+            dest: Rst::R9.into(),
+            src: imm("1"),
+            width: Width::B32,
+        },
+        Instruction::OR {
+            dest: Rst::R9.into(),
+            src: imm("0x80"),
+            width: Width::B32,
+        },
+        Instruction::SHR {
+            dest: Rst::RCX.into(),
+            src: imm("1"),
+            width: Width::B32,
+        },
+        Instruction::JMP {
+            label: ".continuation".to_string(),
+        },
+        Instruction::LABEL {
+            name: ".prefix".to_string(),
+        },
+        Instruction::OR {
+            dest: Rst::RAX.into(),
+            src: Rst::R9.into(),
+            width: Width::B32,
+        },
+        Instruction::LABEL {
+            name: ".lead".to_string(),
+        },
+        Instruction::SUB {
+            dest: Rst::RSI.into(),
+            src: imm("1"),
+            width: Width::B64,
+        },
+        Instruction::STORE {
+            dest: Operand::Mem(Rst::RSI.to_string()),
+            src: Rst::RAX.into(),
+            width: Width::B8,
+        },
+        Instruction::MOVE {
+            dest: Rst::RDX.into(),
+            src: Rst::RBP.into(),
+        },
+        Instruction::SUB {
+            dest: Rst::RDX.into(),
+            src: Rst::RSI.into(),
+            width: Width::B64,
+        },
+        Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: Rst::R8.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: imm(target.syscall_number(SYSCALL::WRITE)),
+        },
+        Instruction::SYSCALL,
+        Instruction::MOVE {
+            dest: Rst::RSP.into(),
+            src: Rst::RBP.into(),
+        },
+        Instruction::POP {
+            dest: Rst::RBP.into(),
+        },
+        Instruction::RET,
+    ];
+    FnCode {
+        label: PRINT_CHAR_FN.to_string(),
+        instructions,
+    }
+}
+
+/// `PANIC_FN` の本体。メッセージを標準エラー出力に書き、終了コード 101 で終わる
+fn panic_fn(target: &dyn Target) -> FnCode {
+    let instructions = vec![
+        Instruction::MOVE {
+            dest: Rst::RDX.into(),
+            src: Rst::RSI.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RSI.into(),
+            src: Rst::RDI.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: Operand::Imm("2".to_string()), // stderr
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: Operand::Imm(target.syscall_number(SYSCALL::WRITE).to_string()),
+        },
+        Instruction::SYSCALL,
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: Operand::Imm(target.syscall_number(SYSCALL::EXIT).to_string()),
+        },
+        Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: Operand::Imm(PANIC_EXIT_CODE.to_string()),
+        },
+        Instruction::SYSCALL,
+    ];
+    FnCode {
+        label: PANIC_FN.to_string(),
+        instructions,
+    }
+}
+
+/// `START_FN` の本体。`main` の戻り値を終了コードにして終わる。
+/// 開始時のスタックは 16 バイト境界に揃っているので、`call` で普通の関数と同じ状態になる
+fn start_fn(target: &dyn Target) -> FnCode {
+    let instructions = vec![
+        Instruction::CALL {
+            func: target.symbol_name("main"),
+        },
+        Instruction::MOVE {
+            dest: Rst::RDI.into(),
+            src: Rst::RAX.into(),
+        },
+        Instruction::MOVE {
+            dest: Rst::RAX.into(),
+            src: Operand::Imm(target.syscall_number(SYSCALL::EXIT).to_string()),
+        },
+        Instruction::SYSCALL,
+    ];
+    FnCode {
+        label: START_FN.to_string(),
+        instructions,
+    }
+}
+
+fn handle_exit(ctx: &mut FnContext) {
+    ctx.push(Instruction::MOVE {
+        dest: Rst::RAX.into(),
+        src: Operand::Imm(ctx.target.syscall_number(SYSCALL::EXIT).to_string()),
+    });
+    ctx.push(Instruction::XOR {
+        dest: Rst::RDI.into(),
+        src: Rst::RDI.into(),
+        width: Width::B64,
+    });
+    ctx.push(Instruction::SYSCALL);
 }
 
 #[derive(Debug)]
@@ -266,17 +2132,112 @@ pub struct AsmCode {
     directives: Vec<String>,
     text_sec: Vec<FnCode>,
     data_sec: Vec<DataDirective>,
+    rodata_sec: Vec<DataDirective>,
+    /// 関数ごとの、仮想レジスタのままの命令列
+    ir: Vec<FnCode>,
 }
 
 impl AsmCode {
-    fn new() -> Self {
+    fn new(target: &dyn Target) -> Self {
         AsmCode {
-            directives: vec!["global _main".to_string(), "default rel".to_string()],
+            directives: target.directives(),
             text_sec: Vec::<FnCode>::new(),
             data_sec: Vec::<DataDirective>::new(),
+            rodata_sec: Vec::<DataDirective>::new(),
+            ir: Vec::<FnCode>::new(),
         }
     }
 
+    /// いずれかの関数が `func` を呼んでいるか
+    fn calls(&self, func: &str) -> bool {
+        self.text_sec.iter().any(|fn_code| {
+            fn_code
+                .instructions
+                .iter()
+                .any(|instr| matches!(instr, Instruction::CALL { func: f } if f == func))
+        })
+    }
+
+    /// レジスタ割り当て前の命令列。仮想レジスタは `%v0` や `%f1` と書く
+    pub fn serialize_ir(&self) -> Vec<String> {
+        self.ir.iter().flat_map(FnCode::serialize).collect()
+    }
+
+    /// 外部のアセンブラを使わずに、全てのセクションを機械語やバイト列にする
+    pub fn assemble(&self) -> Object {
+        self.assemble_with(&[])
+    }
+
+    /// 組み込みのリンカで実行ファイルにする中身。オブジェクトファイルは C のランタイムとも
+    /// リンクできるよう `START_FN` を持たないので、ここで加える
+    pub fn assemble_executable(&self, target: &dyn Target) -> Object {
+        if self
+            .text_sec
+            .iter()
+            .any(|fn_code| fn_code.label == START_FN)
+        {
+            return self.assemble();
+        }
+        self.assemble_with(&[start_fn(target)])
+    }
+
+    /// `extra_fns` は `text_sec` の後に置く
+    fn assemble_with(&self, extra_fns: &[FnCode]) -> Object {
+        Object {
+            text: self.encode_text(extra_fns),
+            data: encode_data(&self.data_sec),
+            rodata: encode_data(&self.rodata_sec),
+            globals: self
+                .directives
+                .iter()
+                .filter_map(|directive| directive.strip_prefix("global "))
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// 全ての関数を機械語にする。データのラベルへの参照は再配置として残る
+    fn encode_text(&self, extra_fns: &[FnCode]) -> MachineCode {
+        let constants = self.constants();
+        let mut encoder = Encoder::new(&constants);
+        for fn_code in self.text_sec.iter().chain(extra_fns) {
+            encoder.begin_fn(&fn_code.label);
+            for instr in &fn_code.instructions {
+                encoder.encode(instr);
+            }
+        }
+        encoder.finish()
+    }
+
+    /// `equ` で定義した定数の値。`$ - label` の形だけを使う
+    fn constants(&self) -> std::collections::HashMap<String, i64> {
+        let mut constants = std::collections::HashMap::new();
+        for section in [&self.data_sec, &self.rodata_sec] {
+            let mut labels = std::collections::HashMap::new();
+            let mut position = 0;
+            for data_dir in section.iter() {
+                match data_dir {
+                    DataDirective::DB { left, right } => {
+                        labels.insert(left.as_str(), position);
+                        position += right.len() as i64;
+                    }
+                    DataDirective::DQ { left, .. } => {
+                        if let Some(left) = left {
+                            labels.insert(left.as_str(), position);
+                        }
+                        position += 8;
+                    }
+                    DataDirective::EQUE { left, right } => {
+                        let start = right.join(" ");
+                        let start = start.strip_prefix("$ - ").unwrap_or(&start);
+                        constants.insert(left.clone(), position - labels[start]);
+                    }
+                }
+            }
+        }
+        constants
+    }
+
     pub fn serialize(&self) -> Vec<String> {
         let mut asm_lines = Vec::<String>::new();
         asm_lines.extend(self.directives.clone());
@@ -284,37 +2245,171 @@ impl AsmCode {
         for data_dir in &self.data_sec {
             asm_lines.extend(data_dir.serialize());
         }
+        if !self.rodata_sec.is_empty() {
+            asm_lines.push("section .rodata".to_string());
+            for data_dir in &self.rodata_sec {
+                asm_lines.extend(data_dir.serialize());
+            }
+        }
         asm_lines.push("section .text".to_string());
         for fn_code in &self.text_sec {
             asm_lines.extend(fn_code.serialize());
         }
         asm_lines
     }
+
+    /// GNU as の Intel 記法のアセンブリ。nasm 用のテキストを読み直さず、命令とデータから直接書く
+    pub fn serialize_gas(&self, target: &dyn Target) -> Vec<String> {
+        let mut lines = vec![".intel_syntax noprefix".to_string()];
+        lines.extend(self.directives.iter().filter_map(|d| gas::directive(d)));
+        lines.push(".data".to_string());
+        lines.extend(serialize_gas_data(&self.data_sec));
+        if !self.rodata_sec.is_empty() {
+            lines.push(gas::rodata_section(target).to_string());
+            lines.extend(serialize_gas_data(&self.rodata_sec));
+        }
+        lines.push(".text".to_string());
+        for fn_code in &self.text_sec {
+            lines.push(format!("{}:", fn_code.label));
+            for instr in &fn_code.instructions {
+                lines.push(gas::instruction(instr, &fn_code.label));
+            }
+        }
+        lines
+    }
+}
+
+/// データセクションを GNU as の疑似命令にする
+fn serialize_gas_data(section: &[DataDirective]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for data_dir in section {
+        match data_dir {
+            DataDirective::DB { left, right } => {
+                lines.push(format!("{}:", left));
+                lines.extend(gas::bytes(right));
+            }
+            DataDirective::DQ { left, right } => {
+                if let Some(left) = left {
+                    lines.push(format!("{}:", left));
+                }
+                lines.push(format!("    .quad {}", right));
+            }
+            // `$ - start` の `$` は GNU as では `.` と書く
+            DataDirective::EQUE { left, right } => {
+                let start = right.join(" ");
+                let start = start.strip_prefix("$ - ").unwrap_or(&start);
+                lines.push(format!("    .set {}, . - {}", left, start));
+            }
+        }
+    }
+    lines
+}
+
+/// データセクションをバイト列にする。`equ` は場所を取らない
+fn encode_data(section: &[DataDirective]) -> MachineCode {
+    let mut code = MachineCode::default();
+    for data_dir in section {
+        match data_dir {
+            DataDirective::DB { left, right } => {
+                code.symbols.push((left.clone(), code.bytes.len()));
+                code.bytes.extend_from_slice(right);
+            }
+            DataDirective::DQ { left, right } => {
+                if let Some(left) = left {
+                    code.symbols.push((left.clone(), code.bytes.len()));
+                }
+                let value = parse_number(right)
+                    .unwrap_or_else(|| unreachable!("`{}` is not a number", right));
+                code.bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            DataDirective::EQUE { .. } => {}
+        }
+    }
+    code
+}
+
+/// 文字列リテラルを読み取り専用のデータセクションに置くための表。同じ中身は1つにまとめる
+#[derive(Default)]
+struct StrTable {
+    labels: std::collections::HashMap<Vec<u8>, String>,
+    directives: Vec<DataDirective>,
+}
+
+impl StrTable {
+    /// `bytes` を置いたラベルを返す。実行時にも長さが分かるように、中身の直前に 8 バイトで長さを置く
+    fn intern(&mut self, bytes: &[u8]) -> String {
+        if let Some(label) = self.labels.get(bytes) {
+            return label.clone();
+        }
+        // 識別子に使えない `.` を入れて、関数名と区別する
+        let label = format!("str.{}", self.labels.len());
+        self.directives.push(DataDirective::DQ {
+            left: None,
+            right: bytes.len().to_string(),
+        });
+        self.directives.push(DataDirective::DB {
+            left: label.clone(),
+            right: bytes.to_vec(),
+        });
+        self.directives.push(DataDirective::EQUE {
+            left: format!("{}_len", label),
+            right: vec![format!("$ - {}", label)],
+        });
+        self.labels.insert(bytes.to_vec(), label.clone());
+        label
+    }
 }
 
 #[derive(Debug)]
 enum DataDirective {
-    DB { left: String, right: Vec<String> },
-    EQUE { left: String, right: Vec<String> },
+    DB {
+        left: String,
+        right: Vec<u8>,
+    },
+    EQUE {
+        left: String,
+        right: Vec<String>,
+    },
+    /// 8 バイトの値。浮動小数点数の定数をビット列で置く
+    DQ {
+        left: Option<String>,
+        right: String,
+    },
 }
 
 impl Serialize for DataDirective {
     fn serialize(&self) -> Vec<String> {
         match self {
+            // 空の文字列はラベルだけを置く
+            DataDirective::DB { left, right } if right.is_empty() => vec![format!("    {}:", left)],
             DataDirective::DB { left, right } => {
+                // 表示できる ASCII 文字は引用符で囲み、それ以外は 16 進数で書く。
+                // `"` と `\` はアセンブラによって意味が変わるので 16 進数にする
                 let mut s = Vec::<String>::new();
-                for r in right {
-                    if r.starts_with("0x") {
-                        s.push(r.clone());
+                let mut quoted = String::new();
+                for &byte in right {
+                    if (b' '..=b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+                        quoted.push(byte as char);
                         continue;
                     }
-                    s.push(format!("\"{}\"", r));
+                    if !quoted.is_empty() {
+                        s.push(format!("\"{}\"", std::mem::take(&mut quoted)));
+                    }
+                    s.push(format!("0x{:02X}", byte));
+                }
+                if !quoted.is_empty() {
+                    s.push(format!("\"{}\"", quoted));
                 }
                 vec![format!("    {} db {}", left, s.join(", "))]
             }
             DataDirective::EQUE { left, right } => {
                 vec![format!("    {} equ {}", left, right.join(" "))]
             }
+            DataDirective::DQ {
+                left: Some(left),
+                right,
+            } => vec![format!("    {} dq {}", left, right)],
+            DataDirective::DQ { left: None, right } => vec![format!("    dq {}", right)],
         }
     }
 }
@@ -342,39 +2437,466 @@ impl Serialize for AsmCode {
     }
 }
 
-trait Serialize {
-    fn serialize(&self) -> Vec<String>;
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::target::{Linux, MacOs};
+    use crate::parser::parser::parse;
+    use crate::resolve::resolve::resolve_program;
+    use crate::thir::typeck::check_program;
 
-#[derive(Debug)]
-enum Instruction {
-    RET,
-    CALL { func: String },
-    MOVE { dest: String, src: String },
-    ADD { dest: String, src: String },
-    LOAD { dest: String, addr: String },
-    SYSCALL,
-    XOR { src1: String, src2: String },
-}
+    fn compile_with(source: &str, target: &dyn Target, regalloc: RegAlloc) -> Vec<String> {
+        compile_with_options(
+            source,
+            &CodegenOptions {
+                target,
+                regalloc,
+                source_name: "main.rs",
+                overflow_checks: true,
+                start_routine: false,
+            },
+        )
+    }
 
-impl Serialize for Instruction {
-    fn serialize(&self) -> Vec<String> {
-        match self {
-            Instruction::RET => vec!["    ret".to_string()],
-            Instruction::CALL { func } => vec![format!("    call {}", func)],
-            Instruction::MOVE { dest, src } => vec![format!("    mov {}, {}", dest, src)],
-            Instruction::ADD { dest, src } => vec![format!("    add {}, {}", dest, src)],
-            Instruction::LOAD { dest, addr } => vec![format!("    lea {}, [{}]", dest, addr)],
-            Instruction::SYSCALL => vec!["    syscall".to_string()],
-            Instruction::XOR { src1, src2 } => vec![format!("    xor {}, {}", src1, src2)],
-        }
+    fn generate(source: &str, options: &CodegenOptions) -> Result<AsmCode, Vec<Diagnostic>> {
+        let program = parse(source).unwrap();
+        let resolutions = resolve_program(&program).unwrap();
+        let types = check_program(&program, &resolutions).unwrap();
+        generate_code(&program, &resolutions, &types, options)
     }
-}
 
-fn convert_to_asm_fn_name(fn_name: &String) -> String {
-    if fn_name == "main" {
-        "_main".to_string()
-    } else {
-        fn_name.clone()
+    fn compile_with_options(source: &str, options: &CodegenOptions) -> Vec<String> {
+        generate(source, options).unwrap().serialize()
+    }
+
+    fn compile_for(source: &str, target: &dyn Target) -> Vec<String> {
+        compile_with(source, target, RegAlloc::Linear)
+    }
+
+    fn compile(source: &str) -> Vec<String> {
+        compile_for(source, &MacOs)
+    }
+
+    #[test]
+    fn test_consts_are_inlined() {
+        let asm = compile(
+            "const LIMIT: i32 = BASE * 4;
+             const BASE: i32 = 10 + 0x1;
+             fn main() {
+                 let x = LIMIT;
+                 f(BASE, x);
+             }
+             fn f(a: i32, b: i32) -> i32 {
+                 return a;
+             }",
+        );
+        assert!(asm.contains(&"    mov r10, 44".to_string()));
+        assert!(asm.contains(&"    mov rdi, 11".to_string()));
+        assert!(
+            !asm.iter()
+                .any(|line| line.contains("LIMIT") || line.contains("BASE"))
+        );
+    }
+
+    #[test]
+    fn test_const_errors_are_reported() {
+        let options = CodegenOptions {
+            target: &MacOs,
+            regalloc: RegAlloc::Linear,
+            source_name: "main.rs",
+            overflow_checks: true,
+            start_routine: false,
+        };
+        let diagnostics = generate("const A: i32 = 1 / 0; fn main() {}", &options).unwrap_err();
+        assert_eq!(
+            diagnostics[0].message,
+            "evaluation of constant value failed"
+        );
+    }
+
+    #[test]
+    fn test_target_specific_output() {
+        let source = "fn main() {
+    println!(\"hi\");
+}";
+        let macos = compile_for(source, &MacOs);
+        assert_eq!(macos[0], "global _main");
+        assert!(macos.contains(&"_main:".to_string()));
+        assert!(macos.contains(&"    mov rax, 0x2000004".to_string()));
+        assert!(macos.contains(&"    mov rax, 0x2000001".to_string()));
+
+        let linux = compile_for(source, &Linux);
+        assert_eq!(linux[0], "global main");
+        assert!(linux.contains(&"main:".to_string()));
+        assert!(linux.contains(&"    mov rax, 1".to_string()));
+        assert!(linux.contains(&"    mov rax, 60".to_string()));
+    }
+
+    #[test]
+    fn test_frame_and_callee_saved_rsts() {
+        let source = "fn f(n: i32) -> i32 {
+                 let a = n;
+                 let b = a;
+                 let c = b;
+                 let d = c;
+                 let e = d;
+                 return a + (b + (c + (d + e)));
+             }
+             fn main() {}";
+        let asm = compile(source);
+        let f_start = asm.iter().position(|line| line == "fn.f:").unwrap();
+        assert_eq!(asm[f_start + 1], "    push rbp");
+        assert_eq!(asm[f_start + 2], "    mov rbp, rsp");
+        // 使った callee-saved レジスタの分だけフレームを取って退避する
+        assert_eq!(asm[f_start + 3], "    sub rsp, 32");
+        assert_eq!(asm[f_start + 4], "    mov qword [rbp - 8], rbx");
+        assert!(asm.contains(&"    mov rbx, qword [rbp - 8]".to_string()));
+        assert!(asm.contains(&"    add r10d, ebx".to_string()));
+        // 同じレジスタ同士の mov は消える
+        assert!(!asm.contains(&"    mov r10, r10".to_string()));
+
+        let main_start = asm.iter().position(|line| line == "_main:").unwrap();
+        assert_eq!(asm[main_start + 3], "    mov rax, 0x2000001");
+        // 足し算の桁あふれを確かめるので、最後にパニックの処理が付く
+        assert!(asm[main_start..].contains(&"    ret".to_string()));
+        assert_eq!(asm.last().unwrap(), "    syscall");
+
+        // naive では全ての値をスタックに置く
+        let naive = compile_with(source, &MacOs, RegAlloc::Naive);
+        let f_start = naive.iter().position(|line| line == "fn.f:").unwrap();
+        assert_eq!(naive[f_start + 3], "    sub rsp, 96");
+        assert_eq!(naive[f_start + 4], "    mov qword [rbp - 8], rdi");
+        assert!(!naive.iter().any(|line| {
+            ["rbx", "r12", "r13", "r14", "r15"]
+                .iter()
+                .any(|rst| line.contains(rst))
+        }));
+    }
+
+    #[test]
+    fn test_stack_arguments_keep_alignment() {
+        let asm = compile(
+            "fn f(a: i32, b: i32, c: i32, d: i32, e: i32, g: i32, h: i32) -> i32 {
+                 return h;
+             }
+             fn main() {
+                 f(1, 2, 3, 4, 5, 6, f(1, 2, 3, 4, 5, 6, 7));
+             }",
+        );
+        assert!(asm.contains(&"    mov r10, qword [rbp + 16]".to_string()));
+        let main_start = asm.iter().position(|line| line == "_main:").unwrap();
+        let main = &asm[main_start..];
+        // 内側の呼び出しを先に済ませてから、外側の引数をレジスタとスタックに置く
+        let inner_call = main
+            .iter()
+            .position(|line| line == "    call fn.f")
+            .unwrap();
+        assert_eq!(
+            main[inner_call - 8..inner_call - 6],
+            ["    sub rsp, 8", "    push 7"]
+        );
+        assert_eq!(main[inner_call - 6], "    mov rdi, 1");
+        assert_eq!(main[inner_call + 1], "    add rsp, 16");
+        let outer_call = main
+            .iter()
+            .rposition(|line| line == "    call fn.f")
+            .unwrap();
+        assert_eq!(main[outer_call - 7], "    push r10");
+        assert_eq!(main[outer_call + 1], "    add rsp, 16");
+    }
+
+    #[test]
+    fn test_float_arithmetic_uses_sse() {
+        let asm = compile(
+            "const HALF: f64 = 0.5;
+             fn scale(a: i32, x: f64, y: f64) -> f64 {
+                 if x == y {
+                     return -x;
+                 }
+                 return x * HALF + y / 2.0 - 1.5;
+             }
+             fn main() {
+                 let r = scale(1, 3.0, 0.5) + scale(2, HALF, 3.0);
+             }",
+        );
+        // 浮動小数点数の引数は整数と別に xmm0 から順に渡し、戻り値は xmm0 に入れる
+        assert!(asm.contains(&"    movsd xmm8, xmm0".to_string()));
+        assert!(asm.contains(&"    movsd xmm9, xmm1".to_string()));
+        assert!(asm.contains(&"    mov r10, rdi".to_string()));
+        assert!(asm.contains(&"    movsd xmm0, xmm8".to_string()));
+        assert!(asm.contains(&"    movsd xmm0, qword [main.f64_0]".to_string()));
+        assert!(asm.contains(&"    movsd xmm1, qword [main.f64_1]".to_string()));
+        // == は NaN の時に偽になるよう PF も確かめる
+        let compare = asm
+            .iter()
+            .position(|line| line == "    ucomisd xmm10, xmm11")
+            .unwrap();
+        assert_eq!(
+            asm[compare + 1..compare + 3],
+            ["    jp .if_else_0", "    jne .if_else_0"]
+        );
+        assert!(asm.contains(&"    mulsd xmm10, qword [scale.f64_0]".to_string()));
+        assert!(asm.contains(&"    divsd xmm9, xmm10".to_string()));
+        // 定数は関数ごとにデータセクションへビット列で置き、同じ値は1つにまとめる
+        assert!(asm.contains(&"    scale.f64_0 dq 0xBFF0000000000000".to_string()));
+        assert!(asm.contains(&"    scale.f64_1 dq 0x3FE0000000000000".to_string()));
+        assert_eq!(
+            asm.iter()
+                .filter(|line| line.ends_with("dq 0x3FE0000000000000"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_integer_widths_and_extension() {
+        let asm = compile(
+            "fn f(a: u8, b: i16, c: u32, d: i64) -> u8 {
+                 let x = b * 2;
+                 if c > 7 {
+                     return a + 1;
+                 }
+                 return (d + x as i64) as u8;
+             }
+             fn main() {}",
+        );
+        // 32 ビット以下の型は 32 ビットで計算し、結果を型の大きさから拡張し直す
+        assert!(asm.contains(&"    imul ebx, r14d".to_string()));
+        assert!(asm.contains(&"    movsx rbx, bx".to_string()));
+        assert!(asm.contains(&"    add r10d, r12d".to_string()));
+        assert!(asm.contains(&"    movzx r10, r10b".to_string()));
+        // 符号無し整数は符号無しの条件で比べる
+        let compare = asm
+            .iter()
+            .position(|line| line == "    cmp r12d, r14d")
+            .unwrap();
+        assert_eq!(asm[compare + 1], "    jbe .if_else_0");
+    }
+
+    #[test]
+    fn test_division_checks_for_zero() {
+        let asm = compile(
+            "const TWO: i32 = 2;
+             fn f(a: i32, b: i32) -> i32 {
+                 return a / b - a % TWO * b;
+             }
+             fn main() {}",
+        );
+        // 変数で割る時だけ 0 かどうかと `MIN / -1` かどうかを確かめる
+        let checks: Vec<&String> = asm
+            .iter()
+            .filter(|line| line.starts_with("    je .panic_"))
+            .collect();
+        assert_eq!(checks, ["    je .panic_0", "    je .panic_1"]);
+        assert!(asm.contains(&"    idiv r13d".to_string()));
+        assert!(asm.contains(&"    mov r10, rdx".to_string()));
+        assert!(asm.contains(&"    imul r10d, ebx".to_string()));
+        assert!(asm.contains(&"    sub r12d, r10d".to_string()));
+        assert!(asm.contains(&".panic_0:".to_string()));
+        assert!(asm.contains(&"    call __likerustc_panic".to_string()));
+        assert!(asm.contains(&"__likerustc_panic:".to_string()));
+        assert!(asm.iter().any(|line| line.contains(
+            "db \"thread 'main' panicked at main.rs:3:25:\", 0x0A, \"attempt to divide by zero\""
+        )));
+        // メッセージは書き換えないので .rodata に置く
+        let rodata = asm
+            .iter()
+            .position(|line| line == "section .rodata")
+            .unwrap();
+        let text = asm.iter().position(|line| line == "section .text").unwrap();
+        assert!(
+            asm[rodata..text]
+                .iter()
+                .any(|line| line.starts_with("    f.panic_msg_0 db"))
+        );
+    }
+
+    #[test]
+    fn test_division_by_constant_zero_is_an_error() {
+        let options = CodegenOptions {
+            target: &MacOs,
+            regalloc: RegAlloc::Linear,
+            source_name: "main.rs",
+            overflow_checks: true,
+            start_routine: false,
+        };
+        let diagnostics = generate(
+            "const ZERO: i32 = 0;
+             fn main() {
+                 let x = 5;
+                 let y = x % ZERO;
+                 let z = 10 / 0;
+             }",
+            &options,
+        )
+        .unwrap_err();
+        let labels: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.label.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                "attempt to calculate the remainder of `_` with a divisor of zero",
+                "attempt to divide `10_i32` by zero"
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "this operation will panic at runtime"
+        );
+    }
+
+    #[test]
+    fn test_overflow_checks() {
+        let source = "fn f(a: i32, b: u64, c: u8) -> u64 {
+                 let x = a + 1;
+                 let y = b * 2;
+                 let z = c.wrapping_mul(3);
+                 return y + x as u64 + z as u64;
+             }
+             fn main() {}";
+        let asm = compile(source);
+        // 符号付きは OF、符号無しは CF を見る。`u64` の掛け算は `mul` の上位の桁で確かめる
+        assert!(asm.contains(&"    jo .panic_0".to_string()));
+        assert!(asm.contains(&"    mul r13".to_string()));
+        assert!(asm.contains(&"    jb .panic_1".to_string()));
+        assert!(
+            asm.iter()
+                .any(|line| line.contains("attempt to multiply with overflow"))
+        );
+
+        let unchecked = compile_with_options(
+            source,
+            &CodegenOptions {
+                target: &MacOs,
+                regalloc: RegAlloc::Linear,
+                source_name: "main.rs",
+                overflow_checks: false,
+                start_routine: false,
+            },
+        );
+        assert!(!unchecked.iter().any(|line| line.contains(".panic_")));
+        assert!(!unchecked.contains(&"__likerustc_panic:".to_string()));
+    }
+
+    #[test]
+    fn test_negative_literals_are_constants() {
+        let asm = compile(
+            "fn f() -> i64 {
+                 let x: i32 = -2147483648;
+                 return -9223372036854775808 + x as i64;
+             }
+             fn main() {}",
+        );
+        // 最小値は符号を反転すると桁あふれするので、負の定数のまま置く
+        assert!(asm.contains(&"    mov r10, -2147483648".to_string()));
+        assert!(asm.contains(&"    mov rbx, -9223372036854775808".to_string()));
+        assert!(!asm.iter().any(|line| line.contains("neg")));
+    }
+
+    #[test]
+    fn test_print_macros_use_separate_labels() {
+        let asm = compile(
+            "fn main() {
+                 let x = 5;
+                 println!(\"x = {}\", x);
+                 println!(\"x = {}\", x);
+                 eprintln!(\"done\");
+             }",
+        );
+        // 同じ文字列は1つにまとめ、呼び出しごとにラベルを作り直さない
+        assert!(asm.contains(&"    str.0 db \"x = \"".to_string()));
+        assert!(asm.contains(&"    str.1 db 0x0A".to_string()));
+        assert!(asm.contains(&"    str.2 db \"done\", 0x0A".to_string()));
+        let prints = asm
+            .iter()
+            .filter(|line| *line == "    call __likerustc_print_int")
+            .count();
+        assert_eq!(prints, 2);
+        assert!(asm.contains(&"__likerustc_print_int:".to_string()));
+        assert!(!asm.iter().any(|line| line.contains("call println!")));
+    }
+
+    #[test]
+    fn test_string_literals_are_interned() {
+        let asm = compile(
+            "fn f() -> &str {
+                 return \"say \\\"hi\\\"\\n\";
+             }
+             fn main() {
+                 let a = \"say \\\"hi\\\"\\n\";
+                 let b = b\"0x41\\\\\";
+                 print!(\"{}{}\", a, f());
+             }",
+        );
+        let rodata = asm
+            .iter()
+            .skip_while(|line| *line != "section .rodata")
+            .take_while(|line| *line != "section .text")
+            .cloned()
+            .collect::<Vec<_>>();
+        // 関数をまたいでも同じ中身は1つにまとめ、長さを中身の直前に置く
+        assert_eq!(
+            rodata,
+            [
+                "section .rodata",
+                "    dq 9",
+                "    str.0 db \"say \", 0x22, \"hi\", 0x22, 0x0A",
+                "    str.0_len equ $ - str.0",
+                "    dq 5",
+                "    str.1 db \"0x41\", 0x5C",
+                "    str.1_len equ $ - str.1",
+            ]
+        );
+        // 文字列の値は実行時に長さを読んで書き出す
+        assert!(asm.contains(&"    mov rdx, qword [rsi - 8]".to_string()));
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let options = CodegenOptions {
+            target: &Linux,
+            regalloc: RegAlloc::Linear,
+            source_name: "main.rs",
+            overflow_checks: true,
+            start_routine: true,
+        };
+        let asm_code = generate(
+            "fn main() {
+                 let x = 2.5;
+                 let mut i = 0;
+                 while i < 3 {
+                     i = i + 1;
+                 }
+                 println!(\"{}\", i);
+             }",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(asm_code.constants()["str.0_len"], 1);
+        let code = asm_code.encode_text(&[]);
+        let symbols: Vec<&str> = code.symbols.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(symbols, ["main", PRINT_INT_FN, PANIC_FN, START_FN]);
+        // 関数とラベルへのジャンプはその場で埋まり、データへの参照だけが残る
+        let relocated: Vec<&str> = code
+            .relocations
+            .iter()
+            .map(|relocation| relocation.symbol.as_str())
+            .collect();
+        assert_eq!(relocated, ["main.f64_0", "str.0", "main.panic_msg_0"]);
+        // 書き換えない文字列とパニックのメッセージは .rodata への参照になる
+        let in_rodata = |symbol: &str| {
+            asm_code.rodata_sec.iter().any(
+                |data_dir| matches!(data_dir, DataDirective::DB { left, .. } if left == symbol),
+            )
+        };
+        assert_eq!(
+            relocated
+                .iter()
+                .map(|symbol| in_rodata(symbol))
+                .collect::<Vec<_>>(),
+            [false, true, true]
+        );
     }
 }
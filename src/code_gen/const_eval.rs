@@ -0,0 +1,721 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::program::{
+    Expr, ExprKind, IntMethod, Item, ItemConst, MethodCall, OPTION_METHODS, Operator, Program,
+    Span, Ty,
+};
+use crate::diagnostic::diagnostic::Diagnostic;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConstValue {
+    /// 整数。`u64` の最大値も入るように `i128` で持つ
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+}
+
+impl ConstValue {
+    /// アセンブリの即値として埋め込む時の表現。`u64` の大きな値は2の補数の負の数になる。
+    /// 浮動小数点数はビット列をそのまま書く
+    pub fn to_immediate(self) -> String {
+        match self {
+            ConstValue::Int(value) => (value as i64).to_string(),
+            ConstValue::Float(value) => float_bits(value),
+            ConstValue::Bool(value) => (value as i64).to_string(),
+            ConstValue::Char(value) => (value as u32).to_string(),
+        }
+    }
+
+    /// エラーメッセージ用の型の表現
+    fn describe(self) -> &'static str {
+        match self {
+            ConstValue::Int(_) => "integer",
+            ConstValue::Float(_) => "`f64`",
+            ConstValue::Bool(_) => "`bool`",
+            ConstValue::Char(_) => "`char`",
+        }
+    }
+}
+
+/// 定数名から評価済みの値への表
+pub type ConstTable = HashMap<String, ConstValue>;
+
+/// 全ての `const` を評価する。定数は宣言順に関係なく他の定数を参照できる
+pub fn evaluate_consts(program: &Program) -> Result<ConstTable, Vec<Diagnostic>> {
+    let mut evaluator = ConstEvaluator {
+        items: HashMap::new(),
+        values: HashMap::new(),
+        in_progress: Vec::new(),
+        failed: HashSet::new(),
+    };
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for item in &program.items {
+        if let Item::ItemConst(item_const) = item {
+            if evaluator.items.contains_key(&item_const.name) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        format!("the name `{}` is defined multiple times", item_const.name),
+                        item_const.span,
+                    )
+                    .with_label(format!("`{}` redefined here", item_const.name)),
+                );
+                continue;
+            }
+            evaluator.items.insert(item_const.name.clone(), item_const);
+        }
+    }
+    for item in &program.items {
+        if let Item::ItemConst(item_const) = item {
+            if evaluator.failed.contains(&item_const.name) {
+                continue;
+            }
+            if let Err(diagnostic) = evaluator.eval_const(&item_const.name)
+                && !diagnostics.contains(&diagnostic)
+            {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(evaluator.values)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+struct ConstEvaluator<'a> {
+    items: HashMap<String, &'a ItemConst>,
+    values: ConstTable,
+    /// 循環参照を検出するための、評価中の定数名
+    in_progress: Vec<String>,
+    /// 評価に失敗した定数。同じ原因のエラーを何度も報告しないために使う
+    failed: HashSet<String>,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    fn eval_const(&mut self, name: &str) -> Result<ConstValue, Diagnostic> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(*value);
+        }
+        let item_const = self.items[name];
+        if self.in_progress.iter().any(|n| n == name) {
+            return Err(Diagnostic::error(
+                format!("cycle detected when evaluating constant `{}`", name),
+                item_const.span,
+            )
+            .with_note(format!(
+                "the cycle is {} -> {}",
+                self.in_progress.join(" -> "),
+                name
+            )));
+        }
+        self.in_progress.push(name.to_string());
+        // 整数の計算は宣言された型で桁あふれを確かめる
+        let int_ty = if item_const.const_type.is_integer() {
+            item_const.const_type
+        } else {
+            Ty::I32
+        };
+        let result = self.eval_expr(&item_const.value, int_ty);
+        self.in_progress.pop();
+        let value = result
+            .and_then(|value| check_const_type(item_const, value))
+            .inspect_err(|_| {
+                self.failed.insert(name.to_string());
+            })?;
+        self.values.insert(name.to_string(), value);
+        Ok(value)
+    }
+
+    /// 式を評価する。サフィックスの無い整数リテラルと整数の計算は `int_ty` 型として扱う
+    fn eval_expr(&mut self, expr: &Expr, int_ty: Ty) -> Result<ConstValue, Diagnostic> {
+        match &expr.kind {
+            ExprKind::ExprLit(lit) => eval_int_literal(lit, false, int_ty, expr.span),
+            ExprKind::ExprFloatLit(lit) => match parse_float_literal(lit) {
+                Some(value) => Ok(ConstValue::Float(value)),
+                None => Err(Diagnostic::error(
+                    format!("invalid float literal `{}`", lit),
+                    expr.span,
+                )),
+            },
+            ExprKind::ExprBoolLit(value) => Ok(ConstValue::Bool(*value)),
+            ExprKind::ExprCharLit(c) => Ok(ConstValue::Char(*c)),
+            ExprKind::ExprStrLit(_) | ExprKind::ExprByteStrLit(_) => Err(Diagnostic::error(
+                "string constants are not supported yet",
+                expr.span,
+            )),
+            ExprKind::ExprVariable(name) => {
+                if self.items.contains_key(name) {
+                    self.eval_const(name)
+                } else {
+                    Err(Diagnostic::error(
+                        format!("cannot find value `{}` in this scope", name),
+                        expr.span,
+                    )
+                    .with_label("not found in this scope")
+                    .with_note("constants can only refer to other constants"))
+                }
+            }
+            ExprKind::ExprFnCall(fn_call) => Err(Diagnostic::error(
+                format!("cannot call non-const fn `{}` in constants", fn_call.name),
+                expr.span,
+            )),
+            ExprKind::ExprMethodCall(call) => self.eval_method_call(call, int_ty, expr.span),
+            ExprKind::ExprUnary {
+                op: Operator::Minus,
+                operand,
+            } if matches!(operand.kind, ExprKind::ExprLit(_)) => {
+                // `-128i8` は符号を含めて1つのリテラルとして範囲を確かめる
+                let ExprKind::ExprLit(lit) = &operand.kind else {
+                    unreachable!()
+                };
+                eval_int_literal(lit, true, int_ty, expr.span)
+            }
+            ExprKind::ExprUnary { op, operand } => {
+                let value = self.eval_expr(operand, int_ty)?;
+                match (op, value) {
+                    (Operator::Minus, ConstValue::Int(v)) if int_ty.is_signed() => {
+                        checked_int(v.checked_neg(), int_ty, expr.span, "negate")
+                    }
+                    (Operator::Minus, ConstValue::Float(v)) => Ok(ConstValue::Float(-v)),
+                    (Operator::Bang, ConstValue::Int(v)) => Ok(ConstValue::Int(int_ty.wrap(!v))),
+                    (Operator::Bang, ConstValue::Bool(b)) => Ok(ConstValue::Bool(!b)),
+                    _ => Err(Diagnostic::error(
+                        format!(
+                            "cannot apply unary operator `{}` to type {}",
+                            op,
+                            match value {
+                                ConstValue::Int(_) => format!("`{}`", int_ty),
+                                _ => value.describe().to_string(),
+                            }
+                        ),
+                        expr.span,
+                    )),
+                }
+            }
+            ExprKind::ExprCast { expr: operand, ty } => {
+                // キャストする値の型は前後から決まらないので、rustcと同じく整数は `i32` にする
+                let value = self.eval_expr(operand, Ty::I32)?;
+                cast(value, *ty).ok_or_else(|| {
+                    Diagnostic::error(
+                        format!("casting {} as `{}` is invalid", value.describe(), ty),
+                        expr.span,
+                    )
+                })
+            }
+            ExprKind::ExprBinaryOp { left, op, right } => {
+                let left_value = self.eval_expr(left, int_ty)?;
+                // && と || は右辺を評価せずに結果が決まることがある
+                match (op, left_value) {
+                    (Operator::AndAnd, ConstValue::Bool(false)) => {
+                        return Ok(ConstValue::Bool(false));
+                    }
+                    (Operator::OrOr, ConstValue::Bool(true)) => return Ok(ConstValue::Bool(true)),
+                    _ => (),
+                }
+                let right_value = self.eval_expr(right, int_ty)?;
+                eval_binary_op(op, left_value, right_value, int_ty, expr.span)
+            }
+        }
+    }
+
+    /// `wrapping_*` と、`Option` のメソッドですぐに取り出す `checked_*` を評価する
+    fn eval_method_call(
+        &mut self,
+        call: &MethodCall,
+        int_ty: Ty,
+        span: Span,
+    ) -> Result<ConstValue, Diagnostic> {
+        // 文字列リテラルの長さはバイト数
+        match &call.receiver.kind {
+            ExprKind::ExprStrLit(s) if call.name == "len" => {
+                return Ok(ConstValue::Int(s.len() as i128));
+            }
+            ExprKind::ExprByteStrLit(bytes) if call.name == "len" => {
+                return Ok(ConstValue::Int(bytes.len() as i128));
+            }
+            _ => (),
+        }
+        if let ExprKind::ExprMethodCall(inner) = &call.receiver.kind
+            && OPTION_METHODS.contains(&call.name.as_str())
+            && let Some(method) = IntMethod::from_name(&inner.name)
+            && method.checked
+        {
+            let value = self.eval_int_method(inner, &method, int_ty, span)?;
+            let default = match call.args.first() {
+                Some(arg) if call.name == "unwrap_or" => Some(self.eval_expr(arg, int_ty)?),
+                _ => None,
+            };
+            return match (call.name.as_str(), value, default) {
+                ("is_some", value, _) => Ok(ConstValue::Bool(value.is_some())),
+                ("is_none", value, _) => Ok(ConstValue::Bool(value.is_none())),
+                (_, Some(value), _) => Ok(ConstValue::Int(value)),
+                (_, None, Some(default)) => Ok(default),
+                (_, None, None) => Err(const_eval_error(
+                    span,
+                    "called `Option::unwrap()` on a `None` value",
+                )),
+            };
+        }
+        match IntMethod::from_name(&call.name) {
+            Some(method) if !method.checked => {
+                let value = self.eval_int_method(call, &method, int_ty, span)?;
+                Ok(ConstValue::Int(value.unwrap()))
+            }
+            _ => Err(Diagnostic::error(
+                format!("cannot call method `{}` in constants", call.name),
+                call.span,
+            )),
+        }
+    }
+
+    /// 整数のメソッドを評価する。`checked_*` で桁あふれしたら `None`、`wrapping_*` なら切り詰めた値
+    fn eval_int_method(
+        &mut self,
+        call: &MethodCall,
+        method: &IntMethod,
+        int_ty: Ty,
+        span: Span,
+    ) -> Result<Option<i128>, Diagnostic> {
+        let ConstValue::Int(l) = self.eval_expr(&call.receiver, int_ty)? else {
+            return Err(Diagnostic::error(
+                format!("no method named `{}` found in constants", call.name),
+                call.span,
+            ));
+        };
+        let r = match (&method.op, call.args.first()) {
+            (None, _) => 0,
+            (Some(op), Some(arg)) => {
+                // シフト量は `u32`
+                let arg_ty = match op {
+                    Operator::Shl | Operator::Shr => Ty::U32,
+                    _ => int_ty,
+                };
+                match self.eval_expr(arg, arg_ty)? {
+                    ConstValue::Int(r) => r,
+                    value => {
+                        return Err(Diagnostic::error("mismatched types", arg.span)
+                            .with_label(format!("expected integer, found {}", value.describe())));
+                    }
+                }
+            }
+            (Some(_), None) => {
+                return Err(Diagnostic::error(
+                    "this method takes 1 argument but 0 arguments were supplied",
+                    call.span,
+                ));
+            }
+        };
+        let (min, max) = int_ty.int_range();
+        let bits = int_ty.bits() as i128;
+        let (value, overflow) = match &method.op {
+            None => (-l, false),
+            Some(op @ (Operator::Slash | Operator::Percent)) if r == 0 => {
+                if method.checked {
+                    return Ok(None);
+                }
+                // 割る数が 0 なら `wrapping_*` でもパニックする
+                return eval_binary_op(op, ConstValue::Int(l), ConstValue::Int(r), int_ty, span)
+                    .map(|_| None);
+            }
+            Some(Operator::Slash) => (l / r, false),
+            // `MIN % -1` は値が 0 でも桁あふれとして扱う
+            Some(Operator::Percent) => (l % r, l == min && r == -1),
+            // シフトは量だけを確かめ、はみ出したビットは捨てる
+            Some(op @ (Operator::Shl | Operator::Shr)) => {
+                if method.checked && r >= bits {
+                    return Ok(None);
+                }
+                let shift = r % bits;
+                let value = if *op == Operator::Shl {
+                    l << shift
+                } else {
+                    l >> shift
+                };
+                return Ok(Some(int_ty.wrap(value)));
+            }
+            Some(Operator::Plus) => (l + r, false),
+            Some(Operator::Minus) => (l - r, false),
+            Some(_) => (l * r, false),
+        };
+        let overflow = overflow || !(min..=max).contains(&value);
+        Ok(match (method.checked, overflow) {
+            (true, true) => None,
+            _ => Some(int_ty.wrap(value)),
+        })
+    }
+}
+
+/// 整数リテラルを `int_ty` 型 (サフィックスがあればその型) の値として読み、範囲を確かめる
+fn eval_int_literal(
+    lit: &str,
+    negated: bool,
+    int_ty: Ty,
+    span: Span,
+) -> Result<ConstValue, Diagnostic> {
+    let ty = int_literal_suffix(lit).unwrap_or(int_ty);
+    let (min, max) = ty.int_range();
+    match parse_int_literal(lit) {
+        Some(value) if (min..=max).contains(&if negated { -value } else { value }) => {
+            Ok(ConstValue::Int(if negated { -value } else { value }))
+        }
+        _ => Err(
+            Diagnostic::error(format!("literal out of range for `{}`", ty), span).with_note(
+                format!(
+                    "the literal `{}` does not fit into the type `{}` whose range is `{}..={}`",
+                    lit, ty, min, max
+                ),
+            ),
+        ),
+    }
+}
+
+/// `as` による変換。整数同士は切り詰め、浮動小数点数から整数へは範囲内に丸める。変換できなければ `None`
+pub fn cast(value: ConstValue, ty: Ty) -> Option<ConstValue> {
+    match (value, ty) {
+        (ConstValue::Int(v), ty) if ty.is_integer() => Some(ConstValue::Int(ty.wrap(v))),
+        (ConstValue::Bool(b), ty) if ty.is_integer() => Some(ConstValue::Int(b as i128)),
+        (ConstValue::Char(c), ty) if ty.is_integer() => Some(ConstValue::Int(ty.wrap(c as i128))),
+        // `u8` 以外からの変換は型検査で弾いている
+        (ConstValue::Int(v), Ty::Char) => u8::try_from(v).ok().map(|b| ConstValue::Char(b as char)),
+        (ConstValue::Char(c), Ty::Char) => Some(ConstValue::Char(c)),
+        (ConstValue::Int(v), Ty::F64) => Some(ConstValue::Float(v as f64)),
+        (ConstValue::Float(v), Ty::F64) => Some(ConstValue::Float(v)),
+        (ConstValue::Float(v), ty) if ty.is_integer() => {
+            // NaN は 0 になる
+            let (min, max) = ty.int_range();
+            Some(ConstValue::Int((v as i128).clamp(min, max)))
+        }
+        (ConstValue::Bool(b), Ty::Bool) => Some(ConstValue::Bool(b)),
+        _ => None,
+    }
+}
+
+/// 評価結果が宣言された型と一致しているか確かめる
+fn check_const_type(item_const: &ItemConst, value: ConstValue) -> Result<ConstValue, Diagnostic> {
+    match (item_const.const_type, value) {
+        (ty, ConstValue::Int(_)) if ty.is_integer() => Ok(value),
+        (Ty::F64, ConstValue::Float(_))
+        | (Ty::Bool, ConstValue::Bool(_))
+        | (Ty::Char, ConstValue::Char(_)) => Ok(value),
+        _ => Err(
+            Diagnostic::error("mismatched types", item_const.value.span).with_label(format!(
+                "expected `{}`, found {}",
+                item_const.const_type,
+                value.describe()
+            )),
+        ),
+    }
+}
+
+fn eval_binary_op(
+    op: &Operator,
+    left: ConstValue,
+    right: ConstValue,
+    int_ty: Ty,
+    span: Span,
+) -> Result<ConstValue, Diagnostic> {
+    use ConstValue::{Bool, Char, Float, Int};
+    match (left, right) {
+        (Int(l), Int(r)) => match op {
+            Operator::Plus => checked_int(l.checked_add(r), int_ty, span, "add"),
+            Operator::Minus => checked_int(l.checked_sub(r), int_ty, span, "subtract"),
+            Operator::Asterisk => checked_int(l.checked_mul(r), int_ty, span, "multiply"),
+            Operator::Slash | Operator::Percent if r == 0 => {
+                let action = if *op == Operator::Slash {
+                    "attempt to divide by zero"
+                } else {
+                    "attempt to calculate the remainder with a divisor of zero"
+                };
+                Err(const_eval_error(span, action))
+            }
+            Operator::Slash => checked_int(l.checked_div(r), int_ty, span, "divide"),
+            Operator::Percent => {
+                checked_int(l.checked_rem(r), int_ty, span, "calculate the remainder")
+            }
+            Operator::Shl | Operator::Shr if !(0..int_ty.bits() as i128).contains(&r) => {
+                let action = if *op == Operator::Shl {
+                    "left"
+                } else {
+                    "right"
+                };
+                Err(const_eval_error(
+                    span,
+                    &format!("attempt to shift {} with overflow", action),
+                ))
+            }
+            Operator::Shl => Ok(Int(int_ty.wrap(l << r))),
+            Operator::Shr => Ok(Int(l >> r)),
+            Operator::Ampersand => Ok(Int(l & r)),
+            Operator::Pipe => Ok(Int(l | r)),
+            Operator::Caret => Ok(Int(l ^ r)),
+            Operator::EqEq => Ok(Bool(l == r)),
+            Operator::NotEq => Ok(Bool(l != r)),
+            Operator::Lt => Ok(Bool(l < r)),
+            Operator::Gt => Ok(Bool(l > r)),
+            Operator::LtEq => Ok(Bool(l <= r)),
+            Operator::GtEq => Ok(Bool(l >= r)),
+            _ => Err(binary_type_error(op, &int_ty.to_string(), span)),
+        },
+        (Float(l), Float(r)) => match op {
+            Operator::Plus => Ok(Float(l + r)),
+            Operator::Minus => Ok(Float(l - r)),
+            Operator::Asterisk => Ok(Float(l * r)),
+            Operator::Slash => Ok(Float(l / r)),
+            Operator::Percent => Ok(Float(l % r)),
+            Operator::EqEq => Ok(Bool(l == r)),
+            Operator::NotEq => Ok(Bool(l != r)),
+            Operator::Lt => Ok(Bool(l < r)),
+            Operator::Gt => Ok(Bool(l > r)),
+            Operator::LtEq => Ok(Bool(l <= r)),
+            Operator::GtEq => Ok(Bool(l >= r)),
+            _ => Err(binary_type_error(op, "f64", span)),
+        },
+        (Bool(l), Bool(r)) => match op {
+            Operator::AndAnd | Operator::Ampersand => Ok(Bool(l && r)),
+            Operator::OrOr | Operator::Pipe => Ok(Bool(l || r)),
+            Operator::Caret | Operator::NotEq => Ok(Bool(l != r)),
+            Operator::EqEq => Ok(Bool(l == r)),
+            _ => Err(binary_type_error(op, "bool", span)),
+        },
+        (Char(l), Char(r)) => match op {
+            Operator::EqEq => Ok(Bool(l == r)),
+            Operator::NotEq => Ok(Bool(l != r)),
+            Operator::Lt => Ok(Bool(l < r)),
+            Operator::Gt => Ok(Bool(l > r)),
+            Operator::LtEq => Ok(Bool(l <= r)),
+            Operator::GtEq => Ok(Bool(l >= r)),
+            _ => Err(binary_type_error(op, "char", span)),
+        },
+        _ => Err(
+            Diagnostic::error("mismatched types", span).with_label(format!(
+                "cannot apply `{}` to {} and {}",
+                op,
+                left.describe(),
+                right.describe()
+            )),
+        ),
+    }
+}
+
+/// 型の範囲に収まらない計算結果をエラーにする
+fn checked_int(
+    value: Option<i128>,
+    ty: Ty,
+    span: Span,
+    action: &str,
+) -> Result<ConstValue, Diagnostic> {
+    let (min, max) = ty.int_range();
+    match value {
+        Some(v) if (min..=max).contains(&v) => Ok(ConstValue::Int(v)),
+        _ => Err(const_eval_error(
+            span,
+            &format!("attempt to {} with overflow", action),
+        )),
+    }
+}
+
+fn const_eval_error(span: Span, label: &str) -> Diagnostic {
+    Diagnostic::error("evaluation of constant value failed", span).with_label(label)
+}
+
+fn binary_type_error(op: &Operator, ty: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        format!("no implementation for `{} {} {}`", ty, op, ty),
+        span,
+    )
+}
+
+/// `10` や `0x1F`、`255u8` のような整数リテラルの値を読む。サフィックスは型に関係なく読み飛ばす
+pub fn parse_int_literal(lit: &str) -> Option<i128> {
+    let lit = match int_literal_suffix(lit) {
+        Some(ty) => &lit[..lit.len() - ty.to_string().len()],
+        None => lit,
+    };
+    let digits = lit.replace('_', "");
+    match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok(),
+        None => digits.parse::<i128>().ok(),
+    }
+}
+
+/// `10u8` のような整数リテラルのサフィックスが表す型
+pub fn int_literal_suffix(lit: &str) -> Option<Ty> {
+    Ty::INTEGERS
+        .into_iter()
+        .find(|ty| lit.len() > ty.to_string().len() && lit.ends_with(&ty.to_string()))
+}
+
+/// `1.5` や `2e10`、`1f64` のような浮動小数点数のリテラルを読む
+pub fn parse_float_literal(lit: &str) -> Option<f64> {
+    lit.trim_end_matches("f64")
+        .replace('_', "")
+        .parse::<f64>()
+        .ok()
+}
+
+/// 浮動小数点数をメモリに置く時のビット列
+pub fn float_bits(value: f64) -> String {
+    format!("0x{:016X}", value.to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse;
+
+    fn eval(source: &str) -> Result<ConstTable, Vec<Diagnostic>> {
+        evaluate_consts(&parse(source).unwrap())
+    }
+
+    fn eval_error(source: &str) -> String {
+        let diagnostics = eval(source).expect_err("expected a const evaluation error");
+        let d = &diagnostics[0];
+        match &d.label {
+            Some(label) => format!("{}: {}", d.message, label),
+            None => d.message.clone(),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_consts_in_any_order() {
+        let consts = eval(
+            "const B: i32 = A * 2 + 1;
+             const A: i32 = (3 + 4) % 5;
+             const C: i32 = -B << 2 | 0x1;",
+        )
+        .unwrap();
+        assert_eq!(consts["A"], ConstValue::Int(2));
+        assert_eq!(consts["B"], ConstValue::Int(5));
+        assert_eq!(consts["C"], ConstValue::Int(-19));
+    }
+
+    #[test]
+    fn test_evaluate_float_consts() {
+        let consts = eval(
+            "const HALF: f64 = 1.0 / 2.0;
+             const X: f64 = -HALF * 1e1 + 2.5E-1;",
+        )
+        .unwrap();
+        assert_eq!(consts["HALF"], ConstValue::Float(0.5));
+        assert_eq!(consts["X"], ConstValue::Float(-4.75));
+        assert_eq!(ConstValue::Float(1.5).to_immediate(), "0x3FF8000000000000");
+        assert_eq!(
+            eval_error("const A: f64 = 1;"),
+            "mismatched types: expected `f64`, found integer"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_typed_integer_consts() {
+        let consts = eval(
+            "const BYTE: u8 = 300 as u8;
+             const MIN: i8 = -128;
+             const WIDE: u64 = 18446744073709551615;
+             const NOT: u16 = !0;
+             const SAT: u8 = -3.5 as u8;
+             const FLAG: bool = true;
+             const ONE: i32 = FLAG as i32;",
+        )
+        .unwrap();
+        assert_eq!(consts["BYTE"], ConstValue::Int(44));
+        assert_eq!(consts["MIN"], ConstValue::Int(-128));
+        assert_eq!(consts["WIDE"], ConstValue::Int(u64::MAX as i128));
+        assert_eq!(consts["WIDE"].to_immediate(), "-1");
+        assert_eq!(consts["NOT"], ConstValue::Int(65535));
+        assert_eq!(consts["SAT"], ConstValue::Int(0));
+        assert_eq!(consts["ONE"], ConstValue::Int(1));
+        assert_eq!(
+            eval_error("const A: u8 = 200 + 100;"),
+            "evaluation of constant value failed: attempt to add with overflow"
+        );
+        assert_eq!(
+            eval_error("const A: u8 = 256;"),
+            "literal out of range for `u8`"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_integer_methods() {
+        let consts = eval(
+            "const WRAP: u8 = 200u8.wrapping_add(100);
+             const NEG: i8 = (-128i8).wrapping_neg();
+             const SHL: i32 = 1i32.wrapping_shl(33);
+             const NONE: i32 = 2147483647i32.checked_mul(2).unwrap_or(-1);
+             const ZERO: bool = 1u8.checked_rem(0).is_none();
+             const SOME: u16 = 3u16.checked_sub(1).unwrap();",
+        )
+        .unwrap();
+        assert_eq!(consts["WRAP"], ConstValue::Int(44));
+        assert_eq!(consts["NEG"], ConstValue::Int(-128));
+        assert_eq!(consts["SHL"], ConstValue::Int(2));
+        assert_eq!(consts["NONE"], ConstValue::Int(-1));
+        assert_eq!(consts["ZERO"], ConstValue::Bool(true));
+        assert_eq!(consts["SOME"], ConstValue::Int(2));
+        assert_eq!(
+            eval_error("const A: u8 = 0u8.checked_sub(1).unwrap();"),
+            "evaluation of constant value failed: called `Option::unwrap()` on a `None` value"
+        );
+        assert_eq!(
+            eval_error("const A: i32 = 1i32.wrapping_div(0);"),
+            "evaluation of constant value failed: attempt to divide by zero"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_char_consts() {
+        let consts = eval(
+            "const A: char = 'a';
+             const B: char = 66u8 as char;
+             const CODE: u32 = A as u32 + 1;
+             const LT: bool = A < B;
+             const LEN: usize = \"héllo\".len() + b\"ab\".len();",
+        )
+        .unwrap();
+        assert_eq!(consts["A"], ConstValue::Char('a'));
+        assert_eq!(consts["B"], ConstValue::Char('B'));
+        assert_eq!(consts["CODE"], ConstValue::Int(98));
+        assert_eq!(consts["LT"], ConstValue::Bool(false));
+        assert_eq!(consts["LEN"], ConstValue::Int(8));
+        assert_eq!(ConstValue::Char('é').to_immediate(), "233");
+        assert_eq!(
+            eval_error("const A: i32 = 'a';"),
+            "mismatched types: expected `i32`, found `char`"
+        );
+    }
+
+    #[test]
+    fn test_const_eval_errors() {
+        assert_eq!(
+            eval_error("const A: i32 = 1 / (2 - 2);"),
+            "evaluation of constant value failed: attempt to divide by zero"
+        );
+        assert_eq!(
+            eval_error("const A: i32 = 2147483647 + 1;"),
+            "evaluation of constant value failed: attempt to add with overflow"
+        );
+        assert_eq!(
+            eval_error("const A: i32 = B; const B: i32 = A;"),
+            "cycle detected when evaluating constant `A`"
+        );
+        assert_eq!(
+            eval("const A: i32 = B; const B: i32 = A;")
+                .unwrap_err()
+                .len(),
+            1
+        );
+        assert_eq!(
+            eval_error("const A: i32 = x + 1;"),
+            "cannot find value `x` in this scope: not found in this scope"
+        );
+        assert_eq!(
+            eval_error("const A: i32 = f();"),
+            "cannot call non-const fn `f` in constants"
+        );
+        assert_eq!(
+            eval_error("const A: i32 = 1 < 2;"),
+            "mismatched types: expected `i32`, found `bool`"
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::HashMap;
+
+use super::encoder::{MachineCode, Object};
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_E551;
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+/// 実行ファイルを読み込む先頭のアドレス。セグメントはページごとに権限を変える
+const BASE_ADDRESS: u64 = 0x40_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// セクションヘッダの並び。0 番は空のセクションで、最後に `.note.GNU-stack` を置く
+const TEXT: u16 = 1;
+const DATA: u16 = 2;
+const RODATA: u16 = 3;
+const RELA_TEXT: u16 = 4;
+const SYMTAB: u16 = 5;
+const STRTAB: u16 = 6;
+const SHSTRTAB: u16 = 7;
+const SECTION_COUNT: u16 = 9;
+
+/// セクション名などを NUL 区切りで並べた文字列表
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        // 0 番目は空文字列
+        StringTable { bytes: vec![0] }
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+struct Symbol {
+    name: u32,
+    info: u8,
+    section: u16,
+    value: u64,
+    size: u64,
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+/// 再配置できる ELF64 のオブジェクトファイルを作る。
+/// `global` でないラベルはローカルなシンボルにし、どこにも無いシンボルは外部のものとして残す
+pub fn write_object(object: &Object) -> Vec<u8> {
+    let mut strtab = StringTable::new();
+    let mut symbols = vec![Symbol {
+        name: 0,
+        info: 0,
+        section: 0,
+        value: 0,
+        size: 0,
+    }];
+    let mut indices = HashMap::new();
+    let sections = [
+        (TEXT, &object.text, STT_FUNC),
+        (DATA, &object.data, STT_OBJECT),
+        (RODATA, &object.rodata, STT_OBJECT),
+    ];
+    // ローカルなシンボルを全て先に置く決まり
+    for global in [false, true] {
+        for (section, code, kind) in sections {
+            for (index, (name, offset)) in code.symbols.iter().enumerate() {
+                if object.globals.contains(name) != global {
+                    continue;
+                }
+                // 関数の大きさは次の関数の先頭まで
+                let size = match kind {
+                    STT_FUNC => {
+                        code.symbols
+                            .get(index + 1)
+                            .map_or(code.bytes.len(), |(_, next)| *next)
+                            - offset
+                    }
+                    _ => 0,
+                };
+                let bind = if global { STB_GLOBAL } else { STB_LOCAL };
+                indices.insert(name.as_str(), symbols.len());
+                symbols.push(Symbol {
+                    name: strtab.add(name),
+                    info: bind << 4 | kind,
+                    section,
+                    value: *offset as u64,
+                    size: size as u64,
+                });
+            }
+        }
+    }
+    let first_global = symbols
+        .iter()
+        .position(|symbol| symbol.info >> 4 == STB_GLOBAL)
+        .unwrap_or(symbols.len());
+
+    let mut rela = Vec::new();
+    for relocation in &object.text.relocations {
+        let index = *indices
+            .entry(relocation.symbol.as_str())
+            .or_insert_with(|| {
+                symbols.push(Symbol {
+                    name: strtab.add(&relocation.symbol),
+                    info: STB_GLOBAL << 4 | STT_NOTYPE,
+                    section: 0,
+                    value: 0,
+                    size: 0,
+                });
+                symbols.len() - 1
+            });
+        // 他のオブジェクトファイルの関数は PLT 経由で呼べるようにしておく
+        let kind = if symbols[index].section == 0 {
+            R_X86_64_PLT32
+        } else {
+            R_X86_64_PC32
+        };
+        rela.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+        rela.extend_from_slice(&((index as u64) << 32 | kind as u64).to_le_bytes());
+        rela.extend_from_slice(&relocation.addend.to_le_bytes());
+    }
+
+    let mut symtab = Vec::new();
+    for symbol in &symbols {
+        symtab.extend_from_slice(&symbol.name.to_le_bytes());
+        symtab.push(symbol.info);
+        symtab.push(0);
+        symtab.extend_from_slice(&symbol.section.to_le_bytes());
+        symtab.extend_from_slice(&symbol.value.to_le_bytes());
+        symtab.extend_from_slice(&symbol.size.to_le_bytes());
+    }
+
+    let mut shstrtab = StringTable::new();
+    let mut out = vec![0; EHDR_SIZE];
+    let mut headers = Vec::new();
+    let mut section = |out: &mut Vec<u8>, name: &str, kind, flags, bytes: &[u8], align: u64| {
+        out.resize(out.len().next_multiple_of(align as usize), 0);
+        headers.push(SectionHeader {
+            name: shstrtab.add(name),
+            kind,
+            flags,
+            offset: out.len(),
+            size: bytes.len(),
+            link: 0,
+            info: 0,
+            align,
+            entry_size: 0,
+        });
+        out.extend_from_slice(bytes);
+    };
+    let text = &object.text.bytes;
+    section(
+        &mut out,
+        ".text",
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_EXECINSTR,
+        text,
+        16,
+    );
+    section(
+        &mut out,
+        ".data",
+        SHT_PROGBITS,
+        SHF_ALLOC | SHF_WRITE,
+        &object.data.bytes,
+        8,
+    );
+    section(
+        &mut out,
+        ".rodata",
+        SHT_PROGBITS,
+        SHF_ALLOC,
+        &object.rodata.bytes,
+        8,
+    );
+    section(&mut out, ".rela.text", SHT_RELA, SHF_INFO_LINK, &rela, 8);
+    section(&mut out, ".symtab", SHT_SYMTAB, 0, &symtab, 8);
+    section(&mut out, ".strtab", SHT_STRTAB, 0, &strtab.bytes, 1);
+    // 自分自身の名前も入れてから書き出す
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    let note_name = shstrtab.add(".note.GNU-stack");
+    let shstrtab_offset = out.len();
+    out.extend_from_slice(&shstrtab.bytes);
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        offset: shstrtab_offset,
+        size: shstrtab.bytes.len(),
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    // スタックを実行可能にしなくてよいことをリンカに伝える
+    headers.push(SectionHeader {
+        name: note_name,
+        kind: SHT_PROGBITS,
+        flags: 0,
+        offset: out.len(),
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    let rela_text = &mut headers[RELA_TEXT as usize - 1];
+    rela_text.link = SYMTAB as u32;
+    rela_text.info = TEXT as u32;
+    rela_text.entry_size = RELA_SIZE as u64;
+    let symtab = &mut headers[SYMTAB as usize - 1];
+    symtab.link = STRTAB as u32;
+    symtab.info = first_global as u32;
+    symtab.entry_size = SYM_SIZE as u64;
+
+    out.resize(out.len().next_multiple_of(8), 0);
+    let section_headers = out.len();
+    out.extend_from_slice(&[0; SHDR_SIZE]);
+    for header in &headers {
+        out.extend_from_slice(&header.name.to_le_bytes());
+        out.extend_from_slice(&header.kind.to_le_bytes());
+        out.extend_from_slice(&header.flags.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&(header.offset as u64).to_le_bytes());
+        out.extend_from_slice(&(header.size as u64).to_le_bytes());
+        out.extend_from_slice(&header.link.to_le_bytes());
+        out.extend_from_slice(&header.info.to_le_bytes());
+        out.extend_from_slice(&header.align.to_le_bytes());
+        out.extend_from_slice(&header.entry_size.to_le_bytes());
+    }
+
+    out[..EHDR_SIZE].copy_from_slice(&file_header(
+        ET_REL,
+        0,
+        0,
+        section_headers,
+        SECTION_COUNT,
+        SHSTRTAB,
+    ));
+    out
+}
+
+/// 静的にリンクした ELF64 の実行ファイルを作り、`entry` から実行を始めるようにする。
+/// 参照しているシンボルは全て `object` の中に無ければならない
+pub fn write_executable(object: &Object, entry: &str) -> Result<Vec<u8>, String> {
+    // コード、読み取り専用のデータ、書き込めるデータを権限ごとに別のページに置く。
+    // コードのセグメントはファイルの先頭から読み込み、ヘッダも一緒に載せる
+    let segments: Vec<(&MachineCode, u32)> = [
+        (&object.text, PF_R | PF_X),
+        (&object.rodata, PF_R),
+        (&object.data, PF_R | PF_W),
+    ]
+    .into_iter()
+    .filter(|(code, flags)| *flags & PF_X != 0 || !code.bytes.is_empty())
+    .collect();
+    let headers_size = EHDR_SIZE + PHDR_SIZE * (segments.len() + 1);
+    let mut offsets = Vec::new();
+    let mut addresses = HashMap::new();
+    let mut offset = headers_size;
+    for (code, _) in &segments {
+        offsets.push(offset);
+        for (name, position) in &code.symbols {
+            let address = BASE_ADDRESS + (offset + position) as u64;
+            if addresses.insert(name.as_str(), address).is_some() {
+                return Err(format!("error: duplicate symbol `{}`", name));
+            }
+        }
+        offset = (offset + code.bytes.len()).next_multiple_of(PAGE_SIZE);
+    }
+    let address = |symbol: &str| {
+        addresses
+            .get(symbol)
+            .copied()
+            .ok_or_else(|| format!("error: undefined symbol `{}`", symbol))
+    };
+
+    let mut text = object.text.bytes.clone();
+    let text_address = BASE_ADDRESS + headers_size as u64;
+    for relocation in &object.text.relocations {
+        let place = text_address + relocation.offset as u64;
+        let value = address(&relocation.symbol)? as i64 + relocation.addend - place as i64;
+        let value = i32::try_from(value).map_err(|_| {
+            format!(
+                "error: `{}` is too far to be referenced with a 32-bit offset",
+                relocation.symbol
+            )
+        })?;
+        text[relocation.offset..relocation.offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    let entry = address(entry)?;
+
+    let mut out = vec![0; headers_size];
+    let mut program_headers = Vec::new();
+    for ((code, flags), offset) in segments.iter().zip(offsets) {
+        out.resize(offset, 0);
+        out.extend_from_slice(if *flags & PF_X != 0 {
+            &text
+        } else {
+            &code.bytes
+        });
+        let start = if *flags & PF_X != 0 { 0 } else { offset };
+        let size = (out.len() - start) as u64;
+        program_headers.push((PT_LOAD, *flags, start as u64, size, PAGE_SIZE as u64));
+    }
+    // スタックを実行可能にしない
+    program_headers.push((PT_GNU_STACK, PF_R | PF_W, 0, 0, 16));
+
+    let mut headers = file_header(ET_EXEC, entry, program_headers.len() as u16, 0, 0, 0);
+    for (kind, flags, offset, size, align) in program_headers {
+        let address = if kind == PT_LOAD {
+            BASE_ADDRESS + offset
+        } else {
+            0
+        };
+        headers.extend_from_slice(&kind.to_le_bytes());
+        headers.extend_from_slice(&flags.to_le_bytes());
+        headers.extend_from_slice(&offset.to_le_bytes());
+        headers.extend_from_slice(&address.to_le_bytes()); // p_vaddr
+        headers.extend_from_slice(&address.to_le_bytes()); // p_paddr
+        headers.extend_from_slice(&size.to_le_bytes()); // p_filesz
+        headers.extend_from_slice(&size.to_le_bytes()); // p_memsz
+        headers.extend_from_slice(&align.to_le_bytes());
+    }
+    out[..headers_size].copy_from_slice(&headers);
+    Ok(out)
+}
+
+/// ELF ヘッダ。プログラムヘッダがあれば、この直後に置く
+fn file_header(
+    kind: u16,
+    entry: u64,
+    program_headers: u16,
+    section_headers: usize,
+    section_count: u16,
+    shstrndx: u16,
+) -> Vec<u8> {
+    let mut ehdr = Vec::with_capacity(EHDR_SIZE);
+    // 64 ビット、リトルエンディアン、バージョン 1
+    ehdr.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    ehdr.extend_from_slice(&[0; 8]);
+    ehdr.extend_from_slice(&kind.to_le_bytes());
+    ehdr.extend_from_slice(&EM_X86_64.to_le_bytes());
+    ehdr.extend_from_slice(&1u32.to_le_bytes());
+    ehdr.extend_from_slice(&entry.to_le_bytes());
+    let phoff = if program_headers > 0 { EHDR_SIZE } else { 0 };
+    ehdr.extend_from_slice(&(phoff as u64).to_le_bytes());
+    ehdr.extend_from_slice(&(section_headers as u64).to_le_bytes());
+    ehdr.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    ehdr.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    ehdr.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    ehdr.extend_from_slice(&program_headers.to_le_bytes());
+    ehdr.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+    ehdr.extend_from_slice(&section_count.to_le_bytes());
+    ehdr.extend_from_slice(&shstrndx.to_le_bytes());
+    ehdr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::encoder::Relocation;
+
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn name_at(bytes: &[u8], offset: usize) -> String {
+        let end = bytes[offset..].iter().position(|&b| b == 0).unwrap();
+        String::from_utf8(bytes[offset..offset + end].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_write_object() {
+        let object = Object {
+            text: MachineCode {
+                bytes: vec![0xE8, 0, 0, 0, 0, 0xC3, 0xC3],
+                symbols: vec![("main".to_string(), 0), ("f".to_string(), 6)],
+                relocations: vec![Relocation {
+                    offset: 1,
+                    symbol: "puts".to_string(),
+                    addend: -4,
+                }],
+            },
+            data: MachineCode::default(),
+            rodata: MachineCode {
+                bytes: b"hi".to_vec(),
+                symbols: vec![("str.0".to_string(), 0)],
+                relocations: vec![],
+            },
+            globals: vec!["main".to_string()],
+        };
+        let elf = write_object(&object);
+        assert_eq!(elf[..6], [0x7F, b'E', b'L', b'F', 2, 1]);
+        assert_eq!(read_u16(&elf, 16), ET_REL);
+        assert_eq!(read_u16(&elf, 18), EM_X86_64);
+        assert_eq!(read_u16(&elf, 60), SECTION_COUNT);
+
+        let shoff = read_u64(&elf, 40) as usize;
+        let header = |index: u16| shoff + SHDR_SIZE * index as usize;
+        let shstrtab = read_u64(&elf, header(SHSTRTAB) + 24) as usize;
+        let names: Vec<String> = (1..SECTION_COUNT)
+            .map(|index| name_at(&elf, shstrtab + read_u32(&elf, header(index)) as usize))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ".text",
+                ".data",
+                ".rodata",
+                ".rela.text",
+                ".symtab",
+                ".strtab",
+                ".shstrtab",
+                ".note.GNU-stack"
+            ]
+        );
+        let text = read_u64(&elf, header(TEXT) + 24) as usize;
+        assert_eq!(elf[text..text + 7], object.text.bytes);
+
+        // ローカルな `f` と `str.0` の後に、グローバルな `main` と外部の `puts` が並ぶ
+        let symtab = read_u64(&elf, header(SYMTAB) + 24) as usize;
+        let strtab = read_u64(&elf, header(STRTAB) + 24) as usize;
+        assert_eq!(read_u32(&elf, header(SYMTAB) + 44), 3);
+        let symbols: Vec<(String, u8, u16, u64)> = (1..5)
+            .map(|index| {
+                let symbol = symtab + SYM_SIZE * index;
+                (
+                    name_at(&elf, strtab + read_u32(&elf, symbol) as usize),
+                    elf[symbol + 4],
+                    read_u16(&elf, symbol + 6),
+                    read_u64(&elf, symbol + 8),
+                )
+            })
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("f".to_string(), STB_LOCAL << 4 | STT_FUNC, TEXT, 6),
+                ("str.0".to_string(), STB_LOCAL << 4 | STT_OBJECT, RODATA, 0),
+                ("main".to_string(), STB_GLOBAL << 4 | STT_FUNC, TEXT, 0),
+                ("puts".to_string(), STB_GLOBAL << 4 | STT_NOTYPE, 0, 0),
+            ]
+        );
+
+        let rela = read_u64(&elf, header(RELA_TEXT) + 24) as usize;
+        assert_eq!(read_u64(&elf, rela), 1);
+        assert_eq!(read_u64(&elf, rela + 8), 4 << 32 | R_X86_64_PLT32 as u64);
+        assert_eq!(read_u64(&elf, rela + 16) as i64, -4);
+    }
+
+    #[test]
+    fn test_write_executable() {
+        let mut object = Object {
+            text: MachineCode {
+                // _start: call main / main: lea rax, [rel str.0]; ret
+                bytes: vec![0xE8, 0, 0, 0, 0, 0x48, 0x8D, 0x05, 0, 0, 0, 0, 0xC3],
+                symbols: vec![("_start".to_string(), 0), ("main".to_string(), 5)],
+                relocations: vec![
+                    Relocation {
+                        offset: 1,
+                        symbol: "main".to_string(),
+                        addend: -4,
+                    },
+                    Relocation {
+                        offset: 8,
+                        symbol: "str.0".to_string(),
+                        addend: -4,
+                    },
+                ],
+            },
+            data: MachineCode::default(),
+            rodata: MachineCode {
+                bytes: b"hi".to_vec(),
+                symbols: vec![("str.0".to_string(), 0)],
+                relocations: vec![],
+            },
+            globals: vec![],
+        };
+        let elf = write_executable(&object, "_start").unwrap();
+        assert_eq!(read_u16(&elf, 16), ET_EXEC);
+        // 空の `.data` のセグメントは作らない
+        assert_eq!(read_u16(&elf, 56), 3);
+        let program_header = |index: usize| read_u64(&elf, 32) as usize + PHDR_SIZE * index;
+        let (text, rodata) = (program_header(0), program_header(1));
+        assert_eq!(read_u32(&elf, text), PT_LOAD);
+        assert_eq!(read_u32(&elf, text + 4), PF_R | PF_X);
+        assert_eq!(read_u64(&elf, text + 8), 0);
+        assert_eq!(read_u64(&elf, text + 16), BASE_ADDRESS);
+        assert_eq!(read_u32(&elf, rodata + 4), PF_R);
+        assert_eq!(read_u64(&elf, rodata + 16) % PAGE_SIZE as u64, 0);
+        assert_eq!(read_u32(&elf, program_header(2)), PT_GNU_STACK);
+
+        // コードはヘッダの直後に置き、先頭の `_start` から実行する
+        let code = EHDR_SIZE + PHDR_SIZE * 3;
+        let entry = read_u64(&elf, 24);
+        assert_eq!(entry, BASE_ADDRESS + code as u64);
+        assert_eq!(elf[code..code + 5], [0xE8, 0, 0, 0, 0]);
+        let rodata_address = read_u64(&elf, rodata + 16);
+        let rel = read_u32(&elf, code + 8) as i32 as i64;
+        assert_eq!(entry as i64 + 12 + rel, rodata_address as i64);
+        let rodata_offset = read_u64(&elf, rodata + 8) as usize;
+        assert_eq!(elf[rodata_offset..], *b"hi");
+
+        object.text.relocations[0].symbol = "puts".to_string();
+        assert_eq!(
+            write_executable(&object, "_start"),
+            Err("error: undefined symbol `puts`".to_string())
+        );
+        object.text.symbols.push(("_start".to_string(), 12));
+        assert_eq!(
+            write_executable(&object, "_start"),
+            Err("error: duplicate symbol `_start`".to_string())
+        );
+    }
+}
@@ -0,0 +1,862 @@
+use std::collections::HashMap;
+
+use super::instruction::{Cond, Instruction, Operand};
+use super::rst::{GENERAL_RSTS, Rst, Width};
+
+/// 機械語にした関数やデータの並び。1つのセクションの中身になる
+#[derive(Debug, Default, PartialEq)]
+pub struct MachineCode {
+    pub bytes: Vec<u8>,
+    /// 関数やデータのラベルと、`bytes` の中での位置
+    pub symbols: Vec<(String, usize)>,
+    /// この中では位置の決まらないシンボルへの参照
+    pub relocations: Vec<Relocation>,
+}
+
+/// アセンブルしたプログラム。オブジェクトファイルや実行ファイルの中身になる
+#[derive(Debug)]
+pub struct Object {
+    pub text: MachineCode,
+    pub data: MachineCode,
+    pub rodata: MachineCode,
+    /// `global` で宣言した、他のオブジェクトファイルから見えるシンボル
+    pub globals: Vec<String>,
+}
+
+/// `rip` からの相対アドレスで、後から 4 バイトを埋める場所。
+/// 埋める値は `symbol` のアドレス + `addend` - 埋める場所のアドレス
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relocation {
+    pub offset: usize,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// ModRM バイトの reg に入れるもの
+#[derive(Clone, Copy)]
+enum Reg {
+    Rst(u8),
+    /// 即値を取る命令などで、オペコードの続きとして使う番号
+    Digit(u8),
+}
+
+/// ModRM バイトの r/m で指定するオペランド
+enum Rm {
+    Reg(u8),
+    /// `[base + disp]`。`base` が無ければ `[rip + symbol + disp]`
+    Mem {
+        base: Option<u8>,
+        symbol: Option<String>,
+        disp: i32,
+    },
+}
+
+/// `Instruction` を1つずつ機械語にする。
+/// ラベルへのジャンプは全て 4 バイトの相対アドレスで書き、`finish` でまとめて埋める
+pub struct Encoder<'a> {
+    code: MachineCode,
+    /// `equ` で定義した定数。即値に書かれたシンボルの値
+    constants: &'a HashMap<String, i64>,
+    /// 関数とラベルの位置。`.` で始まるラベルは関数名を前に付けて区別する
+    labels: HashMap<String, usize>,
+    /// 埋める前の参照。ラベルが見つからなければ再配置として残る
+    references: Vec<Relocation>,
+    fn_label: String,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(constants: &'a HashMap<String, i64>) -> Self {
+        Encoder {
+            code: MachineCode::default(),
+            constants,
+            labels: HashMap::new(),
+            references: Vec::new(),
+            fn_label: String::new(),
+        }
+    }
+
+    /// 関数の先頭。ここから次の関数までのローカルラベルは、この関数のものになる
+    pub fn begin_fn(&mut self, label: &str) {
+        self.fn_label = label.to_string();
+        self.labels.insert(label.to_string(), self.code.bytes.len());
+        self.code
+            .symbols
+            .push((label.to_string(), self.code.bytes.len()));
+    }
+
+    /// ラベルの参照を埋め、残った参照を再配置にする
+    pub fn finish(mut self) -> MachineCode {
+        for reference in std::mem::take(&mut self.references) {
+            match self.labels.get(&reference.symbol) {
+                Some(&target) => {
+                    let value = target as i64 + reference.addend - reference.offset as i64;
+                    self.code.bytes[reference.offset..reference.offset + 4]
+                        .copy_from_slice(&(value as i32).to_le_bytes());
+                }
+                None => self.code.relocations.push(reference),
+            }
+        }
+        self.code
+    }
+
+    pub fn encode(&mut self, instr: &Instruction) {
+        match instr {
+            Instruction::RET => self.emit(&[0xC3]),
+            Instruction::CALL { func } => {
+                self.emit(&[0xE8]);
+                self.rel32(func.clone());
+            }
+            Instruction::MOVE { dest, src } => self.mov(dest, src, Width::B64),
+            Instruction::ADD { dest, src, width } => self.alu(0, dest, src, *width),
+            Instruction::OR { dest, src, width } => self.alu(1, dest, src, *width),
+            Instruction::AND { dest, src, width } => self.alu(4, dest, src, *width),
+            Instruction::SUB { dest, src, width } => self.alu(5, dest, src, *width),
+            Instruction::XOR { dest, src, width } => self.alu(6, dest, src, *width),
+            Instruction::CMP { src1, src2, width } => self.alu(7, src1, src2, *width),
+            Instruction::IMUL { dest, src, width } => {
+                let reg = self.reg(dest);
+                match src {
+                    Operand::Imm(imm) => {
+                        let value = self.imm(imm);
+                        let rm = Rm::Reg(reg);
+                        match i8::try_from(value) {
+                            Ok(value) => self.modrm(
+                                None,
+                                *width,
+                                &[0x6B],
+                                Reg::Rst(reg),
+                                &rm,
+                                &[value as u8],
+                            ),
+                            Err(_) => {
+                                let imm = imm_bytes(value, *width);
+                                self.modrm(None, *width, &[0x69], Reg::Rst(reg), &rm, &imm)
+                            }
+                        }
+                    }
+                    _ => {
+                        let rm = self.rm(src);
+                        self.modrm(None, *width, &[0x0F, 0xAF], Reg::Rst(reg), &rm, &[])
+                    }
+                }
+            }
+            Instruction::NOT { dest, width } => self.unary(2, dest, *width),
+            Instruction::NEG { dest, width } => self.unary(3, dest, *width),
+            Instruction::MUL { src, width } => self.unary(4, src, *width),
+            Instruction::DIV { src, width } => self.unary(6, src, *width),
+            Instruction::IDIV { src, width } => self.unary(7, src, *width),
+            Instruction::CQO => self.emit(&[0x48, 0x99]),
+            Instruction::CDQ => self.emit(&[0x99]),
+            Instruction::SHL { dest, src, width } => self.shift(4, dest, src, *width),
+            Instruction::SHR { dest, src, width } => self.shift(5, dest, src, *width),
+            Instruction::SAR { dest, src, width } => self.shift(7, dest, src, *width),
+            Instruction::MOVSX { dest, src, width } => {
+                let opcode: &[u8] = match width {
+                    Width::B8 => &[0x0F, 0xBE],
+                    Width::B16 => &[0x0F, 0xBF],
+                    Width::B32 => &[0x63],
+                    Width::B64 => &[0x8B],
+                };
+                let (reg, rm) = (self.reg(dest), self.rm(src));
+                self.modrm(None, Width::B64, opcode, Reg::Rst(reg), &rm, &[]);
+            }
+            // 32 ビットのレジスタへの書き込みは上位 32 ビットを 0 にする
+            Instruction::MOVZX {
+                dest,
+                src,
+                width: Width::B32,
+            } => self.mov(dest, src, Width::B32),
+            Instruction::MOVZX { dest, src, width } => {
+                let opcode: &[u8] = match width {
+                    Width::B8 => &[0x0F, 0xB6],
+                    Width::B16 => &[0x0F, 0xB7],
+                    _ => &[0x8B],
+                };
+                let (reg, rm) = (self.reg(dest), self.rm(src));
+                self.modrm(None, Width::B64, opcode, Reg::Rst(reg), &rm, &[]);
+            }
+            Instruction::STORE { dest, src, width } => self.mov(dest, src, *width),
+            Instruction::PUSH { src } => match src {
+                Operand::Rst(rst) => self.short_reg(0x50, rst.number()),
+                Operand::Imm(imm) => match i8::try_from(self.imm(imm)) {
+                    Ok(value) => self.emit(&[0x6A, value as u8]),
+                    Err(_) => {
+                        let imm = imm_bytes(self.imm(imm), Width::B32);
+                        self.emit(&[0x68]);
+                        self.emit(&imm);
+                    }
+                },
+                _ => {
+                    let rm = self.rm(src);
+                    self.modrm(None, Width::B32, &[0xFF], Reg::Digit(6), &rm, &[]);
+                }
+            },
+            Instruction::POP { dest } => match dest {
+                Operand::Rst(rst) => self.short_reg(0x58, rst.number()),
+                _ => {
+                    let rm = self.rm(dest);
+                    self.modrm(None, Width::B32, &[0x8F], Reg::Digit(0), &rm, &[]);
+                }
+            },
+            Instruction::LOAD { dest, addr } => {
+                let (reg, rm) = (self.reg(dest), self.address(addr));
+                self.modrm(None, Width::B64, &[0x8D], Reg::Rst(reg), &rm, &[]);
+            }
+            Instruction::SYSCALL => self.emit(&[0x0F, 0x05]),
+            Instruction::JMP { label } => {
+                self.emit(&[0xE9]);
+                self.rel32(self.qualify(label));
+            }
+            Instruction::JCC { cond, label } => {
+                self.emit(&[0x0F, 0x80 | cond_code(*cond)]);
+                self.rel32(self.qualify(label));
+            }
+            Instruction::LABEL { name } => {
+                self.labels
+                    .insert(self.qualify(name), self.code.bytes.len());
+            }
+            Instruction::MOVSD { dest, src } => match dest {
+                Operand::Rst(rst) => self.sse(0xF2, Width::B32, 0x10, rst.number(), src),
+                _ => {
+                    let reg = self.reg(src);
+                    self.sse(0xF2, Width::B32, 0x11, reg, dest)
+                }
+            },
+            Instruction::ADDSD { dest, src } => {
+                self.sse(0xF2, Width::B32, 0x58, self.reg(dest), src)
+            }
+            Instruction::MULSD { dest, src } => {
+                self.sse(0xF2, Width::B32, 0x59, self.reg(dest), src)
+            }
+            Instruction::SUBSD { dest, src } => {
+                self.sse(0xF2, Width::B32, 0x5C, self.reg(dest), src)
+            }
+            Instruction::DIVSD { dest, src } => {
+                self.sse(0xF2, Width::B32, 0x5E, self.reg(dest), src)
+            }
+            Instruction::UCOMISD { src1, src2 } => {
+                self.sse(0x66, Width::B32, 0x2E, self.reg(src1), src2)
+            }
+            Instruction::CVTSI2SD { dest, src } => {
+                self.sse(0xF2, Width::B64, 0x2A, self.reg(dest), src)
+            }
+            Instruction::CVTTSD2SI { dest, src } => {
+                self.sse(0xF2, Width::B64, 0x2C, self.reg(dest), src)
+            }
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.bytes.extend_from_slice(bytes);
+    }
+
+    /// `symbol` への相対アドレスを 4 バイトで書く。相対アドレスは次の命令の先頭から数える
+    fn rel32(&mut self, symbol: String) {
+        self.reference(symbol, -4);
+        self.emit(&[0; 4]);
+    }
+
+    fn reference(&mut self, symbol: String, addend: i64) {
+        self.references.push(Relocation {
+            offset: self.code.bytes.len(),
+            symbol,
+            addend,
+        });
+    }
+
+    /// nasm と同じく、`.` で始まるラベルは直前の関数のものとみなす
+    fn qualify(&self, label: &str) -> String {
+        if label.starts_with('.') {
+            format!("{}{}", self.fn_label, label)
+        } else {
+            label.to_string()
+        }
+    }
+
+    /// プレフィックス、オペコード、ModRM、SIB、ディスプレースメント、即値の順に書く。
+    /// `width` が `B16` なら 0x66 を、`B64` なら REX.W を付ける。SSE の命令は `B32` を渡す
+    fn modrm(
+        &mut self,
+        prefix: Option<u8>,
+        width: Width,
+        opcode: &[u8],
+        reg: Reg,
+        rm: &Rm,
+        imm: &[u8],
+    ) {
+        let (reg, reg_is_rst) = match reg {
+            Reg::Rst(number) => (number, true),
+            Reg::Digit(digit) => (digit, false),
+        };
+        if width == Width::B16 {
+            self.emit(&[0x66]);
+        }
+        if let Some(prefix) = prefix {
+            self.emit(&[prefix]);
+        }
+        let (rm_number, needs_sib) = match rm {
+            Rm::Reg(number) => (*number, false),
+            Rm::Mem {
+                base: Some(base), ..
+            } => (*base, base & 7 == 4),
+            Rm::Mem { base: None, .. } => (0b101, false),
+        };
+        let mut rex = 0x40;
+        if width == Width::B64 {
+            rex |= 0x08;
+        }
+        if reg >= 8 {
+            rex |= 0x04;
+        }
+        if rm_number >= 8 && !matches!(rm, Rm::Mem { base: None, .. }) {
+            rex |= 0x01;
+        }
+        // REX があると 4 から 7 の 8 ビットレジスタは `ah` などではなく `spl` から `dil` になる
+        let byte_rst = |number: u8| (4..8).contains(&number);
+        let needs_rex = width == Width::B8
+            && ((reg_is_rst && byte_rst(reg))
+                || matches!(rm, Rm::Reg(number) if byte_rst(*number)));
+        if rex != 0x40 || needs_rex {
+            self.emit(&[rex]);
+        }
+        self.emit(opcode);
+        let reg_bits = (reg & 7) << 3;
+        match rm {
+            Rm::Reg(number) => self.emit(&[0b11_000_000 | reg_bits | (number & 7)]),
+            Rm::Mem {
+                base: None,
+                symbol,
+                disp,
+            } => {
+                self.emit(&[reg_bits | 0b101]);
+                // `rip` は即値まで読んだ次の命令の先頭を指す
+                let addend = *disp as i64 - 4 - imm.len() as i64;
+                match symbol {
+                    Some(symbol) => {
+                        self.reference(symbol.clone(), addend);
+                        self.emit(&[0; 4]);
+                    }
+                    None => self.emit(&disp.to_le_bytes()),
+                }
+            }
+            Rm::Mem {
+                base: Some(base),
+                disp,
+                ..
+            } => {
+                let sib: &[u8] = if needs_sib { &[0x24] } else { &[] };
+                // `rbp` と `r13` は mod が 0 だと `rip` 相対の意味になるので、0 でも 1 バイトで書く
+                if *disp == 0 && base & 7 != 5 {
+                    self.emit(&[reg_bits | (base & 7)]);
+                    self.emit(sib);
+                } else if let Ok(disp) = i8::try_from(*disp) {
+                    self.emit(&[0b01_000_000 | reg_bits | (base & 7)]);
+                    self.emit(sib);
+                    self.emit(&[disp as u8]);
+                } else {
+                    self.emit(&[0b10_000_000 | reg_bits | (base & 7)]);
+                    self.emit(sib);
+                    self.emit(&disp.to_le_bytes());
+                }
+            }
+        }
+        self.emit(imm);
+    }
+
+    /// `push rax` のように、オペコードの下位 3 ビットでレジスタを指定する命令
+    fn short_reg(&mut self, opcode: u8, number: u8) {
+        if number >= 8 {
+            self.emit(&[0x41]);
+        }
+        self.emit(&[opcode | (number & 7)]);
+    }
+
+    fn mov(&mut self, dest: &Operand, src: &Operand, width: Width) {
+        let byte = width == Width::B8;
+        match (dest, src) {
+            (_, Operand::Rst(rst)) => {
+                let rm = self.rm(dest);
+                let opcode = if byte { 0x88 } else { 0x89 };
+                self.modrm(None, width, &[opcode], Reg::Rst(rst.number()), &rm, &[]);
+            }
+            (Operand::Rst(rst), _) if !matches!(src, Operand::Imm(_)) => {
+                let rm = self.rm(src);
+                let opcode = if byte { 0x8A } else { 0x8B };
+                self.modrm(None, width, &[opcode], Reg::Rst(rst.number()), &rm, &[]);
+            }
+            (_, Operand::Imm(imm)) => {
+                let value = self.imm(imm);
+                match dest {
+                    // 32 ビットに収まる正の数は、上位を 0 にする 32 ビットの `mov` で書く
+                    Operand::Rst(rst) if width == Width::B64 && u32::try_from(value).is_ok() => {
+                        self.short_reg(0xB8, rst.number());
+                        self.emit(&(value as u32).to_le_bytes());
+                    }
+                    Operand::Rst(rst) if width == Width::B64 && i32::try_from(value).is_err() => {
+                        self.emit(&[0x48 | (rst.number() >> 3)]);
+                        self.emit(&[0xB8 | (rst.number() & 7)]);
+                        self.emit(&value.to_le_bytes());
+                    }
+                    _ => {
+                        let rm = self.rm(dest);
+                        let opcode = if byte { 0xC6 } else { 0xC7 };
+                        self.modrm(
+                            None,
+                            width,
+                            &[opcode],
+                            Reg::Digit(0),
+                            &rm,
+                            &imm_bytes(value, width),
+                        );
+                    }
+                }
+            }
+            _ => unreachable!("cannot move `{}` to `{}`", src, dest),
+        }
+    }
+
+    /// `add` や `cmp` など、オペコードの並びが揃っている 2 オペランドの演算。`digit` は即値の時の ModRM の reg
+    fn alu(&mut self, digit: u8, dest: &Operand, src: &Operand, width: Width) {
+        let byte = width == Width::B8;
+        let base = digit << 3;
+        match (dest, src) {
+            (_, Operand::Rst(rst)) => {
+                let rm = self.rm(dest);
+                let opcode = base + if byte { 0 } else { 1 };
+                self.modrm(None, width, &[opcode], Reg::Rst(rst.number()), &rm, &[]);
+            }
+            (Operand::Rst(rst), Operand::Stack(_) | Operand::Mem(_)) => {
+                let rm = self.rm(src);
+                let opcode = base + if byte { 2 } else { 3 };
+                self.modrm(None, width, &[opcode], Reg::Rst(rst.number()), &rm, &[]);
+            }
+            (_, Operand::Imm(imm)) => {
+                let value = self.imm(imm);
+                let rm = self.rm(dest);
+                match i8::try_from(value) {
+                    _ if byte => {
+                        self.modrm(None, width, &[0x80], Reg::Digit(digit), &rm, &[value as u8])
+                    }
+                    Ok(value) => {
+                        self.modrm(None, width, &[0x83], Reg::Digit(digit), &rm, &[value as u8])
+                    }
+                    Err(_) => {
+                        let imm = imm_bytes(value, width);
+                        self.modrm(None, width, &[0x81], Reg::Digit(digit), &rm, &imm)
+                    }
+                }
+            }
+            _ => unreachable!("invalid operands `{}` and `{}`", dest, src),
+        }
+    }
+
+    /// `neg` や `idiv` など、オペランドが1つの演算
+    fn unary(&mut self, digit: u8, operand: &Operand, width: Width) {
+        let rm = self.rm(operand);
+        let opcode = if width == Width::B8 { 0xF6 } else { 0xF7 };
+        self.modrm(None, width, &[opcode], Reg::Digit(digit), &rm, &[]);
+    }
+
+    /// シフト量は即値か `cl`
+    fn shift(&mut self, digit: u8, dest: &Operand, src: &Operand, width: Width) {
+        let rm = self.rm(dest);
+        let byte = width == Width::B8;
+        match src {
+            Operand::Imm(imm) => {
+                let count = self.imm(imm) as u8;
+                let opcode = if byte { 0xC0 } else { 0xC1 };
+                self.modrm(None, width, &[opcode], Reg::Digit(digit), &rm, &[count]);
+            }
+            _ => {
+                let opcode = if byte { 0xD2 } else { 0xD3 };
+                self.modrm(None, width, &[opcode], Reg::Digit(digit), &rm, &[]);
+            }
+        }
+    }
+
+    /// `prefix 0F opcode` の形の SSE の命令。`reg` は ModRM の reg に入るレジスタ
+    fn sse(&mut self, prefix: u8, width: Width, opcode: u8, reg: u8, src: &Operand) {
+        let rm = self.rm(src);
+        self.modrm(
+            Some(prefix),
+            width,
+            &[0x0F, opcode],
+            Reg::Rst(reg),
+            &rm,
+            &[],
+        );
+    }
+
+    fn reg(&self, operand: &Operand) -> u8 {
+        match operand {
+            Operand::Rst(rst) => rst.number(),
+            _ => unreachable!("`{}` is not a register", operand),
+        }
+    }
+
+    fn rm(&self, operand: &Operand) -> Rm {
+        match operand {
+            Operand::Rst(rst) => Rm::Reg(rst.number()),
+            Operand::Stack(slot) => Rm::Mem {
+                base: Some(Rst::RBP.number()),
+                symbol: None,
+                disp: slot.offset() as i32,
+            },
+            Operand::Mem(addr) => self.address(addr),
+            _ => unreachable!("`{}` must be allocated before encoding", operand),
+        }
+    }
+
+    /// `rsi`、`rax - 8`、`main.f64_0` のように書いたアドレス。レジスタ名でなければラベルとみなす
+    fn address(&self, addr: &str) -> Rm {
+        let (name, disp) = match addr.split_once(" + ") {
+            Some((name, disp)) => (name, disp.parse::<i32>().unwrap()),
+            None => match addr.split_once(" - ") {
+                Some((name, disp)) => (name, -disp.parse::<i32>().unwrap()),
+                None => (addr, 0),
+            },
+        };
+        match GENERAL_RSTS.iter().find(|rst| rst.as_str() == name) {
+            Some(rst) => Rm::Mem {
+                base: Some(rst.number()),
+                symbol: None,
+                disp,
+            },
+            None => Rm::Mem {
+                base: None,
+                symbol: Some(self.qualify(name)),
+                disp,
+            },
+        }
+    }
+
+    /// 数か、`equ` で定義した定数
+    fn imm(&self, imm: &str) -> i64 {
+        parse_number(imm)
+            .or_else(|| self.constants.get(imm).copied())
+            .unwrap_or_else(|| unreachable!("undefined constant `{}`", imm))
+    }
+}
+
+/// 10 進数か `0x` で始まる 16 進数、`'0'` のような文字で書いた数
+pub fn parse_number(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok().map(|value| value as i64);
+    }
+    if let Some(c) = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return c.chars().next().map(|c| c as i64);
+    }
+    text.parse::<i64>().ok()
+}
+
+/// 即値を `width` の大きさで書く。64 ビットの演算の即値は 32 ビットを符号拡張して使う
+fn imm_bytes(value: i64, width: Width) -> Vec<u8> {
+    match width {
+        Width::B8 => vec![value as u8],
+        Width::B16 => (value as u16).to_le_bytes().to_vec(),
+        Width::B32 | Width::B64 => (value as u32).to_le_bytes().to_vec(),
+    }
+}
+
+/// 条件付きジャンプのオペコードの下位 4 ビット
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::O => 0x0,
+        Cond::NO => 0x1,
+        Cond::B => 0x2,
+        Cond::AE => 0x3,
+        Cond::E => 0x4,
+        Cond::NE => 0x5,
+        Cond::BE => 0x6,
+        Cond::A => 0x7,
+        Cond::P => 0xA,
+        Cond::NP => 0xB,
+        Cond::L => 0xC,
+        Cond::GE => 0xD,
+        Cond::LE => 0xE,
+        Cond::G => 0xF,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(instructions: &[Instruction]) -> MachineCode {
+        let constants = HashMap::from([("str.0_len".to_string(), 5)]);
+        let mut encoder = Encoder::new(&constants);
+        encoder.begin_fn("f");
+        for instr in instructions {
+            encoder.encode(instr);
+        }
+        encoder.finish()
+    }
+
+    fn bytes(instr: Instruction) -> Vec<u8> {
+        encode(&[instr]).bytes
+    }
+
+    fn rst(rst: Rst) -> Operand {
+        Operand::Rst(rst)
+    }
+
+    fn imm(imm: &str) -> Operand {
+        Operand::Imm(imm.to_string())
+    }
+
+    fn mem(addr: &str) -> Operand {
+        Operand::Mem(addr.to_string())
+    }
+
+    #[test]
+    fn test_encode_moves() {
+        let mov = |dest, src| bytes(Instruction::MOVE { dest, src });
+        assert_eq!(mov(rst(Rst::RAX), rst(Rst::RBX)), [0x48, 0x89, 0xD8]);
+        assert_eq!(mov(rst(Rst::R12), rst(Rst::RSP)), [0x49, 0x89, 0xE4]);
+        assert_eq!(mov(rst(Rst::RDI), imm("1")), [0xBF, 1, 0, 0, 0]);
+        assert_eq!(mov(rst(Rst::RSI), imm("str.0_len")), [0xBE, 5, 0, 0, 0]);
+        assert_eq!(mov(rst(Rst::RAX), imm("0x2000004")), [0xB8, 4, 0, 0, 2]);
+        assert_eq!(mov(rst(Rst::RDX), imm("'0'")), [0xBA, 0x30, 0, 0, 0]);
+        assert_eq!(
+            mov(rst(Rst::R9), imm("-1")),
+            [0x49, 0xC7, 0xC1, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            mov(rst(Rst::RCX), imm("-9223372036854775808")),
+            [0x48, 0xB9, 0, 0, 0, 0, 0, 0, 0, 0x80]
+        );
+        assert_eq!(mov(rst(Rst::RAX), mem("rbp - 8")), [0x48, 0x8B, 0x45, 0xF8]);
+        // `rsp` と `r12` は SIB、`r13` は 0 のディスプレースメントが要る
+        assert_eq!(mov(mem("rsp"), rst(Rst::R13)), [0x4C, 0x89, 0x2C, 0x24]);
+        assert_eq!(mov(rst(Rst::RSI), mem("r13")), [0x49, 0x8B, 0x75, 0x00]);
+        assert_eq!(
+            mov(mem("r12 + 200"), imm("-5")),
+            [
+                0x49, 0xC7, 0x84, 0x24, 0xC8, 0, 0, 0, 0xFB, 0xFF, 0xFF, 0xFF
+            ]
+        );
+        let store = |dest, src, width| bytes(Instruction::STORE { dest, src, width });
+        assert_eq!(
+            store(mem("rsi"), rst(Rst::RDI), Width::B8),
+            [0x40, 0x88, 0x3E]
+        );
+        assert_eq!(
+            store(mem("rsi + 1"), imm("65"), Width::B8),
+            [0xC6, 0x46, 1, 0x41]
+        );
+        assert_eq!(
+            store(mem("rbp - 4"), imm("-7"), Width::B16),
+            [0x66, 0xC7, 0x45, 0xFC, 0xF9, 0xFF]
+        );
+        let movsx = |dest, src, width| bytes(Instruction::MOVSX { dest, src, width });
+        assert_eq!(
+            movsx(rst(Rst::RAX), rst(Rst::RDI), Width::B8),
+            [0x48, 0x0F, 0xBE, 0xC7]
+        );
+        assert_eq!(
+            movsx(rst(Rst::RAX), rst(Rst::RAX), Width::B32),
+            [0x48, 0x63, 0xC0]
+        );
+        let movzx = |dest, src, width| bytes(Instruction::MOVZX { dest, src, width });
+        assert_eq!(
+            movzx(rst(Rst::RDX), rst(Rst::R14), Width::B16),
+            [0x49, 0x0F, 0xB7, 0xD6]
+        );
+        assert_eq!(
+            movzx(rst(Rst::RAX), rst(Rst::RAX), Width::B32),
+            [0x89, 0xC0]
+        );
+    }
+
+    #[test]
+    fn test_encode_arithmetic() {
+        assert_eq!(
+            bytes(Instruction::ADD {
+                dest: rst(Rst::RAX),
+                src: rst(Rst::R10),
+                width: Width::B32
+            }),
+            [0x44, 0x01, 0xD0]
+        );
+        assert_eq!(
+            bytes(Instruction::SUB {
+                dest: rst(Rst::RSP),
+                src: imm("1024"),
+                width: Width::B64
+            }),
+            [0x48, 0x81, 0xEC, 0, 4, 0, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::AND {
+                dest: rst(Rst::RSI),
+                src: mem("rbp - 300"),
+                width: Width::B64
+            }),
+            [0x48, 0x23, 0xB5, 0xD4, 0xFE, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            bytes(Instruction::OR {
+                dest: rst(Rst::RDI),
+                src: rst(Rst::RSI),
+                width: Width::B8
+            }),
+            [0x40, 0x08, 0xF7]
+        );
+        assert_eq!(
+            bytes(Instruction::CMP {
+                src1: mem("rbp - 8"),
+                src2: imm("128"),
+                width: Width::B8
+            }),
+            [0x80, 0x7D, 0xF8, 0x80]
+        );
+        assert_eq!(
+            bytes(Instruction::IMUL {
+                dest: rst(Rst::RCX),
+                src: imm("1000"),
+                width: Width::B64
+            }),
+            [0x48, 0x69, 0xC9, 0xE8, 0x03, 0, 0]
+        );
+        assert_eq!(
+            bytes(Instruction::IDIV {
+                src: mem("rbp - 8"),
+                width: Width::B32
+            }),
+            [0xF7, 0x7D, 0xF8]
+        );
+        assert_eq!(
+            bytes(Instruction::NOT {
+                dest: rst(Rst::R15),
+                width: Width::B16
+            }),
+            [0x66, 0x41, 0xF7, 0xD7]
+        );
+        assert_eq!(
+            bytes(Instruction::SAR {
+                dest: rst(Rst::RSI),
+                src: rst(Rst::RCX),
+                width: Width::B8
+            }),
+            [0x40, 0xD2, 0xFE]
+        );
+        assert_eq!(
+            bytes(Instruction::SHL {
+                dest: rst(Rst::RAX),
+                src: imm("3"),
+                width: Width::B64
+            }),
+            [0x48, 0xC1, 0xE0, 3]
+        );
+        assert_eq!(bytes(Instruction::CQO), [0x48, 0x99]);
+        assert_eq!(
+            bytes(Instruction::PUSH { src: rst(Rst::R12) }),
+            [0x41, 0x54]
+        );
+        assert_eq!(
+            bytes(Instruction::POP {
+                dest: mem("rsp + 8")
+            }),
+            [0x8F, 0x44, 0x24, 8]
+        );
+    }
+
+    #[test]
+    fn test_encode_floats() {
+        assert_eq!(
+            bytes(Instruction::MOVSD {
+                dest: rst(Rst::XMM0),
+                src: rst(Rst::XMM9)
+            }),
+            [0xF2, 0x41, 0x0F, 0x10, 0xC1]
+        );
+        assert_eq!(
+            bytes(Instruction::MOVSD {
+                dest: mem("rbp - 8"),
+                src: rst(Rst::XMM15)
+            }),
+            [0xF2, 0x44, 0x0F, 0x11, 0x7D, 0xF8]
+        );
+        assert_eq!(
+            bytes(Instruction::UCOMISD {
+                src1: rst(Rst::XMM0),
+                src2: rst(Rst::XMM1)
+            }),
+            [0x66, 0x0F, 0x2E, 0xC1]
+        );
+        assert_eq!(
+            bytes(Instruction::CVTSI2SD {
+                dest: rst(Rst::XMM0),
+                src: rst(Rst::R10)
+            }),
+            [0xF2, 0x49, 0x0F, 0x2A, 0xC2]
+        );
+        assert_eq!(
+            bytes(Instruction::CVTTSD2SI {
+                dest: rst(Rst::RAX),
+                src: rst(Rst::XMM8)
+            }),
+            [0xF2, 0x49, 0x0F, 0x2C, 0xC0]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_relocations() {
+        let code = encode(&[
+            Instruction::LABEL {
+                name: ".loop".to_string(),
+            },
+            Instruction::JCC {
+                cond: Cond::NE,
+                label: ".end".to_string(),
+            },
+            Instruction::JMP {
+                label: ".loop".to_string(),
+            },
+            Instruction::LABEL {
+                name: ".end".to_string(),
+            },
+            Instruction::CALL {
+                func: "f".to_string(),
+            },
+            Instruction::LOAD {
+                dest: rst(Rst::RDI),
+                addr: "str.0".to_string(),
+            },
+            Instruction::MOVE {
+                dest: mem("main.x + 8"),
+                src: imm("1"),
+            },
+        ]);
+        assert_eq!(
+            code.bytes,
+            [
+                0x0F, 0x85, 5, 0, 0, 0, // jne .end
+                0xE9, 0xF5, 0xFF, 0xFF, 0xFF, // jmp .loop
+                0xE8, 0xF0, 0xFF, 0xFF, 0xFF, // call f
+                0x48, 0x8D, 0x3D, 0, 0, 0, 0, // lea rdi, [rip + str.0]
+                0x48, 0xC7, 0x05, 0, 0, 0, 0, 1, 0, 0, 0, // mov qword [rip + main.x + 8], 1
+            ]
+        );
+        assert_eq!(code.symbols, vec![("f".to_string(), 0)]);
+        // 即値の後ろから数えるので、`addend` は即値の分だけ小さくなる
+        assert_eq!(
+            code.relocations,
+            vec![
+                Relocation {
+                    offset: 19,
+                    symbol: "str.0".to_string(),
+                    addend: -4,
+                },
+                Relocation {
+                    offset: 26,
+                    symbol: "main.x".to_string(),
+                    addend: 0,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use super::rst::Rst;
+
+/// `rbp` からのオフセットで表した、スタック上の 8 バイトの置き場所。
+/// 負ならこの関数のフレーム、正なら呼び出し元が積んだ引数
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StackSlot(isize);
+
+impl StackSlot {
+    /// レジスタに入らずスタックで渡された `index` 番目の引数の置き場所。
+    /// 戻りアドレスと退避した `rbp` の上に、前の引数ほど下に積まれている
+    pub fn incoming_argument(index: usize) -> StackSlot {
+        StackSlot(16 + 8 * index as isize)
+    }
+
+    /// `rbp` からのオフセット
+    pub fn offset(&self) -> isize {
+        self.0
+    }
+
+    /// `[]` の中に書くアドレス
+    pub fn address(&self) -> String {
+        if self.0 < 0 {
+            format!("rbp - {}", -self.0)
+        } else {
+            format!("rbp + {}", self.0)
+        }
+    }
+}
+
+impl std::fmt::Display for StackSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "qword [{}]", self.address())
+    }
+}
+
+/// 変数や計算途中の値の置き場所
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Location {
+    Rst(Rst),
+    Stack(StackSlot),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Rst(rst) => write!(f, "{}", rst),
+            Location::Stack(slot) => write!(f, "{}", slot),
+        }
+    }
+}
+
+/// 関数1つ分のスタックフレーム。スロットはレジスタ割り当てでスタックに置くと決めた値ごとに確保する
+#[derive(Debug, Default)]
+pub struct Frame {
+    depth: usize,
+}
+
+impl Frame {
+    pub fn alloc_slot(&mut self) -> StackSlot {
+        self.depth += 8;
+        StackSlot(-(self.depth as isize))
+    }
+
+    /// 退避するレジスタのスロットを値用のスロットの下に置き、フレーム全体の大きさを返す。
+    /// `call` の時点で `rsp` が 16 の倍数になるように、大きさは 16 の倍数に切り上げる
+    pub fn layout(&self, saved_rsts: &[Rst]) -> (Vec<(Rst, StackSlot)>, usize) {
+        let saved = saved_rsts
+            .iter()
+            .enumerate()
+            .map(|(i, rst)| (*rst, StackSlot(-((self.depth + 8 * (i + 1)) as isize))))
+            .collect();
+        let size = self.depth + 8 * saved_rsts.len();
+        (saved, size.next_multiple_of(16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_layout() {
+        let mut frame = Frame::default();
+        frame.alloc_slot();
+        let slot = frame.alloc_slot();
+        assert_eq!(slot.to_string(), "qword [rbp - 16]");
+
+        let (saved, size) = frame.layout(&[Rst::R12]);
+        assert_eq!(saved, vec![(Rst::R12, StackSlot(-24))]);
+        assert_eq!(size, 32);
+        assert_eq!(Frame::default().layout(&[]).1, 0);
+
+        assert_eq!(
+            StackSlot::incoming_argument(1).to_string(),
+            "qword [rbp + 24]"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use super::encoder::parse_number;
+use super::instruction::{Instruction, Operand};
+use super::rst::{GENERAL_RSTS, Width};
+use super::target::Target;
+
+/// nasm 用の宣言を GNU as の疑似命令にする。`AsmCode` の `directives` に入る形だけを扱う
+pub fn directive(directive: &str) -> Option<String> {
+    if let Some(symbol) = directive.strip_prefix("global ") {
+        return Some(format!(".globl {}", symbol));
+    }
+    match directive.split_whitespace().collect::<Vec<_>>().as_slice() {
+        // ラベルのアドレスは全て `rip` からの相対で書く
+        ["default", "rel"] => None,
+        ["section", ".note.GNU-stack", ..] => {
+            Some(".section .note.GNU-stack,\"\",@progbits".to_string())
+        }
+        _ => unreachable!("unknown directive `{}`", directive),
+    }
+}
+
+/// 読み取り専用のデータセクションの宣言
+pub fn rodata_section(target: &dyn Target) -> &'static str {
+    if target.object_format() == "macho64" {
+        ".section __TEXT,__const"
+    } else {
+        ".section .rodata"
+    }
+}
+
+/// バイト列を置く疑似命令。表示できる ASCII 文字は `.ascii` にまとめ、それ以外は `.byte` で書く
+pub fn bytes(bytes: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut quoted = String::new();
+    for &byte in bytes {
+        if (b' '..=b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+            quoted.push(byte as char);
+            continue;
+        }
+        if !quoted.is_empty() {
+            lines.push(format!("    .ascii \"{}\"", std::mem::take(&mut quoted)));
+        }
+        lines.push(format!("    .byte 0x{:02X}", byte));
+    }
+    if !quoted.is_empty() {
+        lines.push(format!("    .ascii \"{}\"", quoted));
+    }
+    lines
+}
+
+/// GNU as の Intel 記法で命令を書く。`.` で始まるローカルラベルには関数名 `fn_label` を付ける
+pub fn instruction(instr: &Instruction, fn_label: &str) -> String {
+    let op = |operand: &Operand, width: Width| self::operand(operand, width, fn_label);
+    let binary = |name: &str, dest: &Operand, src: &Operand, width: Width| {
+        format!("    {} {}, {}", name, op(dest, width), op(src, width))
+    };
+    match instr {
+        Instruction::RET => "    ret".to_string(),
+        Instruction::CALL { func } => format!("    call {}", label(func, fn_label)),
+        Instruction::MOVE { dest, src } => binary("mov", dest, src, Width::B64),
+        Instruction::ADD { dest, src, width } => binary("add", dest, src, *width),
+        Instruction::SUB { dest, src, width } => binary("sub", dest, src, *width),
+        Instruction::IMUL { dest, src, width } => binary("imul", dest, src, *width),
+        Instruction::MUL { src, width } => format!("    mul {}", op(src, *width)),
+        Instruction::CQO => "    cqo".to_string(),
+        Instruction::CDQ => "    cdq".to_string(),
+        Instruction::IDIV { src, width } => format!("    idiv {}", op(src, *width)),
+        Instruction::DIV { src, width } => format!("    div {}", op(src, *width)),
+        Instruction::NEG { dest, width } => format!("    neg {}", op(dest, *width)),
+        Instruction::NOT { dest, width } => format!("    not {}", op(dest, *width)),
+        Instruction::AND { dest, src, width } => binary("and", dest, src, *width),
+        Instruction::OR { dest, src, width } => binary("or", dest, src, *width),
+        Instruction::XOR { dest, src, width } => binary("xor", dest, src, *width),
+        Instruction::SHL { dest, src, width } => shift("shl", dest, src, *width, fn_label),
+        Instruction::SHR { dest, src, width } => shift("shr", dest, src, *width, fn_label),
+        Instruction::SAR { dest, src, width } => shift("sar", dest, src, *width, fn_label),
+        Instruction::MOVSX { dest, src, width } => {
+            let name = if *width == Width::B32 {
+                "movsxd"
+            } else {
+                "movsx"
+            };
+            format!("    {} {}, {}", name, op(dest, Width::B64), op(src, *width))
+        }
+        // 32 ビットのレジスタへの書き込みは上位 32 ビットを 0 にする
+        Instruction::MOVZX {
+            dest,
+            src,
+            width: Width::B32,
+        } => binary("mov", dest, src, Width::B32),
+        Instruction::MOVZX { dest, src, width } => {
+            format!("    movzx {}, {}", op(dest, Width::B64), op(src, *width))
+        }
+        Instruction::STORE { dest, src, width } => binary("mov", dest, src, *width),
+        Instruction::PUSH { src } => format!("    push {}", op(src, Width::B64)),
+        Instruction::POP { dest } => format!("    pop {}", op(dest, Width::B64)),
+        Instruction::LOAD { dest, addr } => format!(
+            "    lea {}, {}",
+            op(dest, Width::B64),
+            address(addr, fn_label)
+        ),
+        Instruction::SYSCALL => "    syscall".to_string(),
+        Instruction::CMP { src1, src2, width } => binary("cmp", src1, src2, *width),
+        Instruction::JMP { label: target } => format!("    jmp {}", label(target, fn_label)),
+        Instruction::JCC {
+            cond,
+            label: target,
+        } => {
+            format!("    j{} {}", cond.as_str(), label(target, fn_label))
+        }
+        Instruction::LABEL { name } => format!("{}:", label(name, fn_label)),
+        Instruction::MOVSD { dest, src } => binary("movsd", dest, src, Width::B64),
+        Instruction::ADDSD { dest, src } => binary("addsd", dest, src, Width::B64),
+        Instruction::SUBSD { dest, src } => binary("subsd", dest, src, Width::B64),
+        Instruction::MULSD { dest, src } => binary("mulsd", dest, src, Width::B64),
+        Instruction::DIVSD { dest, src } => binary("divsd", dest, src, Width::B64),
+        Instruction::UCOMISD { src1, src2 } => binary("ucomisd", src1, src2, Width::B64),
+        Instruction::CVTSI2SD { dest, src } => binary("cvtsi2sd", dest, src, Width::B64),
+        Instruction::CVTTSD2SI { dest, src } => binary("cvttsd2si", dest, src, Width::B64),
+    }
+}
+
+/// シフト量は即値か `cl`
+fn shift(name: &str, dest: &Operand, src: &Operand, width: Width, fn_label: &str) -> String {
+    format!(
+        "    {} {}, {}",
+        name,
+        operand(dest, width, fn_label),
+        operand(src, Width::B8, fn_label)
+    )
+}
+
+/// `width` ビット分を読み書きする時の表現。`eax` や `dword ptr [rbp - 8]` など
+fn operand(operand: &Operand, width: Width, fn_label: &str) -> String {
+    match operand {
+        Operand::Rst(rst) => rst.name(width).to_string(),
+        Operand::Stack(slot) => format!("{} ptr [{}]", width.ptr(), slot.address()),
+        Operand::Mem(addr) => format!("{} ptr {}", width.ptr(), address(addr, fn_label)),
+        // `equ` で決めた定数は、メモリではなく値として読ませる
+        Operand::Imm(imm) => match parse_number(imm) {
+            Some(value) => value.to_string(),
+            None => format!("offset {}", imm),
+        },
+        Operand::VReg(_) => unreachable!("`{}` must be allocated before assembling", operand),
+    }
+}
+
+/// `rsi`、`rax - 8`、`main.f64_0` のように書いたアドレス。レジスタ名でなければ `rip` からの相対にする
+fn address(addr: &str, fn_label: &str) -> String {
+    let base = addr.split(' ').next().unwrap_or_default();
+    if GENERAL_RSTS.iter().any(|rst| rst.as_str() == base) {
+        format!("[{}]", addr)
+    } else {
+        format!("[rip + {}{}]", label(base, fn_label), &addr[base.len()..])
+    }
+}
+
+/// nasm と同じく `.` で始まるラベルは関数ごとにし、GNU as のローカルラベルの `.L` を付ける
+fn label(label: &str, fn_label: &str) -> String {
+    if label.starts_with('.') {
+        format!(".L{}{}", fn_label, label)
+    } else {
+        label.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::frame::StackSlot;
+    use crate::code_gen::instruction::Cond;
+    use crate::code_gen::rst::Rst;
+
+    #[test]
+    fn test_instructions() {
+        let instructions = [
+            Instruction::CALL {
+                func: "fn.rax".to_string(),
+            },
+            Instruction::LABEL {
+                name: ".while_start_0".to_string(),
+            },
+            Instruction::LOAD {
+                dest: Rst::RSI.into(),
+                addr: "str.0".to_string(),
+            },
+            Instruction::MOVE {
+                dest: Rst::RDX.into(),
+                src: Operand::Imm("str.0_len".to_string()),
+            },
+            Instruction::MOVE {
+                dest: Rst::RAX.into(),
+                src: Operand::Imm("'0'".to_string()),
+            },
+            Instruction::MOVSD {
+                dest: Rst::XMM0.into(),
+                src: Operand::Mem("main.f64_0".to_string()),
+            },
+            Instruction::STORE {
+                dest: Operand::Stack(StackSlot::incoming_argument(0)),
+                src: Rst::RAX.into(),
+                width: Width::B32,
+            },
+            Instruction::MOVE {
+                dest: Rst::RCX.into(),
+                src: Operand::Mem("rsp + 16".to_string()),
+            },
+            Instruction::SHL {
+                dest: Rst::RAX.into(),
+                src: Rst::RCX.into(),
+                width: Width::B16,
+            },
+            Instruction::JCC {
+                cond: Cond::NE,
+                label: ".while_start_0".to_string(),
+            },
+        ];
+        let lines: Vec<String> = instructions
+            .iter()
+            .map(|instr| instruction(instr, "main"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "    call fn.rax",
+                ".Lmain.while_start_0:",
+                "    lea rsi, [rip + str.0]",
+                "    mov rdx, offset str.0_len",
+                "    mov rax, 48",
+                "    movsd xmm0, qword ptr [rip + main.f64_0]",
+                "    mov dword ptr [rbp + 16], eax",
+                "    mov rcx, qword ptr [rsp + 16]",
+                "    shl ax, cl",
+                "    jne .Lmain.while_start_0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(
+            bytes(b"a, \"b\"\n"),
+            [
+                "    .ascii \"a, \"",
+                "    .byte 0x22",
+                "    .ascii \"b\"",
+                "    .byte 0x22",
+                "    .byte 0x0A",
+            ]
+        );
+        assert_eq!(
+            directive("section .note.GNU-stack noalloc noexec nowrite progbits").unwrap(),
+            ".section .note.GNU-stack,\"\",@progbits"
+        );
+        assert_eq!(directive("global main").unwrap(), ".globl main");
+        assert_eq!(directive("default rel"), None);
+    }
+}
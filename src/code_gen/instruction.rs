@@ -0,0 +1,536 @@
+use super::frame::{Location, StackSlot};
+use super::rst::{Rst, Width};
+
+pub trait Serialize {
+    fn serialize(&self) -> Vec<String>;
+}
+
+/// 仮想レジスタ。コード生成では個数を気にせず作り、レジスタ割り当てで実際の置き場所に置き換える。
+/// 番号は種類に関係なく関数内で一意
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct VReg(pub usize, pub RegClass);
+
+/// 仮想レジスタに入れる値の種類。割り当てるレジスタの種類が変わる
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum RegClass {
+    /// 汎用レジスタに入れる整数や真偽値
+    Int,
+    /// SSE レジスタに入れる浮動小数点数
+    Float,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    VReg(VReg),
+    Rst(Rst),
+    Stack(StackSlot),
+    /// `[main.f64_0]` や `[rsp]` のように、アドレスを式で書いたメモリ上の 8 バイト
+    Mem(String),
+    /// 即値やシンボルなど、そのまま書き出すもの
+    Imm(String),
+}
+
+impl Operand {
+    pub fn is_memory(&self) -> bool {
+        matches!(self, Operand::Stack(_) | Operand::Mem(_))
+    }
+
+    /// `mov` 以外の命令や、メモリへの `mov` には書けない 32 ビットに収まらない即値か
+    pub fn is_wide_imm(&self) -> bool {
+        match self {
+            Operand::Imm(imm) => imm
+                .parse::<i64>()
+                .is_ok_and(|value| i32::try_from(value).is_err()),
+            _ => false,
+        }
+    }
+
+    /// `width` ビット分を読み書きする時の表現。`eax` や `dword [rbp - 8]` など
+    pub fn sized(&self, width: Width) -> String {
+        match self {
+            Operand::Rst(rst) => rst.name(width).to_string(),
+            Operand::Stack(slot) => format!("{} [{}]", width.ptr(), slot.address()),
+            Operand::Mem(addr) => format!("{} [{}]", width.ptr(), addr),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl From<VReg> for Operand {
+    fn from(vreg: VReg) -> Self {
+        Operand::VReg(vreg)
+    }
+}
+
+impl From<Rst> for Operand {
+    fn from(rst: Rst) -> Self {
+        Operand::Rst(rst)
+    }
+}
+
+impl From<Location> for Operand {
+    fn from(location: Location) -> Self {
+        match location {
+            Location::Rst(rst) => Operand::Rst(rst),
+            Location::Stack(slot) => Operand::Stack(slot),
+        }
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::VReg(VReg(index, RegClass::Int)) => write!(f, "%v{}", index),
+            Operand::VReg(VReg(index, RegClass::Float)) => write!(f, "%f{}", index),
+            Operand::Rst(rst) => write!(f, "{}", rst),
+            Operand::Stack(slot) => write!(f, "{}", slot),
+            Operand::Mem(addr) => write!(f, "qword [{}]", addr),
+            Operand::Imm(imm) => f.write_str(imm),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    RET,
+    CALL {
+        func: String,
+    },
+    /// 64 ビット全体の `mov`
+    MOVE {
+        dest: Operand,
+        src: Operand,
+    },
+    ADD {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    SUB {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    IMUL {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// `rax` に符号無しで掛けて `rdx:rax` に入れる。上位の `rdx` が 0 でなければ CF が立つ
+    MUL {
+        src: Operand,
+        width: Width,
+    },
+    /// `rax` を符号拡張して `rdx:rax` にする
+    CQO,
+    /// `eax` を符号拡張して `edx:eax` にする
+    CDQ,
+    /// `rdx:rax` (32 ビットなら `edx:eax`) を符号付きで割り、商を `rax`、余りを `rdx` に入れる
+    IDIV {
+        src: Operand,
+        width: Width,
+    },
+    /// `IDIV` の符号無し版
+    DIV {
+        src: Operand,
+        width: Width,
+    },
+    NEG {
+        dest: Operand,
+        width: Width,
+    },
+    NOT {
+        dest: Operand,
+        width: Width,
+    },
+    AND {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    OR {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    XOR {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// シフト量 `src` は即値か `rcx` (`cl`)
+    SHL {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// 論理右シフト
+    SHR {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// 算術右シフト
+    SAR {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// `src` の下位 `width` ビットを符号拡張して 64 ビットの `dest` に入れる
+    MOVSX {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// `src` の下位 `width` ビットをゼロ拡張して 64 ビットの `dest` に入れる
+    MOVZX {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    /// `src` の下位 `width` ビットを、メモリの `dest` に書き込む
+    STORE {
+        dest: Operand,
+        src: Operand,
+        width: Width,
+    },
+    PUSH {
+        src: Operand,
+    },
+    POP {
+        dest: Operand,
+    },
+    LOAD {
+        dest: Operand,
+        addr: String,
+    },
+    SYSCALL,
+    CMP {
+        src1: Operand,
+        src2: Operand,
+        width: Width,
+    },
+    JMP {
+        label: String,
+    },
+    JCC {
+        cond: Cond,
+        label: String,
+    },
+    LABEL {
+        name: String,
+    },
+    /// 浮動小数点数の `mov`
+    MOVSD {
+        dest: Operand,
+        src: Operand,
+    },
+    ADDSD {
+        dest: Operand,
+        src: Operand,
+    },
+    SUBSD {
+        dest: Operand,
+        src: Operand,
+    },
+    MULSD {
+        dest: Operand,
+        src: Operand,
+    },
+    DIVSD {
+        dest: Operand,
+        src: Operand,
+    },
+    /// 浮動小数点数の比較。結果は符号無し整数の比較と同じフラグに入り、NaN があると PF が立つ
+    UCOMISD {
+        src1: Operand,
+        src2: Operand,
+    },
+    /// 64 ビットの符号付き整数を浮動小数点数にする
+    CVTSI2SD {
+        dest: Operand,
+        src: Operand,
+    },
+    /// 浮動小数点数を 0 に向かって丸めて 64 ビットの符号付き整数にする
+    CVTTSD2SI {
+        dest: Operand,
+        src: Operand,
+    },
+}
+
+impl Instruction {
+    /// 命令が読むオペランド
+    pub fn uses(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::MOVE { src, .. }
+            | Instruction::MOVSD { src, .. }
+            | Instruction::MOVSX { src, .. }
+            | Instruction::MOVZX { src, .. }
+            | Instruction::CVTSI2SD { src, .. }
+            | Instruction::CVTTSD2SI { src, .. }
+            | Instruction::STORE { src, .. }
+            | Instruction::PUSH { src }
+            | Instruction::MUL { src, .. }
+            | Instruction::IDIV { src, .. }
+            | Instruction::DIV { src, .. } => vec![src],
+            Instruction::ADD { dest, src, .. }
+            | Instruction::SUB { dest, src, .. }
+            | Instruction::IMUL { dest, src, .. }
+            | Instruction::AND { dest, src, .. }
+            | Instruction::OR { dest, src, .. }
+            | Instruction::XOR { dest, src, .. }
+            | Instruction::SHL { dest, src, .. }
+            | Instruction::SHR { dest, src, .. }
+            | Instruction::SAR { dest, src, .. }
+            | Instruction::ADDSD { dest, src }
+            | Instruction::SUBSD { dest, src }
+            | Instruction::MULSD { dest, src }
+            | Instruction::DIVSD { dest, src } => vec![dest, src],
+            Instruction::CMP { src1, src2, .. } | Instruction::UCOMISD { src1, src2 } => {
+                vec![src1, src2]
+            }
+            Instruction::NEG { dest, .. } | Instruction::NOT { dest, .. } => vec![dest],
+            _ => vec![],
+        }
+    }
+
+    /// 命令が書き込むオペランド
+    pub fn defs(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::MOVE { dest, .. }
+            | Instruction::ADD { dest, .. }
+            | Instruction::SUB { dest, .. }
+            | Instruction::IMUL { dest, .. }
+            | Instruction::NEG { dest, .. }
+            | Instruction::NOT { dest, .. }
+            | Instruction::AND { dest, .. }
+            | Instruction::OR { dest, .. }
+            | Instruction::XOR { dest, .. }
+            | Instruction::SHL { dest, .. }
+            | Instruction::SHR { dest, .. }
+            | Instruction::SAR { dest, .. }
+            | Instruction::MOVSX { dest, .. }
+            | Instruction::MOVZX { dest, .. }
+            | Instruction::MOVSD { dest, .. }
+            | Instruction::ADDSD { dest, .. }
+            | Instruction::SUBSD { dest, .. }
+            | Instruction::MULSD { dest, .. }
+            | Instruction::DIVSD { dest, .. }
+            | Instruction::CVTSI2SD { dest, .. }
+            | Instruction::CVTTSD2SI { dest, .. }
+            | Instruction::POP { dest }
+            | Instruction::LOAD { dest, .. } => vec![dest],
+            _ => vec![],
+        }
+    }
+
+    /// 命令中の全てのオペランドを書き換える
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::MOVE { dest, src }
+            | Instruction::ADD { dest, src, .. }
+            | Instruction::SUB { dest, src, .. }
+            | Instruction::IMUL { dest, src, .. }
+            | Instruction::AND { dest, src, .. }
+            | Instruction::OR { dest, src, .. }
+            | Instruction::XOR { dest, src, .. }
+            | Instruction::SHL { dest, src, .. }
+            | Instruction::SHR { dest, src, .. }
+            | Instruction::SAR { dest, src, .. }
+            | Instruction::MOVSX { dest, src, .. }
+            | Instruction::MOVZX { dest, src, .. }
+            | Instruction::STORE { dest, src, .. }
+            | Instruction::MOVSD { dest, src }
+            | Instruction::ADDSD { dest, src }
+            | Instruction::SUBSD { dest, src }
+            | Instruction::MULSD { dest, src }
+            | Instruction::DIVSD { dest, src }
+            | Instruction::CVTSI2SD { dest, src }
+            | Instruction::CVTTSD2SI { dest, src } => vec![dest, src],
+            Instruction::CMP { src1, src2, .. } | Instruction::UCOMISD { src1, src2 } => {
+                vec![src1, src2]
+            }
+            Instruction::PUSH { src }
+            | Instruction::MUL { src, .. }
+            | Instruction::IDIV { src, .. }
+            | Instruction::DIV { src, .. } => vec![src],
+            Instruction::POP { dest }
+            | Instruction::LOAD { dest, .. }
+            | Instruction::NEG { dest, .. }
+            | Instruction::NOT { dest, .. } => vec![dest],
+            _ => vec![],
+        }
+    }
+
+    /// 呼び出し先で caller-saved レジスタが壊される命令か
+    pub fn clobbers_caller_saved(&self) -> bool {
+        matches!(self, Instruction::CALL { .. } | Instruction::SYSCALL)
+    }
+}
+
+/// 条件付きジャンプの条件。`L` から `GE` は符号付き整数、`B` から `AE` は符号無し整数と `ucomisd` の結果に使う
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Cond {
+    E,
+    NE,
+    L,
+    LE,
+    G,
+    GE,
+    B,
+    BE,
+    A,
+    AE,
+    /// 比較した値に NaN があった
+    P,
+    NP,
+    /// 符号付き整数の演算が桁あふれした
+    O,
+    NO,
+}
+
+impl Cond {
+    pub fn negate(self) -> Cond {
+        match self {
+            Cond::E => Cond::NE,
+            Cond::NE => Cond::E,
+            Cond::L => Cond::GE,
+            Cond::LE => Cond::G,
+            Cond::G => Cond::LE,
+            Cond::GE => Cond::L,
+            Cond::B => Cond::AE,
+            Cond::BE => Cond::A,
+            Cond::A => Cond::BE,
+            Cond::AE => Cond::B,
+            Cond::P => Cond::NP,
+            Cond::NP => Cond::P,
+            Cond::O => Cond::NO,
+            Cond::NO => Cond::O,
+        }
+    }
+
+    /// 符号付き整数の大小比較を、符号無し整数の比較にする
+    pub fn unsigned(self) -> Cond {
+        match self {
+            Cond::L => Cond::B,
+            Cond::LE => Cond::BE,
+            Cond::G => Cond::A,
+            Cond::GE => Cond::AE,
+            cond => cond,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::NE => "ne",
+            Cond::L => "l",
+            Cond::LE => "le",
+            Cond::G => "g",
+            Cond::GE => "ge",
+            Cond::B => "b",
+            Cond::BE => "be",
+            Cond::A => "a",
+            Cond::AE => "ae",
+            Cond::P => "p",
+            Cond::NP => "np",
+            Cond::O => "o",
+            Cond::NO => "no",
+        }
+    }
+}
+
+impl Serialize for Instruction {
+    fn serialize(&self) -> Vec<String> {
+        // `add eax, ebx` のように、大きさを揃えた2つのオペランドを取る命令
+        let binary = |name: &str, dest: &Operand, src: &Operand, width: &Width| {
+            vec![format!(
+                "    {} {}, {}",
+                name,
+                dest.sized(*width),
+                src.sized(*width)
+            )]
+        };
+        match self {
+            Instruction::RET => vec!["    ret".to_string()],
+            Instruction::CALL { func } => vec![format!("    call {}", func)],
+            Instruction::MOVE { dest, src } => vec![format!("    mov {}, {}", dest, src)],
+            Instruction::ADD { dest, src, width } => binary("add", dest, src, width),
+            Instruction::SUB { dest, src, width } => binary("sub", dest, src, width),
+            Instruction::IMUL { dest, src, width } => binary("imul", dest, src, width),
+            Instruction::MUL { src, width } => vec![format!("    mul {}", src.sized(*width))],
+            Instruction::CQO => vec!["    cqo".to_string()],
+            Instruction::CDQ => vec!["    cdq".to_string()],
+            Instruction::IDIV { src, width } => vec![format!("    idiv {}", src.sized(*width))],
+            Instruction::DIV { src, width } => vec![format!("    div {}", src.sized(*width))],
+            Instruction::NEG { dest, width } => vec![format!("    neg {}", dest.sized(*width))],
+            Instruction::NOT { dest, width } => vec![format!("    not {}", dest.sized(*width))],
+            Instruction::AND { dest, src, width } => binary("and", dest, src, width),
+            Instruction::OR { dest, src, width } => binary("or", dest, src, width),
+            Instruction::XOR { dest, src, width } => binary("xor", dest, src, width),
+            Instruction::SHL { dest, src, width } => {
+                vec![format!(
+                    "    shl {}, {}",
+                    dest.sized(*width),
+                    src.sized(Width::B8)
+                )]
+            }
+            Instruction::SHR { dest, src, width } => {
+                vec![format!(
+                    "    shr {}, {}",
+                    dest.sized(*width),
+                    src.sized(Width::B8)
+                )]
+            }
+            Instruction::SAR { dest, src, width } => {
+                vec![format!(
+                    "    sar {}, {}",
+                    dest.sized(*width),
+                    src.sized(Width::B8)
+                )]
+            }
+            Instruction::MOVSX { dest, src, width } => {
+                let name = if *width == Width::B32 {
+                    "movsxd"
+                } else {
+                    "movsx"
+                };
+                vec![format!("    {} {}, {}", name, dest, src.sized(*width))]
+            }
+            // 32 ビットのレジスタへの書き込みは上位 32 ビットを 0 にする
+            Instruction::MOVZX {
+                dest,
+                src,
+                width: Width::B32,
+            } => binary("mov", dest, src, &Width::B32),
+            Instruction::MOVZX { dest, src, width } => {
+                vec![format!("    movzx {}, {}", dest, src.sized(*width))]
+            }
+            Instruction::STORE { dest, src, width } => binary("mov", dest, src, width),
+            Instruction::PUSH { src } => vec![format!("    push {}", src)],
+            Instruction::POP { dest } => vec![format!("    pop {}", dest)],
+            Instruction::LOAD { dest, addr } => vec![format!("    lea {}, [{}]", dest, addr)],
+            Instruction::SYSCALL => vec!["    syscall".to_string()],
+            Instruction::CMP { src1, src2, width } => binary("cmp", src1, src2, width),
+            Instruction::JMP { label } => vec![format!("    jmp {}", label)],
+            Instruction::JCC { cond, label } => {
+                vec![format!("    j{} {}", cond.as_str(), label)]
+            }
+            Instruction::LABEL { name } => vec![format!("{}:", name)],
+            Instruction::MOVSD { dest, src } => vec![format!("    movsd {}, {}", dest, src)],
+            Instruction::ADDSD { dest, src } => vec![format!("    addsd {}, {}", dest, src)],
+            Instruction::SUBSD { dest, src } => vec![format!("    subsd {}, {}", dest, src)],
+            Instruction::MULSD { dest, src } => vec![format!("    mulsd {}, {}", dest, src)],
+            Instruction::DIVSD { dest, src } => vec![format!("    divsd {}, {}", dest, src)],
+            Instruction::UCOMISD { src1, src2 } => {
+                vec![format!("    ucomisd {}, {}", src1, src2)]
+            }
+            Instruction::CVTSI2SD { dest, src } => {
+                vec![format!("    cvtsi2sd {}, {}", dest, src)]
+            }
+            Instruction::CVTTSD2SI { dest, src } => {
+                vec![format!("    cvttsd2si {}, {}", dest, src)]
+            }
+        }
+    }
+}
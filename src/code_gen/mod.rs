@@ -1,3 +1,11 @@
 pub mod code_gen;
+pub mod const_eval;
+pub mod elf;
+pub mod encoder;
+pub mod frame;
+pub mod gas;
+pub mod instruction;
+pub mod regalloc;
 pub mod rst;
 pub mod syscall;
+pub mod target;
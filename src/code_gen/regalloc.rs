@@ -0,0 +1,511 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::frame::{Frame, Location};
+use super::instruction::{Instruction, Operand, RegClass, VReg};
+use super::rst::Rst;
+
+/// 仮想レジスタに割り当てるレジスタ。引数や戻り値、システムコールで決まった使い方をするものは含めない
+const ALLOCATABLE_RSTS: [Rst; 6] = [Rst::R10, Rst::RBX, Rst::R12, Rst::R13, Rst::R14, Rst::R15];
+
+/// 浮動小数点数の仮想レジスタに割り当てるレジスタ。引数と戻り値に使う `xmm0` から `xmm7` は含めない
+const ALLOCATABLE_XMMS: [Rst; 7] = [
+    Rst::XMM8,
+    Rst::XMM9,
+    Rst::XMM10,
+    Rst::XMM11,
+    Rst::XMM12,
+    Rst::XMM13,
+    Rst::XMM14,
+];
+
+/// メモリ同士の `mov` のように、そのままでは書けない命令を書き換える時に使う作業用レジスタ
+const SCRATCH_RST: Rst = Rst::R11;
+
+/// 浮動小数点数の命令を書き換える時に使う作業用レジスタ
+const SCRATCH_XMM: Rst = Rst::XMM15;
+
+/// `--regalloc` で選ぶレジスタ割り当ての方法
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RegAlloc {
+    /// 全ての仮想レジスタをスタックに置く
+    Naive,
+    /// 生存区間を元に linear scan でレジスタを割り当てる
+    Linear,
+}
+
+impl RegAlloc {
+    pub const NAMES: [&str; 2] = ["naive", "linear"];
+
+    pub fn from_name(name: &str) -> Option<RegAlloc> {
+        match name {
+            "naive" => Some(RegAlloc::Naive),
+            "linear" => Some(RegAlloc::Linear),
+            _ => None,
+        }
+    }
+}
+
+/// 仮想レジスタが生きている命令の範囲。穴は考えず、最初から最後までを1つの区間にする
+#[derive(Debug, PartialEq, Clone)]
+struct Interval {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    /// 区間の途中に `call` があり、caller-saved レジスタに置けない
+    crosses_call: bool,
+}
+
+/// 仮想レジスタを実際のレジスタかスタックに置き換えた命令列と、使った callee-saved レジスタを返す
+pub fn allocate(
+    instructions: Vec<Instruction>,
+    regalloc: RegAlloc,
+    frame: &mut Frame,
+) -> (Vec<Instruction>, Vec<Rst>) {
+    let locations = match regalloc {
+        RegAlloc::Naive => {
+            let vregs: BTreeSet<VReg> = instructions
+                .iter()
+                .flat_map(|instr| instr.uses().into_iter().chain(instr.defs()))
+                .filter_map(as_vreg)
+                .collect();
+            vregs
+                .into_iter()
+                .map(|vreg| (vreg, Location::Stack(frame.alloc_slot())))
+                .collect()
+        }
+        RegAlloc::Linear => linear_scan(&live_intervals(&instructions), frame),
+    };
+    let used_callee_saved = ALLOCATABLE_RSTS
+        .into_iter()
+        .filter(|rst| {
+            rst.is_callee_saved() && locations.values().any(|l| *l == Location::Rst(*rst))
+        })
+        .collect();
+
+    let mut allocated = Vec::new();
+    for mut instr in instructions {
+        for operand in instr.operands_mut() {
+            if let Operand::VReg(vreg) = operand {
+                *operand = locations[vreg].into();
+            }
+        }
+        legalize(instr, &mut allocated);
+    }
+    (allocated, used_callee_saved)
+}
+
+fn as_vreg(operand: &Operand) -> Option<VReg> {
+    match operand {
+        Operand::VReg(vreg) => Some(*vreg),
+        _ => None,
+    }
+}
+
+/// 命令ごとの後続の命令の位置。
+/// 命令列の外 (エピローグの後ろに置くパニック処理など) へのジャンプは戻ってこないので後続を持たない
+fn successors(instructions: &[Instruction]) -> Vec<Vec<usize>> {
+    let labels: HashMap<&str, usize> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instr)| match instr {
+            Instruction::LABEL { name } => Some((name.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    let next = |i: usize| (i + 1 < instructions.len()).then_some(i + 1);
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instr)| match instr {
+            Instruction::JMP { label } => labels.get(label.as_str()).copied().into_iter().collect(),
+            Instruction::JCC { label, .. } => next(i)
+                .into_iter()
+                .chain(labels.get(label.as_str()).copied())
+                .collect(),
+            Instruction::RET => vec![],
+            _ => next(i).into_iter().collect(),
+        })
+        .collect()
+}
+
+/// 後ろ向きのデータフロー解析で各命令の直前に生きている仮想レジスタを求め、生存区間にする
+fn live_intervals(instructions: &[Instruction]) -> Vec<Interval> {
+    let succs = successors(instructions);
+    let uses: Vec<Vec<VReg>> = instructions
+        .iter()
+        .map(|instr| instr.uses().into_iter().filter_map(as_vreg).collect())
+        .collect();
+    let defs: Vec<Vec<VReg>> = instructions
+        .iter()
+        .map(|instr| instr.defs().into_iter().filter_map(as_vreg).collect())
+        .collect();
+
+    let mut live_in = vec![HashSet::<VReg>::new(); instructions.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..instructions.len()).rev() {
+            let mut live: HashSet<VReg> = succs[i]
+                .iter()
+                .flat_map(|succ| live_in[*succ].iter().copied())
+                .collect();
+            for def in &defs[i] {
+                live.remove(def);
+            }
+            live.extend(uses[i].iter().copied());
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut ranges = HashMap::<VReg, (usize, usize)>::new();
+    for (i, live) in live_in.iter().enumerate() {
+        for vreg in live.iter().chain(&defs[i]) {
+            let range = ranges.entry(*vreg).or_insert((i, i));
+            range.0 = range.0.min(i);
+            range.1 = range.1.max(i);
+        }
+    }
+    let calls: Vec<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instr)| instr.clobbers_caller_saved())
+        .map(|(i, _)| i)
+        .collect();
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .map(|(vreg, (start, end))| Interval {
+            vreg,
+            start,
+            end,
+            crosses_call: calls.iter().any(|call| start < *call && *call < end),
+        })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, interval.vreg));
+    intervals
+}
+
+/// 区間の始まる順にレジスタを割り当てる。空きが無ければ、使い終わるのが一番遅い区間をスタックに追い出す
+fn linear_scan(intervals: &[Interval], frame: &mut Frame) -> HashMap<VReg, Location> {
+    let mut locations = HashMap::new();
+    let mut active: Vec<(&Interval, Rst)> = Vec::new();
+    for interval in intervals {
+        // ここで終わる区間のレジスタは、ここから始まる区間が使ってよい。
+        // 命令はオペランドを読んでから書き込むので値は壊れない
+        active.retain(|(other, _)| other.end > interval.start);
+        // SSE レジスタは全て caller-saved なので、call をまたぐ浮動小数点数は常にスタックに置く
+        let usable = |rst: Rst| !interval.crosses_call || rst.is_callee_saved();
+        let candidates = match interval.vreg.1 {
+            RegClass::Int => &ALLOCATABLE_RSTS[..],
+            RegClass::Float => &ALLOCATABLE_XMMS[..],
+        };
+        let free = candidates
+            .iter()
+            .copied()
+            .find(|rst| usable(*rst) && active.iter().all(|(_, used)| used != rst));
+        if let Some(rst) = free {
+            locations.insert(interval.vreg, Location::Rst(rst));
+            active.push((interval, rst));
+            continue;
+        }
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, rst))| usable(*rst) && candidates.contains(rst))
+            .max_by_key(|(_, (other, _))| other.end)
+            .map(|(i, _)| i);
+        match victim {
+            Some(i) if active[i].0.end > interval.end => {
+                let (spilled, rst) = active.remove(i);
+                locations.insert(spilled.vreg, Location::Stack(frame.alloc_slot()));
+                locations.insert(interval.vreg, Location::Rst(rst));
+                active.push((interval, rst));
+            }
+            _ => {
+                locations.insert(interval.vreg, Location::Stack(frame.alloc_slot()));
+            }
+        }
+    }
+    locations
+}
+
+/// 割り当ての結果、x86-64 で書けない形になった命令を作業用レジスタを使って書き換える
+fn legalize(instr: Instruction, out: &mut Vec<Instruction>) {
+    let scratch = Operand::Rst(SCRATCH_RST);
+    let xmm_scratch = Operand::Rst(SCRATCH_XMM);
+    // 演算の右辺がメモリ同士にならないように、右辺を作業用レジスタに読む
+    let through_scratch = |src: Operand, out: &mut Vec<Instruction>| {
+        out.push(Instruction::MOVE {
+            dest: scratch.clone(),
+            src,
+        });
+        scratch.clone()
+    };
+    match instr {
+        Instruction::MOVE { dest, src } if dest == src => {}
+        // 32 ビットに収まらない即値は、レジスタにしか直接 mov できない
+        Instruction::MOVE { dest, src }
+            if dest.is_memory() && (src.is_memory() || src.is_wide_imm()) =>
+        {
+            let src = through_scratch(src, out);
+            out.push(Instruction::MOVE { dest, src });
+        }
+        Instruction::PUSH { src } if src.is_wide_imm() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::PUSH { src });
+        }
+        Instruction::ADD { dest, src, width } if dest.is_memory() && src.is_memory() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::ADD { dest, src, width });
+        }
+        Instruction::SUB { dest, src, width } if dest.is_memory() && src.is_memory() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::SUB { dest, src, width });
+        }
+        Instruction::AND { dest, src, width } if dest.is_memory() && src.is_memory() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::AND { dest, src, width });
+        }
+        Instruction::OR { dest, src, width } if dest.is_memory() && src.is_memory() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::OR { dest, src, width });
+        }
+        Instruction::XOR { dest, src, width } if dest.is_memory() && src.is_memory() => {
+            let src = through_scratch(src, out);
+            out.push(Instruction::XOR { dest, src, width });
+        }
+        Instruction::CMP { src1, src2, width } if src1.is_memory() && src2.is_memory() => {
+            let src2 = through_scratch(src2, out);
+            out.push(Instruction::CMP { src1, src2, width });
+        }
+        Instruction::IMUL { dest, src, width } if dest.is_memory() => {
+            // imul は書き込み先がレジスタでなければならない
+            out.push(Instruction::MOVE {
+                dest: scratch.clone(),
+                src: dest.clone(),
+            });
+            out.push(Instruction::IMUL {
+                dest: scratch.clone(),
+                src,
+                width,
+            });
+            out.push(Instruction::MOVE { dest, src: scratch });
+        }
+        // 拡張と変換の書き込み先はレジスタでなければならない
+        Instruction::MOVSX { dest, src, width } if dest.is_memory() => {
+            out.push(Instruction::MOVSX {
+                dest: scratch.clone(),
+                src,
+                width,
+            });
+            out.push(Instruction::MOVE { dest, src: scratch });
+        }
+        Instruction::MOVZX { dest, src, width } if dest.is_memory() => {
+            out.push(Instruction::MOVZX {
+                dest: scratch.clone(),
+                src,
+                width,
+            });
+            out.push(Instruction::MOVE { dest, src: scratch });
+        }
+        Instruction::CVTTSD2SI { dest, src } if dest.is_memory() => {
+            out.push(Instruction::CVTTSD2SI {
+                dest: scratch.clone(),
+                src,
+            });
+            out.push(Instruction::MOVE { dest, src: scratch });
+        }
+        Instruction::CVTSI2SD { dest, src } if dest.is_memory() => {
+            out.push(Instruction::CVTSI2SD {
+                dest: xmm_scratch.clone(),
+                src,
+            });
+            out.push(Instruction::MOVSD {
+                dest,
+                src: xmm_scratch,
+            });
+        }
+        Instruction::MOVSD { dest, src } if dest == src => {}
+        Instruction::MOVSD { dest, src } if dest.is_memory() && src.is_memory() => {
+            out.push(Instruction::MOVSD {
+                dest: xmm_scratch.clone(),
+                src,
+            });
+            out.push(Instruction::MOVSD {
+                dest,
+                src: xmm_scratch,
+            });
+        }
+        // SSE の演算と比較は、書き込み先と左辺が SSE レジスタでなければならない
+        Instruction::ADDSD { dest, src } if dest.is_memory() => {
+            sse_through_scratch(dest, src, out, |dest, src| Instruction::ADDSD { dest, src });
+        }
+        Instruction::SUBSD { dest, src } if dest.is_memory() => {
+            sse_through_scratch(dest, src, out, |dest, src| Instruction::SUBSD { dest, src });
+        }
+        Instruction::MULSD { dest, src } if dest.is_memory() => {
+            sse_through_scratch(dest, src, out, |dest, src| Instruction::MULSD { dest, src });
+        }
+        Instruction::DIVSD { dest, src } if dest.is_memory() => {
+            sse_through_scratch(dest, src, out, |dest, src| Instruction::DIVSD { dest, src });
+        }
+        Instruction::UCOMISD { src1, src2 } if src1.is_memory() => {
+            out.push(Instruction::MOVSD {
+                dest: xmm_scratch.clone(),
+                src: src1,
+            });
+            out.push(Instruction::UCOMISD {
+                src1: xmm_scratch,
+                src2,
+            });
+        }
+        Instruction::LOAD { dest, addr } if dest.is_memory() => {
+            out.push(Instruction::LOAD {
+                dest: scratch.clone(),
+                addr,
+            });
+            out.push(Instruction::MOVE { dest, src: scratch });
+        }
+        instr => out.push(instr),
+    }
+}
+
+/// メモリにある `dest` を作業用の SSE レジスタに読み、演算してから書き戻す
+fn sse_through_scratch(
+    dest: Operand,
+    src: Operand,
+    out: &mut Vec<Instruction>,
+    op: impl Fn(Operand, Operand) -> Instruction,
+) {
+    let scratch = Operand::Rst(SCRATCH_XMM);
+    out.push(Instruction::MOVSD {
+        dest: scratch.clone(),
+        src: dest.clone(),
+    });
+    out.push(op(scratch.clone(), src));
+    out.push(Instruction::MOVSD { dest, src: scratch });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::instruction::{Cond, Serialize};
+    use crate::code_gen::rst::Width;
+
+    fn mov(dest: impl Into<Operand>, src: impl Into<Operand>) -> Instruction {
+        Instruction::MOVE {
+            dest: dest.into(),
+            src: src.into(),
+        }
+    }
+
+    fn imm(value: &str) -> Operand {
+        Operand::Imm(value.to_string())
+    }
+
+    fn serialize(instructions: &[Instruction]) -> Vec<String> {
+        instructions
+            .iter()
+            .flat_map(|instr| instr.serialize())
+            .collect()
+    }
+
+    #[test]
+    fn test_live_intervals_cover_loops() {
+        let (i, n) = (VReg(0, RegClass::Int), VReg(1, RegClass::Int));
+        let instructions = vec![
+            mov(i, imm("0")),
+            mov(n, imm("10")),
+            Instruction::LABEL {
+                name: ".start".to_string(),
+            },
+            Instruction::CMP {
+                src1: i.into(),
+                src2: n.into(),
+                width: Width::B64,
+            },
+            Instruction::JCC {
+                cond: Cond::GE,
+                label: ".end".to_string(),
+            },
+            Instruction::ADD {
+                dest: i.into(),
+                src: imm("1"),
+                width: Width::B64,
+            },
+            Instruction::CALL {
+                func: "f".to_string(),
+            },
+            Instruction::JMP {
+                label: ".start".to_string(),
+            },
+            Instruction::LABEL {
+                name: ".end".to_string(),
+            },
+            mov(Rst::RAX, i),
+        ];
+        let intervals = live_intervals(&instructions);
+        // n はループの最後の命令まで生きている
+        assert_eq!(
+            intervals,
+            vec![
+                Interval {
+                    vreg: i,
+                    start: 0,
+                    end: 9,
+                    crosses_call: true
+                },
+                Interval {
+                    vreg: n,
+                    start: 1,
+                    end: 7,
+                    crosses_call: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linear_scan_spills_and_avoids_caller_saved_across_calls() {
+        let vregs: Vec<VReg> = (0..8).map(|i| VReg(i, RegClass::Int)).collect();
+        let mut instructions: Vec<Instruction> =
+            vregs.iter().map(|vreg| mov(*vreg, imm("1"))).collect();
+        let call_result = VReg(8, RegClass::Int);
+        instructions.push(Instruction::CALL {
+            func: "f".to_string(),
+        });
+        instructions.push(mov(call_result, Rst::RAX));
+        for vreg in &vregs {
+            instructions.push(Instruction::ADD {
+                dest: call_result.into(),
+                src: (*vreg).into(),
+                width: Width::B64,
+            });
+        }
+
+        let mut frame = Frame::default();
+        let (allocated, used_callee_saved) =
+            allocate(instructions.clone(), RegAlloc::Linear, &mut frame);
+        let asm = serialize(&allocated);
+        // call をまたぐ値は callee-saved レジスタの5つに入り、残りはスタックに置かれる
+        assert_eq!(
+            used_callee_saved,
+            vec![Rst::RBX, Rst::R12, Rst::R13, Rst::R14, Rst::R15]
+        );
+        assert_eq!(asm[0], "    mov rbx, 1");
+        assert!(asm.contains(&"    mov qword [rbp - 8], 1".to_string()));
+        assert!(asm.contains(&"    mov r10, rax".to_string()));
+        assert!(!asm.iter().any(|line| line.contains("%v")));
+
+        let mut frame = Frame::default();
+        let (allocated, used_callee_saved) = allocate(instructions, RegAlloc::Naive, &mut frame);
+        let asm = serialize(&allocated);
+        assert!(used_callee_saved.is_empty());
+        assert_eq!(frame.layout(&[]).1, 80);
+        // メモリ同士の演算は作業用レジスタを経由する
+        assert!(asm.contains(&"    mov r11, qword [rbp - 8]".to_string()));
+        assert!(asm.contains(&"    add qword [rbp - 72], r11".to_string()));
+    }
+}
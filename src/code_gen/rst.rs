@@ -1,6 +1,7 @@
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Rst {
     RAX,
+    RBX,
     RDX,
     RCX,
     RDI,
@@ -13,29 +14,224 @@ pub enum Rst {
     R13,
     R14,
     R15,
+    RBP,
+    RSP,
+    /// SSE レジスタ。浮動小数点数を入れる
+    XMM0,
+    XMM1,
+    XMM2,
+    XMM3,
+    XMM4,
+    XMM5,
+    XMM6,
+    XMM7,
+    XMM8,
+    XMM9,
+    XMM10,
+    XMM11,
+    XMM12,
+    XMM13,
+    XMM14,
+    XMM15,
 }
 
+/// 命令が読み書きする値の大きさ
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Width {
+    B8,
+    B16,
+    B32,
+    B64,
+}
+
+impl Width {
+    /// メモリのオペランドに付ける大きさの指定
+    pub fn ptr(self) -> &'static str {
+        match self {
+            Width::B8 => "byte",
+            Width::B16 => "word",
+            Width::B32 => "dword",
+            Width::B64 => "qword",
+        }
+    }
+}
+
+/// 汎用レジスタ。メモリのアドレスに書かれたレジスタ名を読むのに使う
+pub const GENERAL_RSTS: [Rst; 16] = [
+    Rst::RAX,
+    Rst::RCX,
+    Rst::RDX,
+    Rst::RBX,
+    Rst::RSP,
+    Rst::RBP,
+    Rst::RSI,
+    Rst::RDI,
+    Rst::R8,
+    Rst::R9,
+    Rst::R10,
+    Rst::R11,
+    Rst::R12,
+    Rst::R13,
+    Rst::R14,
+    Rst::R15,
+];
+
+/// System V ABI で整数の引数を渡すレジスタ。7 番目以降はスタックで渡す
+pub const ARGUMENT_RSTS: [Rst; 6] = [Rst::RDI, Rst::RSI, Rst::RDX, Rst::RCX, Rst::R8, Rst::R9];
+
+/// System V ABI で浮動小数点数の引数を渡すレジスタ。9 番目以降はスタックで渡す
+pub const FLOAT_ARGUMENT_RSTS: [Rst; 8] = [
+    Rst::XMM0,
+    Rst::XMM1,
+    Rst::XMM2,
+    Rst::XMM3,
+    Rst::XMM4,
+    Rst::XMM5,
+    Rst::XMM6,
+    Rst::XMM7,
+];
+
 impl Rst {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(self) -> &'static str {
+        self.name(Width::B64)
+    }
+
+    /// `width` ビット分を読み書きする時のレジスタ名。`rax` に対する `eax` など。SSE レジスタは常に同じ名前
+    pub fn name(self, width: Width) -> &'static str {
+        match (self, width) {
+            (Rst::RAX, Width::B64) => "rax",
+            (Rst::RAX, Width::B32) => "eax",
+            (Rst::RAX, Width::B16) => "ax",
+            (Rst::RAX, Width::B8) => "al",
+            (Rst::RBX, Width::B64) => "rbx",
+            (Rst::RBX, Width::B32) => "ebx",
+            (Rst::RBX, Width::B16) => "bx",
+            (Rst::RBX, Width::B8) => "bl",
+            (Rst::RDX, Width::B64) => "rdx",
+            (Rst::RDX, Width::B32) => "edx",
+            (Rst::RDX, Width::B16) => "dx",
+            (Rst::RDX, Width::B8) => "dl",
+            (Rst::RCX, Width::B64) => "rcx",
+            (Rst::RCX, Width::B32) => "ecx",
+            (Rst::RCX, Width::B16) => "cx",
+            (Rst::RCX, Width::B8) => "cl",
+            (Rst::RDI, Width::B64) => "rdi",
+            (Rst::RDI, Width::B32) => "edi",
+            (Rst::RDI, Width::B16) => "di",
+            (Rst::RDI, Width::B8) => "dil",
+            (Rst::RSI, Width::B64) => "rsi",
+            (Rst::RSI, Width::B32) => "esi",
+            (Rst::RSI, Width::B16) => "si",
+            (Rst::RSI, Width::B8) => "sil",
+            (Rst::R8, Width::B64) => "r8",
+            (Rst::R8, Width::B32) => "r8d",
+            (Rst::R8, Width::B16) => "r8w",
+            (Rst::R8, Width::B8) => "r8b",
+            (Rst::R9, Width::B64) => "r9",
+            (Rst::R9, Width::B32) => "r9d",
+            (Rst::R9, Width::B16) => "r9w",
+            (Rst::R9, Width::B8) => "r9b",
+            (Rst::R10, Width::B64) => "r10",
+            (Rst::R10, Width::B32) => "r10d",
+            (Rst::R10, Width::B16) => "r10w",
+            (Rst::R10, Width::B8) => "r10b",
+            (Rst::R11, Width::B64) => "r11",
+            (Rst::R11, Width::B32) => "r11d",
+            (Rst::R11, Width::B16) => "r11w",
+            (Rst::R11, Width::B8) => "r11b",
+            (Rst::R12, Width::B64) => "r12",
+            (Rst::R12, Width::B32) => "r12d",
+            (Rst::R12, Width::B16) => "r12w",
+            (Rst::R12, Width::B8) => "r12b",
+            (Rst::R13, Width::B64) => "r13",
+            (Rst::R13, Width::B32) => "r13d",
+            (Rst::R13, Width::B16) => "r13w",
+            (Rst::R13, Width::B8) => "r13b",
+            (Rst::R14, Width::B64) => "r14",
+            (Rst::R14, Width::B32) => "r14d",
+            (Rst::R14, Width::B16) => "r14w",
+            (Rst::R14, Width::B8) => "r14b",
+            (Rst::R15, Width::B64) => "r15",
+            (Rst::R15, Width::B32) => "r15d",
+            (Rst::R15, Width::B16) => "r15w",
+            (Rst::R15, Width::B8) => "r15b",
+            (Rst::RBP, Width::B64) => "rbp",
+            (Rst::RBP, Width::B32) => "ebp",
+            (Rst::RBP, Width::B16) => "bp",
+            (Rst::RBP, Width::B8) => "bpl",
+            (Rst::RSP, Width::B64) => "rsp",
+            (Rst::RSP, Width::B32) => "esp",
+            (Rst::RSP, Width::B16) => "sp",
+            (Rst::RSP, Width::B8) => "spl",
+            (Rst::XMM0, _) => "xmm0",
+            (Rst::XMM1, _) => "xmm1",
+            (Rst::XMM2, _) => "xmm2",
+            (Rst::XMM3, _) => "xmm3",
+            (Rst::XMM4, _) => "xmm4",
+            (Rst::XMM5, _) => "xmm5",
+            (Rst::XMM6, _) => "xmm6",
+            (Rst::XMM7, _) => "xmm7",
+            (Rst::XMM8, _) => "xmm8",
+            (Rst::XMM9, _) => "xmm9",
+            (Rst::XMM10, _) => "xmm10",
+            (Rst::XMM11, _) => "xmm11",
+            (Rst::XMM12, _) => "xmm12",
+            (Rst::XMM13, _) => "xmm13",
+            (Rst::XMM14, _) => "xmm14",
+            (Rst::XMM15, _) => "xmm15",
+        }
+    }
+
+    /// 機械語の中でのレジスタの番号。8 以上は REX プレフィックスで上位のビットを指定する
+    pub fn number(self) -> u8 {
         match self {
-            Rst::RAX => "rax",
-            Rst::RDX => "rdx",
-            Rst::RCX => "rcx",
-            Rst::RDI => "rdi",
-            Rst::RSI => "rsi",
-            Rst::R8 => "r8",
-            Rst::R9 => "r9",
-            Rst::R10 => "r10",
-            Rst::R11 => "r11",
-            Rst::R12 => "r12",
-            Rst::R13 => "r13",
-            Rst::R14 => "r14",
-            Rst::R15 => "r15",
+            Rst::RAX => 0,
+            Rst::RCX => 1,
+            Rst::RDX => 2,
+            Rst::RBX => 3,
+            Rst::RSP => 4,
+            Rst::RBP => 5,
+            Rst::RSI => 6,
+            Rst::RDI => 7,
+            Rst::R8 => 8,
+            Rst::R9 => 9,
+            Rst::R10 => 10,
+            Rst::R11 => 11,
+            Rst::R12 => 12,
+            Rst::R13 => 13,
+            Rst::R14 => 14,
+            Rst::R15 => 15,
+            Rst::XMM0 => 0,
+            Rst::XMM1 => 1,
+            Rst::XMM2 => 2,
+            Rst::XMM3 => 3,
+            Rst::XMM4 => 4,
+            Rst::XMM5 => 5,
+            Rst::XMM6 => 6,
+            Rst::XMM7 => 7,
+            Rst::XMM8 => 8,
+            Rst::XMM9 => 9,
+            Rst::XMM10 => 10,
+            Rst::XMM11 => 11,
+            Rst::XMM12 => 12,
+            Rst::XMM13 => 13,
+            Rst::XMM14 => 14,
+            Rst::XMM15 => 15,
         }
     }
 
-    pub fn to_string(&self) -> String {
-        self.as_str().to_string()
+    /// System V ABI で、呼ばれた関数が値を保存しておく必要のあるレジスタか。SSE レジスタは全て caller-saved
+    pub fn is_callee_saved(self) -> bool {
+        matches!(
+            self,
+            Rst::RBX | Rst::R12 | Rst::R13 | Rst::R14 | Rst::R15 | Rst::RBP
+        )
+    }
+}
+
+impl std::fmt::Display for Rst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 // println!("{}", r.as_str()); // 出力: rax
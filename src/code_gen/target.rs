@@ -0,0 +1,117 @@
+use super::syscall::SYSCALL;
+
+/// 出力するアセンブリの対象となる OS ごとの違い
+pub trait Target {
+    /// `--target` で指定する名前
+    fn name(&self) -> &'static str;
+
+    /// nasm の `-f` に渡すオブジェクトファイルの形式
+    fn object_format(&self) -> &'static str;
+
+    /// システムコール番号。アセンブリに即値として埋め込む
+    fn syscall_number(&self, syscall: SYSCALL) -> &'static str;
+
+    /// ソースコード上の関数名をアセンブリ上のシンボル名にする
+    fn symbol_name(&self, fn_name: &str) -> String;
+
+    /// ファイル先頭に置く宣言
+    fn directives(&self) -> Vec<String> {
+        vec![
+            format!("global {}", self.symbol_name("main")),
+            "default rel".to_string(),
+        ]
+    }
+}
+
+/// macOS (Mach-O)。BSD系のシステムコール番号にはクラスを表す `0x2000000` が付く
+pub struct MacOs;
+
+impl Target for MacOs {
+    fn name(&self) -> &'static str {
+        "x86_64-macos"
+    }
+
+    fn object_format(&self) -> &'static str {
+        "macho64"
+    }
+
+    fn syscall_number(&self, syscall: SYSCALL) -> &'static str {
+        match syscall {
+            SYSCALL::WRITE => "0x2000004",
+            SYSCALL::EXIT => "0x2000001",
+        }
+    }
+
+    fn symbol_name(&self, fn_name: &str) -> String {
+        // Cから呼ばれる main だけ、Mach-Oの慣習に合わせて `_` を付ける
+        if fn_name == "main" {
+            "_main".to_string()
+        } else {
+            mangle(fn_name)
+        }
+    }
+}
+
+/// Linux (ELF)。`main` を libc の `_start` から呼んでもらう
+pub struct Linux;
+
+impl Target for Linux {
+    fn name(&self) -> &'static str {
+        "x86_64-linux"
+    }
+
+    fn object_format(&self) -> &'static str {
+        "elf64"
+    }
+
+    fn syscall_number(&self, syscall: SYSCALL) -> &'static str {
+        match syscall {
+            SYSCALL::WRITE => "1",
+            SYSCALL::EXIT => "60",
+        }
+    }
+
+    fn symbol_name(&self, fn_name: &str) -> String {
+        if fn_name == "main" {
+            fn_name.to_string()
+        } else {
+            mangle(fn_name)
+        }
+    }
+
+    fn directives(&self) -> Vec<String> {
+        vec![
+            format!("global {}", self.symbol_name("main")),
+            "default rel".to_string(),
+            // スタックを実行可能にしなくてよいことをリンカに伝える
+            "section .note.GNU-stack noalloc noexec nowrite progbits".to_string(),
+        ]
+    }
+}
+
+/// `main` 以外の関数のシンボル名。`rax` や `db` のようなレジスタ名・疑似命令や、
+/// `_start` のような実行時の処理と重ならないよう、識別子に使えない `.` を含む名前にする
+fn mangle(fn_name: &str) -> String {
+    format!("fn.{}", fn_name)
+}
+
+pub const TARGET_NAMES: [&str; 2] = ["x86_64-macos", "x86_64-linux"];
+
+/// `--target` の名前からターゲットを選ぶ
+pub fn target_from_name(name: &str) -> Option<Box<dyn Target>> {
+    let targets: [Box<dyn Target>; 2] = [Box::new(MacOs), Box::new(Linux)];
+    targets.into_iter().find(|target| target.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_by_name() {
+        for name in TARGET_NAMES {
+            assert_eq!(target_from_name(name).unwrap().name(), name);
+        }
+        assert!(target_from_name("riscv64-linux").is_none());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::parser::token::Span;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// 診断の末尾に `= note:` や `= help:` として表示する補足
+#[derive(Debug, PartialEq, Clone)]
+pub enum SubDiagnostic {
+    Note(String),
+    /// 直し方の提案
+    Help(String),
+}
+
+/// コンパイル時のエラー。`render` でrustc風の表示に変換する
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// キャレットの横に表示する短い説明
+    pub label: Option<String>,
+    pub children: Vec<SubDiagnostic>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            label: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.children.push(SubDiagnostic::Note(note.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.children.push(SubDiagnostic::Help(help.into()));
+        self
+    }
+
+    /// 以下のような形式で文字列にする
+    /// ```text
+    /// error: expected `;`, found `}`
+    ///  --> sample.rs:3:14
+    ///   |
+    /// 3 |     let x = 1
+    ///   |              ^ expected `;`
+    /// ```
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let line_text = source
+            .lines()
+            .nth(self.span.line.saturating_sub(1))
+            .unwrap_or("");
+
+        let mut lines = Vec::<String>::new();
+        lines.push(format!("{}: {}", self.severity.as_str(), self.message));
+        lines.push(format!(
+            "{}--> {}:{}:{}",
+            gutter, filename, self.span.line, self.span.column
+        ));
+        lines.push(format!("{} |", gutter));
+        lines.push(format!("{} | {}", line_number, line_text));
+
+        let padding: String = line_text
+            .chars()
+            .take(self.span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat(self.underline_width(source, line_text));
+        let mut marker = format!("{} | {}{}", gutter, padding, underline);
+        if let Some(label) = &self.label {
+            marker.push(' ');
+            marker.push_str(label);
+        }
+        lines.push(marker);
+
+        if !self.children.is_empty() {
+            lines.push(format!("{} |", gutter));
+            for child in &self.children {
+                let (kind, message) = match child {
+                    SubDiagnostic::Note(message) => ("note", message),
+                    SubDiagnostic::Help(message) => ("help", message),
+                };
+                lines.push(format!("{} = {}: {}", gutter, kind, message));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// キャレットの数。複数行にまたがるSpanは1行目の末尾までで打ち切る
+    fn underline_width(&self, source: &str, line_text: &str) -> usize {
+        let spanned = source
+            .get(self.span.start..self.span.end)
+            .unwrap_or("")
+            .chars()
+            .take_while(|c| *c != '\n')
+            .count();
+        let rest_of_line = line_text
+            .chars()
+            .count()
+            .saturating_sub(self.span.column.saturating_sub(1));
+        spanned.min(rest_of_line).max(1)
+    }
+}
+
+/// 複数の診断をまとめて表示用の文字列にする
+pub fn render_all(diagnostics: &[Diagnostic], source: &str, filename: &str) -> String {
+    diagnostics
+        .iter()
+        .map(|d| d.render(source, filename))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "fn main() {\n    let x = 1\n}";
+        let diagnostic = Diagnostic::error(
+            "expected `;`, found `}`",
+            Span {
+                start: 26,
+                end: 27,
+                line: 3,
+                column: 1,
+            },
+        )
+        .with_label("expected `;`")
+        .with_note("statements are terminated by `;`")
+        .with_help("add `;` here");
+        let expected = [
+            "error: expected `;`, found `}`",
+            " --> sample.rs:3:1",
+            "  |",
+            "3 | }",
+            "  | ^ expected `;`",
+            "  |",
+            "  = note: statements are terminated by `;`",
+            "  = help: add `;` here",
+        ]
+        .join("\n");
+        assert_eq!(diagnostic.render(source, "sample.rs"), expected);
+    }
+
+    #[test]
+    fn test_render_underlines_whole_span() {
+        let source = "fn main() {\n    let x: bool = 1;\n}";
+        let diagnostic = Diagnostic::error(
+            "cannot find type `bool` in this scope",
+            Span {
+                start: 23,
+                end: 27,
+                line: 2,
+                column: 12,
+            },
+        );
+        let rendered = diagnostic.render(source, "sample.rs");
+        assert_eq!(rendered.lines().last().unwrap(), "  |            ^^^^");
+    }
+}
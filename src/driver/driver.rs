@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::options::{EmitKind, Options};
+use super::toolchain::write_lines;
+use crate::code_gen::code_gen::{AsmCode, CodegenOptions, generate_code};
+use crate::diagnostic::diagnostic::{Diagnostic, render_all};
+use crate::libs;
+use crate::parser::{lexer, parser};
+use crate::resolve::resolve::resolve_program;
+use crate::thir::typeck::check_program;
+
+/// `options` の通りにコンパイルして出力を書き出す。失敗したら表示するメッセージを返す
+pub fn compile(options: &Options) -> Result<(), String> {
+    let source_code = read_source(options)?;
+    let abort = |diagnostics: Vec<Diagnostic>| abort_message(&diagnostics, &source_code, options);
+    if options.emit.contains(&EmitKind::Tokens) {
+        let tokens = lexer::to_token_stream(&source_code).map_err(abort)?;
+        let lines: Vec<String> = tokens
+            .iter()
+            .map(|t| format!("{}:{}\t{:?}", t.span.line, t.span.column, t.token))
+            .collect();
+        write_output(options, EmitKind::Tokens, &lines)?;
+    }
+    let program = parser::parse(&source_code).map_err(abort)?;
+    if options.emit.contains(&EmitKind::Ast) {
+        write_output(options, EmitKind::Ast, &[format!("{:#?}", program)])?;
+    }
+    if options
+        .emit
+        .iter()
+        .all(|kind| matches!(kind, EmitKind::Tokens | EmitKind::Ast))
+    {
+        return Ok(());
+    }
+    let asm_code = compile_source(&source_code, &codegen_options(options)).map_err(abort)?;
+    if options.emit.contains(&EmitKind::Ir) {
+        write_output(options, EmitKind::Ir, &asm_code.serialize_ir())?;
+    }
+    if options.emit.contains(&EmitKind::Asm) {
+        write_output(options, EmitKind::Asm, &asm_code.serialize())?;
+    }
+    let (emit_obj, emit_exe) = (
+        options.emit.contains(&EmitKind::Obj),
+        options.emit.contains(&EmitKind::Exe),
+    );
+    if emit_obj || emit_exe {
+        with_temp_dir(|temp_dir| {
+            let target = options.target.as_ref();
+            let obj_path = options.output_path(EmitKind::Obj);
+            if emit_obj {
+                options
+                    .toolchain
+                    .assemble(&asm_code, target, temp_dir, &obj_path)?;
+            }
+            if emit_exe {
+                let exe_path = options.output_path(EmitKind::Exe);
+                let obj_path = emit_obj.then_some(obj_path.as_path());
+                options
+                    .toolchain
+                    .link(&asm_code, target, temp_dir, obj_path, &exe_path)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// `likerustc run`。実行ファイルまで作って `args` を渡して実行し、終了コードを返す
+pub fn run(options: &Options, args: &[String]) -> Result<i32, String> {
+    let source_code = read_source(options)?;
+    let asm_code = compile_source(&source_code, &codegen_options(options))
+        .map_err(|diagnostics| abort_message(&diagnostics, &source_code, options))?;
+    let target = options.target.as_ref();
+    with_temp_dir(|temp_dir| {
+        let exe_path = temp_dir.join(options.output_path(EmitKind::Exe).file_name().unwrap());
+        options
+            .toolchain
+            .link(&asm_code, target, temp_dir, None, &exe_path)?;
+        let status = std::process::Command::new(&exe_path)
+            .args(args)
+            .status()
+            .map_err(|e| format!("error: could not execute `{}`: {}", exe_path.display(), e))?;
+        if let Some(code) = status.code() {
+            return Ok(code);
+        }
+        // シグナルで終わった時は、シェルと同じく 128 + シグナル番号を終了コードにする
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            eprintln!(
+                "error: process didn't exit successfully: `{}` (signal: {})",
+                options.input, signal
+            );
+            return Ok(128 + signal);
+        }
+        Err(format!(
+            "error: process didn't exit successfully: {}",
+            status
+        ))
+    })
+}
+
+fn read_source(options: &Options) -> Result<String, String> {
+    libs::readfile(&options.input)
+        .map_err(|e| format!("error: couldn't read `{}`: {}", options.input, e))
+}
+
+fn codegen_options(options: &Options) -> CodegenOptions<'_> {
+    CodegenOptions {
+        target: options.target.as_ref(),
+        regalloc: options.regalloc,
+        source_name: &options.input,
+        overflow_checks: options.overflow_checks,
+        start_routine: options.toolchain.needs_start_routine(),
+    }
+}
+
+/// アセンブラとリンカに渡す途中のファイルは一時ディレクトリに置き、終わったら消す
+fn with_temp_dir<T>(f: impl FnOnce(&Path) -> Result<T, String>) -> Result<T, String> {
+    // 同じプロセスの中で何度呼ばれても重ならないよう、通し番号を付ける
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let temp_dir: PathBuf = std::env::temp_dir().join(format!(
+        "likerustc-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&temp_dir)
+        .map_err(|e| format!("error: couldn't create `{}`: {}", temp_dir.display(), e))?;
+    let result = f(&temp_dir);
+    std::fs::remove_dir_all(&temp_dir).ok();
+    result
+}
+
+/// 字句解析から コード生成までを行う
+pub fn compile_source(
+    source_code: &str,
+    options: &CodegenOptions,
+) -> Result<AsmCode, Vec<Diagnostic>> {
+    let program = parser::parse(source_code)?;
+    let resolutions = resolve_program(&program)?;
+    let types = check_program(&program, &resolutions)?;
+    generate_code(&program, &resolutions, &types, options)
+}
+
+/// rustc と同じく、診断の後にエラーの数を添える
+fn abort_message(diagnostics: &[Diagnostic], source_code: &str, options: &Options) -> String {
+    let rendered = render_all(diagnostics, source_code, &options.input);
+    let summary = match diagnostics.len() {
+        1 => "error: aborting due to 1 previous error".to_string(),
+        n => format!("error: aborting due to {} previous errors", n),
+    };
+    format!("{}\n\n{}", rendered, summary)
+}
+
+fn write_output(options: &Options, kind: EmitKind, lines: &[String]) -> Result<(), String> {
+    write_lines(&options.output_path(kind), lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::regalloc::RegAlloc;
+    use crate::code_gen::target::MacOs;
+    use crate::driver::options::{Command, parse_args};
+
+    #[test]
+    fn for_test() {
+        let filename = "./src/parser/test/sample.txt";
+        let source_code = libs::readfile(filename).unwrap();
+        let options = CodegenOptions {
+            target: &MacOs,
+            regalloc: RegAlloc::Linear,
+            source_name: filename,
+            overflow_checks: true,
+            start_routine: false,
+        };
+        let _code = compile_source(&source_code, &options).unwrap();
+    }
+
+    /// 一時ディレクトリにソースを置き、コマンドライン引数の通りにコンパイルする
+    fn run_in_temp_dir(
+        name: &str,
+        source: &str,
+        args: &str,
+    ) -> (std::path::PathBuf, Result<(), String>) {
+        let dir = std::env::temp_dir().join(format!("likerustc_driver_{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("main.rs");
+        std::fs::write(&input, source).unwrap();
+        let mut args: Vec<String> = args
+            .split_whitespace()
+            .map(|arg| arg.replace("$DIR", dir.to_str().unwrap()))
+            .collect();
+        args.push(input.to_str().unwrap().to_string());
+        let Ok(Command::Compile(options)) = parse_args(&args) else {
+            panic!("invalid arguments: {:?}", args);
+        };
+        (dir, compile(&options))
+    }
+
+    #[test]
+    fn test_emit_outputs() {
+        let (dir, result) = run_in_temp_dir(
+            "emit",
+            "fn main() { let x = 1; }",
+            "--emit=tokens,ir,asm -o $DIR/out",
+        );
+        assert_eq!(result, Ok(()));
+        let asm = std::fs::read_to_string(dir.join("out.asm")).unwrap();
+        assert!(asm.contains("global _main"));
+        let ir = std::fs::read_to_string(dir.join("out.ir")).unwrap();
+        assert!(ir.starts_with("main:\n    mov %v0, 1\n"));
+        let tokens = std::fs::read_to_string(dir.join("out.tokens")).unwrap();
+        assert!(tokens.starts_with("1:1\tFn\n1:4\tIdentifier(\"main\")\n"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// ELF のオブジェクトファイルは外部のツール無しで書き出せる
+    #[test]
+    fn test_emit_builtin_object() {
+        let (dir, result) = run_in_temp_dir(
+            "obj",
+            "fn main() { println!(\"hi\"); }",
+            "--target x86_64-linux --emit=obj -o $DIR/out.o",
+        );
+        assert_eq!(result, Ok(()));
+        let object = std::fs::read(dir.join("out.o")).unwrap();
+        assert_eq!(object[..4], [0x7F, b'E', b'L', b'F']);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// 組み込みのアセンブラで作ったオブジェクトファイルは `_start` を持たず、C のランタイムとリンクできる
+    #[test]
+    fn test_link_builtin_object_with_cc() {
+        let has_cc = std::process::Command::new("cc")
+            .arg("--version")
+            .output()
+            .is_ok_and(|o| o.status.success());
+        if !cfg!(target_os = "linux") || !has_cc {
+            return;
+        }
+        let (dir, result) = run_in_temp_dir(
+            "cc",
+            "fn main() -> i32 { println!(\"{}\", 6 * 7); return 3; }",
+            "--target x86_64-linux --emit=obj -o $DIR/main.o",
+        );
+        assert_eq!(result, Ok(()));
+        let status = std::process::Command::new("cc")
+            .arg(dir.join("main.o"))
+            .arg("-o")
+            .arg(dir.join("main"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = std::process::Command::new(dir.join("main"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"42\n");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// Linux では外部のツール無しで、実行できるファイルまで作れる
+    #[test]
+    fn test_emit_builtin_executable() {
+        if !cfg!(target_os = "linux") {
+            return;
+        }
+        let (dir, result) = run_in_temp_dir(
+            "exe",
+            "fn main() -> i32 { println!(\"{}\", 6 * 7); return 3; }",
+            "--target x86_64-linux --emit=exe -o $DIR/main",
+        );
+        assert_eq!(result, Ok(()));
+        let output = std::process::Command::new(dir.join("main"))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"42\n");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_overflow_checks_only_at_opt_level_0() {
+        let source = "fn main() { let x = 1; let y = x + 2; }";
+        for (level, checked) in [(0, true), (1, false), (2, false)] {
+            let (dir, result) = run_in_temp_dir(
+                &format!("opt_level_{}", level),
+                source,
+                &format!("-O{} -o $DIR/out.asm", level),
+            );
+            assert_eq!(result, Ok(()));
+            let asm = std::fs::read_to_string(dir.join("out.asm")).unwrap();
+            let has_checks = asm
+                .lines()
+                .any(|line| line.starts_with("    jo ") || line.starts_with("    jc "));
+            assert_eq!(has_checks, checked, "-O{}", level);
+            std::fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        let (dir, result) = run_in_temp_dir("errors", "fn main() { let x = ; }", "-o $DIR/out");
+        let message = result.unwrap_err();
+        assert!(message.starts_with("error: expected expression, found `;`"));
+        assert!(message.ends_with("\n\nerror: aborting due to 1 previous error"));
+        assert!(!dir.join("out").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// `as` と `ld` だけで実行ファイルを作り、`main` の戻り値が終了コードになることを確かめる。
+    /// レジスタ名や疑似命令と同じ名前の関数も、GNU as に別のものとして読まれない
+    #[test]
+    fn test_run_with_gnu_tools() {
+        let has_as = std::process::Command::new("as")
+            .arg("--version")
+            .output()
+            .is_ok_and(|o| o.status.success());
+        if !cfg!(target_os = "linux") || !has_as {
+            return;
+        }
+        let dir = std::env::temp_dir().join("likerustc_driver_run");
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("main.rs");
+        let programs = [
+            ("fn main() -> i32 { print!(\"hi\"); return 7; }", 7),
+            (
+                "fn db() -> i32 { return 4; }
+                 fn equ() -> i32 { return 1; }
+                 fn rax() -> i32 { return db() + equ(); }
+                 fn main() -> i32 { return rax(); }",
+                5,
+            ),
+        ];
+        for (source, expected) in programs {
+            std::fs::write(&input, source).unwrap();
+            let args: Vec<String> = format!(
+                "run --target x86_64-linux -C assembler=as -C linker=ld {}",
+                input.display()
+            )
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+            let Ok(Command::Run(options, program_args)) = parse_args(&args) else {
+                panic!("invalid arguments: {:?}", args);
+            };
+            assert_eq!(run(&options, &program_args), Ok(expected));
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,3 @@
+pub mod driver;
+pub mod options;
+pub mod toolchain;
@@ -0,0 +1,390 @@
+use std::path::PathBuf;
+
+use super::toolchain::Toolchain;
+use crate::code_gen::regalloc::RegAlloc;
+use crate::code_gen::target::{TARGET_NAMES, Target, target_from_name};
+
+/// `--target` を省略したときの出力先
+const DEFAULT_TARGET: &str = "x86_64-macos";
+
+/// `-O` を省略したときの最適化レベル。
+/// `-O0` は全ての値をスタックに置いて桁あふれを確かめ、`-O1` からはレジスタ割り当てをして桁あふれを確かめない
+const DEFAULT_OPT_LEVEL: u8 = 1;
+
+pub const USAGE: &str = "\
+Usage: likerustc [OPTIONS] <source_file>
+       likerustc run [OPTIONS] <source_file> [-- <args>...]
+
+Options:
+    -o <path>              Write output to <path>
+    --emit <kinds>         Comma separated list of outputs to produce:
+                           tokens, ast, ir, asm, obj, exe (default: asm)
+    -O0, -O1, -O2          Optimization level (default: 1). -O is the same as -O2
+    --target <target>      x86_64-macos (default) or x86_64-linux
+    --regalloc <name>      naive or linear (default: depends on -O)
+    -C overflow-checks[=yes|no]
+    -C opt-level=<0|1|2>
+    -C assembler=<path>    nasm, or GNU as when the name is not nasm or yasm
+                           (default: built in for x86_64-linux, nasm otherwise)
+    -C linker=<path>       cc, or a plain ld when the name ends with `ld`
+                           (default: built in for x86_64-linux, cc otherwise)
+    -h, --help             Print this message";
+
+/// `--emit` で選ぶ出力の種類
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EmitKind {
+    /// 字句解析の結果
+    Tokens,
+    /// 構文解析の結果
+    Ast,
+    /// レジスタ割り当て前の命令列
+    Ir,
+    /// nasm 用のアセンブリ
+    Asm,
+    /// アセンブルしたオブジェクトファイル
+    Obj,
+    /// リンクした実行ファイル
+    Exe,
+}
+
+impl EmitKind {
+    pub const NAMES: [&str; 6] = ["tokens", "ast", "ir", "asm", "obj", "exe"];
+
+    pub fn from_name(name: &str) -> Option<EmitKind> {
+        let kind = match name {
+            "tokens" => EmitKind::Tokens,
+            "ast" => EmitKind::Ast,
+            "ir" => EmitKind::Ir,
+            "asm" => EmitKind::Asm,
+            "obj" => EmitKind::Obj,
+            "exe" => EmitKind::Exe,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// 出力ファイルの拡張子。実行ファイルには付けない
+    pub fn extension(self) -> &'static str {
+        match self {
+            EmitKind::Tokens => "tokens",
+            EmitKind::Ast => "ast",
+            EmitKind::Ir => "ir",
+            EmitKind::Asm => "asm",
+            EmitKind::Obj => "o",
+            EmitKind::Exe => "",
+        }
+    }
+}
+
+/// コマンドライン引数を読んだ結果
+pub struct Options {
+    pub input: String,
+    /// `-o` で指定した出力先
+    pub output: Option<PathBuf>,
+    /// 出力する種類。指定された順に並べ、重複は除く
+    pub emit: Vec<EmitKind>,
+    pub target: Box<dyn Target>,
+    pub regalloc: RegAlloc,
+    pub overflow_checks: bool,
+    pub toolchain: Toolchain,
+}
+
+impl Options {
+    /// `kind` の出力先。`-o` が無ければ入力ファイル名の拡張子を変えたものにし、
+    /// 複数の種類を出力する時は `-o` のファイル名の拡張子を種類ごとに変える
+    pub fn output_path(&self, kind: EmitKind) -> PathBuf {
+        match &self.output {
+            Some(output) if self.emit.len() == 1 || kind == EmitKind::Exe => output.clone(),
+            Some(output) => output.with_extension(kind.extension()),
+            None => {
+                let stem = PathBuf::from(&self.input)
+                    .file_stem()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("out"));
+                stem.with_extension(kind.extension())
+            }
+        }
+    }
+}
+
+/// コマンドラインの読み方の結果
+pub enum Command {
+    Compile(Options),
+    /// `run` の後の `--` より後ろは、実行するプログラムに渡す
+    Run(Options, Vec<String>),
+    Help,
+}
+
+/// プログラム名を除いたコマンドライン引数を読む。誤りがあればエラーメッセージを返す
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    if args.first().is_some_and(|arg| arg == "run") {
+        let (args, program_args) = match args.iter().position(|arg| arg == "--") {
+            Some(index) => (&args[1..index], &args[index + 1..]),
+            None => (&args[1..], &[][..]),
+        };
+        return Ok(match parse_options(args)? {
+            Command::Compile(options) if options.output.is_some() || options.emit.len() > 1 => {
+                return Err("`-o` and `--emit` cannot be used with `run`".to_string());
+            }
+            Command::Compile(options) => Command::Run(options, program_args.to_vec()),
+            command => command,
+        });
+    }
+    parse_options(args)
+}
+
+fn parse_options(args: &[String]) -> Result<Command, String> {
+    let mut target_name = DEFAULT_TARGET.to_string();
+    let mut regalloc = None;
+    let mut overflow_checks = None;
+    let mut opt_level = DEFAULT_OPT_LEVEL;
+    let mut output = None;
+    let mut emit = Vec::new();
+    let mut toolchain = Toolchain {
+        assembler: None,
+        linker: None,
+    };
+    let mut input = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        } else if let Some(name) = option_value(arg, "--target", &mut rest)? {
+            target_name = name;
+        } else if let Some(name) = option_value(arg, "--regalloc", &mut rest)? {
+            regalloc = Some(RegAlloc::from_name(&name).ok_or_else(|| {
+                format!(
+                    "unknown register allocator `{}` (available: {})",
+                    name,
+                    RegAlloc::NAMES.join(", ")
+                )
+            })?);
+        } else if let Some(kinds) = option_value(arg, "--emit", &mut rest)? {
+            for name in kinds.split(',') {
+                let kind = EmitKind::from_name(name).ok_or_else(|| {
+                    format!(
+                        "unknown emission type: `{}` - expected one of: {}",
+                        name,
+                        EmitKind::NAMES.join(", ")
+                    )
+                })?;
+                if !emit.contains(&kind) {
+                    emit.push(kind);
+                }
+            }
+        } else if let Some(path) = option_value(arg, "-o", &mut rest)? {
+            output = Some(PathBuf::from(path));
+        } else if let Some(flag) = option_value(arg, "-C", &mut rest)? {
+            match flag.split_once('=').unwrap_or((&flag, "")) {
+                ("opt-level", level) => opt_level = parse_opt_level(level)?,
+                ("assembler", path) => toolchain.assembler = Some(path.to_string()),
+                ("linker", path) => toolchain.linker = Some(path.to_string()),
+                _ => overflow_checks = Some(codegen_flag(&flag, "overflow-checks")?),
+            }
+        } else if arg == "-O" {
+            opt_level = 2;
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            opt_level = parse_opt_level(level)?;
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unrecognized option: `{}`", arg));
+        } else if input.is_some() {
+            return Err("multiple input filenames provided".to_string());
+        } else {
+            input = Some(arg.clone());
+        }
+    }
+    let input = input.ok_or("no input filename given")?;
+    let target = target_from_name(&target_name).ok_or_else(|| {
+        format!(
+            "unknown target `{}` (available: {})",
+            target_name,
+            TARGET_NAMES.join(", ")
+        )
+    })?;
+    if emit.is_empty() {
+        emit.push(EmitKind::Asm);
+    }
+    if toolchain.assembler.as_deref() == Some("") || toolchain.linker.as_deref() == Some("") {
+        return Err("`-C assembler` and `-C linker` need a path".to_string());
+    }
+    // 個別に指定したオプションは `-O` より優先する
+    Ok(Command::Compile(Options {
+        input,
+        output,
+        emit,
+        target,
+        regalloc: regalloc.unwrap_or(if opt_level == 0 {
+            RegAlloc::Naive
+        } else {
+            RegAlloc::Linear
+        }),
+        overflow_checks: overflow_checks.unwrap_or(opt_level == 0),
+        toolchain,
+    }))
+}
+
+/// `--name value` か `--name=value` の形のオプションなら値を返す
+fn option_value<'a>(
+    arg: &str,
+    name: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Result<Option<String>, String> {
+    if arg == name {
+        match rest.next() {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(format!("option `{}` requires an argument", name)),
+        }
+    } else {
+        Ok(arg
+            .strip_prefix(name)
+            .and_then(|value| value.strip_prefix('='))
+            .map(str::to_string))
+    }
+}
+
+fn parse_opt_level(level: &str) -> Result<u8, String> {
+    match level {
+        "0" | "1" | "2" => Ok(level.parse().unwrap()),
+        _ => Err(format!(
+            "optimization level needs to be between 0-2 (instead was `{}`)",
+            level
+        )),
+    }
+}
+
+/// `-C name` か `-C name=value` の形の、真偽値を取るコード生成のオプションを読む
+fn codegen_flag(flag: &str, name: &str) -> Result<bool, String> {
+    let (key, value) = flag.split_once('=').unwrap_or((flag, "yes"));
+    if key != name {
+        return Err(format!("unknown codegen option: `{}`", key));
+    }
+    match value {
+        "y" | "yes" | "on" | "true" => Ok(true),
+        "n" | "no" | "off" | "false" => Ok(false),
+        _ => Err(format!(
+            "incorrect value `{}` for codegen option `{}` - a boolean was expected",
+            value, name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        match parse_args(&args)? {
+            Command::Compile(options) => Ok(options),
+            Command::Run(..) | Command::Help => Err("not a compilation".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse("src/main.rs").unwrap();
+        assert_eq!(options.input, "src/main.rs");
+        assert_eq!(options.emit, vec![EmitKind::Asm]);
+        assert_eq!(options.target.name(), DEFAULT_TARGET);
+        assert_eq!(options.regalloc, RegAlloc::Linear);
+        assert!(!options.overflow_checks);
+        assert_eq!(
+            options.output_path(EmitKind::Asm),
+            PathBuf::from("main.asm")
+        );
+        assert_eq!(options.output_path(EmitKind::Exe), PathBuf::from("main"));
+    }
+
+    #[test]
+    fn test_emit_and_output() {
+        let options = parse("a.rs --emit=ir,asm,ir -o build/out").unwrap();
+        assert_eq!(options.emit, vec![EmitKind::Ir, EmitKind::Asm]);
+        assert_eq!(
+            options.output_path(EmitKind::Ir),
+            PathBuf::from("build/out.ir")
+        );
+        assert_eq!(
+            options.output_path(EmitKind::Asm),
+            PathBuf::from("build/out.asm")
+        );
+        let options = parse("-o out.s --emit asm a.rs").unwrap();
+        assert_eq!(options.output_path(EmitKind::Asm), PathBuf::from("out.s"));
+    }
+
+    #[test]
+    fn test_opt_levels() {
+        let options = parse("a.rs -O0").unwrap();
+        assert_eq!(
+            (options.regalloc, options.overflow_checks),
+            (RegAlloc::Naive, true)
+        );
+        let options = parse("a.rs -O1").unwrap();
+        assert_eq!(
+            (options.regalloc, options.overflow_checks),
+            (RegAlloc::Linear, false)
+        );
+        let options = parse("a.rs -O").unwrap();
+        assert_eq!(
+            (options.regalloc, options.overflow_checks),
+            (RegAlloc::Linear, false)
+        );
+        // 個別のオプションは `-O` の後に書いても前に書いても優先する
+        let options = parse("a.rs --regalloc=linear -C overflow-checks -C opt-level=0").unwrap();
+        assert_eq!(
+            (options.regalloc, options.overflow_checks),
+            (RegAlloc::Linear, true)
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse("").err().unwrap(), "no input filename given");
+        assert_eq!(
+            parse("a.rs b.rs").err().unwrap(),
+            "multiple input filenames provided"
+        );
+        assert_eq!(parse("a.rs -x").err().unwrap(), "unrecognized option: `-x`");
+        assert_eq!(
+            parse("a.rs -o").err().unwrap(),
+            "option `-o` requires an argument"
+        );
+        assert_eq!(
+            parse("a.rs --emit=llvm-ir").err().unwrap(),
+            "unknown emission type: `llvm-ir` - expected one of: tokens, ast, ir, asm, obj, exe"
+        );
+        assert_eq!(
+            parse("a.rs -O3").err().unwrap(),
+            "optimization level needs to be between 0-2 (instead was `3`)"
+        );
+        assert_eq!(
+            parse("a.rs -C debuginfo=2").err().unwrap(),
+            "unknown codegen option: `debuginfo`"
+        );
+        assert_eq!(parse("a.rs --help").err().unwrap(), "not a compilation");
+    }
+
+    #[test]
+    fn test_run_subcommand() {
+        let args: Vec<String> = "run -C linker=ld a.rs -- -x y"
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let Ok(Command::Run(options, program_args)) = parse_args(&args) else {
+            panic!("expected the run subcommand");
+        };
+        assert_eq!(options.input, "a.rs");
+        assert_eq!(options.toolchain.linker.as_deref(), Some("ld"));
+        assert_eq!(options.toolchain.assembler, None);
+        assert_eq!(program_args, vec!["-x", "y"]);
+        let args = vec![
+            "run".to_string(),
+            "-o".to_string(),
+            "x".to_string(),
+            "a.rs".to_string(),
+        ];
+        assert_eq!(
+            parse_args(&args).err().unwrap(),
+            "`-o` and `--emit` cannot be used with `run`"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::code_gen::code_gen::{AsmCode, START_FN};
+use crate::code_gen::elf::{write_executable, write_object};
+use crate::code_gen::target::Target;
+
+/// ELF 以外で `-C assembler` を省略したときのアセンブラ
+const DEFAULT_ASSEMBLER: &str = "nasm";
+
+/// 組み込みのリンカを使わないときに `-C linker` を省略したときのリンカ
+const DEFAULT_LINKER: &str = "cc";
+
+/// オブジェクトファイルと実行ファイルを作るのに使う外部のツール
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    /// `-C assembler` で指定したアセンブラ。省略すると ELF では組み込みのアセンブラを使う
+    pub assembler: Option<String>,
+    /// `-C linker` で指定したリンカ。アセンブラと共に省略すると ELF では組み込みのリンカを使う
+    pub linker: Option<String>,
+}
+
+impl Toolchain {
+    /// nasm と yasm は nasm の書き方、それ以外は GNU as の書き方のアセンブリを読む
+    fn uses_nasm_syntax(assembler: &str) -> bool {
+        let name = tool_name(assembler);
+        name.contains("nasm") || name.contains("yasm")
+    }
+
+    /// 外部のツールを使わずに、静的にリンクした実行ファイルを直接書き出すか
+    fn uses_builtin_linker(&self, target: &dyn Target) -> bool {
+        self.assembler.is_none() && self.linker.is_none() && target.object_format() == "elf64"
+    }
+
+    /// `ld` や `ld.lld` のようなリンカは C のランタイムをリンクしないので、`_start` を自分で用意する。
+    /// 組み込みのリンカはリンクする時に加えるので、オブジェクトファイルには含めない
+    pub fn needs_start_routine(&self) -> bool {
+        self.linker.as_deref().is_some_and(|linker| {
+            let name = tool_name(linker);
+            name.starts_with("ld") || name.ends_with("ld")
+        })
+    }
+
+    /// `obj_path` にオブジェクトファイルを作る。外部のアセンブラに渡すアセンブリは `temp_dir` に書き出す
+    pub fn assemble(
+        &self,
+        asm_code: &AsmCode,
+        target: &dyn Target,
+        temp_dir: &Path,
+        obj_path: &Path,
+    ) -> Result<(), String> {
+        let assembler = match &self.assembler {
+            Some(assembler) => assembler,
+            None if target.object_format() == "elf64" => {
+                return std::fs::write(obj_path, write_object(&asm_code.assemble()))
+                    .map_err(|e| format!("error: couldn't write `{}`: {}", obj_path.display(), e));
+            }
+            None => DEFAULT_ASSEMBLER,
+        };
+        let mut command = Command::new(assembler);
+        if Self::uses_nasm_syntax(assembler) {
+            let asm_path = temp_dir.join("out.asm");
+            write_lines(&asm_path, &asm_code.serialize())?;
+            command.args(["-f", target.object_format()]).arg(&asm_path);
+        } else {
+            let asm_path = temp_dir.join("out.s");
+            write_lines(&asm_path, &asm_code.serialize_gas(target))?;
+            if is_macho(target) {
+                command.args(["-arch", "x86_64"]);
+            }
+            command.arg(&asm_path);
+        }
+        command.arg("-o").arg(obj_path);
+        run_tool(command, "assembler", "-C assembler=as")
+    }
+
+    /// `exe_path` に実行ファイルを作る。外部のリンカを使う時は、アセンブル済みの `obj_path` が
+    /// 無ければ `temp_dir` でアセンブルしてからリンクする
+    pub fn link(
+        &self,
+        asm_code: &AsmCode,
+        target: &dyn Target,
+        temp_dir: &Path,
+        obj_path: Option<&Path>,
+        exe_path: &Path,
+    ) -> Result<(), String> {
+        if self.uses_builtin_linker(target) {
+            let executable = write_executable(&asm_code.assemble_executable(target), START_FN)?;
+            return write_executable_file(exe_path, &executable);
+        }
+        let obj_path = match obj_path {
+            Some(obj_path) => obj_path.to_path_buf(),
+            None => {
+                let obj_path = temp_dir.join("out.o");
+                self.assemble(asm_code, target, temp_dir, &obj_path)?;
+                obj_path
+            }
+        };
+        let mut command = Command::new(self.linker.as_deref().unwrap_or(DEFAULT_LINKER));
+        if is_macho(target) {
+            command.args(["-arch", "x86_64"]);
+        }
+        if self.needs_start_routine() {
+            command.args(["-e", START_FN]);
+            if is_macho(target) {
+                // macOS はシステムコールも libSystem を通して呼ぶ前提なので、リンクしておく
+                command.arg("-lSystem");
+            }
+        }
+        command.arg(&obj_path).arg("-o").arg(exe_path);
+        run_tool(command, "linker", "-C linker=ld")
+    }
+}
+
+/// パスを除いたツールの名前
+fn tool_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_macho(target: &dyn Target) -> bool {
+    target.object_format() == "macho64"
+}
+
+pub fn write_lines(path: &Path, lines: &[String]) -> Result<(), String> {
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    std::fs::write(path, contents)
+        .map_err(|e| format!("error: couldn't write `{}`: {}", path.display(), e))
+}
+
+/// 実行できるファイルとして書き出す
+fn write_executable_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("error: couldn't write `{}`: {}", path.display(), e);
+    std::fs::write(path, contents).map_err(error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).map_err(error)?;
+    }
+    Ok(())
+}
+
+/// 外部のツールを実行する。見つからない時や失敗した時はエラーメッセージを返す。
+/// 見つからない時は、代わりに使えるツールの指定の仕方を添える
+fn run_tool(mut command: Command, kind: &str, alternative: &str) -> Result<(), String> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().map_err(|e| {
+        format!(
+            "error: could not execute {} `{}`: {}\n\nhelp: install `{}`, or choose another {} with `{}`",
+            kind, name, e, name, kind, alternative
+        )
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("error: {} `{}` failed with {}", kind, name, status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_gen::target::{Linux, MacOs};
+
+    fn linker(linker: &str) -> Toolchain {
+        Toolchain {
+            assembler: None,
+            linker: Some(linker.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_tool_flavors() {
+        assert!(Toolchain::uses_nasm_syntax("nasm"));
+        assert!(Toolchain::uses_nasm_syntax("/usr/local/bin/yasm"));
+        assert!(!Toolchain::uses_nasm_syntax("x86_64-linux-gnu-as"));
+        assert!(!linker("cc").needs_start_routine());
+        assert!(!linker("/usr/bin/clang").needs_start_routine());
+        assert!(linker("ld").needs_start_routine());
+        assert!(linker("/usr/bin/ld.lld").needs_start_routine());
+        assert!(linker("x86_64-linux-gnu-ld").needs_start_routine());
+
+        // 組み込みのリンカは ELF だけを書ける
+        let builtin = Toolchain {
+            assembler: None,
+            linker: None,
+        };
+        assert!(builtin.uses_builtin_linker(&Linux));
+        assert!(!builtin.uses_builtin_linker(&MacOs));
+        // `_start` はリンクする時に加える
+        assert!(!builtin.needs_start_routine());
+        let with_as = Toolchain {
+            assembler: Some("as".to_string()),
+            linker: None,
+        };
+        assert!(!with_as.uses_builtin_linker(&Linux));
+    }
+
+    #[test]
+    fn test_missing_tool() {
+        let message = run_tool(
+            Command::new("likerustc-no-such-tool"),
+            "assembler",
+            "-C assembler=as",
+        )
+        .unwrap_err();
+        assert!(message.starts_with("error: could not execute assembler `likerustc-no-such-tool`"));
+        assert!(message.ends_with("with `-C assembler=as`"));
+    }
+}
@@ -0,0 +1,679 @@
+//! ソースコードをコンパイルし、アセンブル・リンクした実行ファイルの終了コードを確かめるテスト。
+//! Linux では組み込みのアセンブラとリンカを使い、それ以外でアセンブラが無ければアセンブリの生成までを確かめる
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use crate::code_gen::code_gen::{AsmCode, CodegenOptions, generate_code};
+use crate::code_gen::regalloc::RegAlloc;
+use crate::code_gen::target::{Linux, MacOs, Target};
+use crate::driver::toolchain::Toolchain;
+use crate::parser::parser::parse;
+use crate::resolve::resolve::resolve_program;
+use crate::thir::typeck::check_program;
+
+/// テストを実行しているマシン向けのターゲット
+fn host_target() -> &'static dyn Target {
+    if cfg!(target_os = "linux") {
+        &Linux
+    } else {
+        &MacOs
+    }
+}
+
+fn generate_with(source: &str, regalloc: RegAlloc, start_routine: bool) -> AsmCode {
+    let program = parse(source).expect("failed to parse");
+    let resolutions = resolve_program(&program).expect("failed to resolve names");
+    let types = check_program(&program, &resolutions).expect("failed to type check");
+    let options = CodegenOptions {
+        target: host_target(),
+        regalloc,
+        source_name: "main.rs",
+        overflow_checks: true,
+        start_routine,
+    };
+    generate_code(&program, &resolutions, &types, &options).expect("failed to generate code")
+}
+
+fn compile_to_asm(source: &str) -> String {
+    generate_with(source, RegAlloc::Linear, false)
+        .serialize()
+        .join("\n")
+        + "\n"
+}
+
+/// Linux では組み込みのアセンブラとリンカを使う。macOS では nasm、GNU as の順に探す
+fn available_toolchain() -> Option<Toolchain> {
+    let assembler = if cfg!(target_os = "linux") {
+        None
+    } else if cfg!(target_os = "macos") {
+        let (assembler, _) = [("nasm", "-v"), ("as", "--version")].into_iter().find(
+            |(assembler, version_flag)| {
+                Command::new(assembler)
+                    .arg(version_flag)
+                    .output()
+                    .map(|o| o.status.success())
+                    .unwrap_or(false)
+            },
+        )?;
+        Some(assembler.to_string())
+    } else {
+        return None;
+    };
+    Some(Toolchain {
+        assembler,
+        linker: None,
+    })
+}
+
+/// 全てのレジスタ割り当ての方法でコンパイルして実行し、終了コードが一致することを確かめて返す。
+/// ツールチェーンが無ければ `None`
+fn run(name: &str, source: &str) -> Option<i32> {
+    run_output(name, source).and_then(|output| output.status.code())
+}
+
+/// `run` と同じく実行し、終了コードと出力が一致することを確かめて実行結果を返す
+fn run_output(name: &str, source: &str) -> Option<Output> {
+    let naive = run_with(name, source, RegAlloc::Naive)?;
+    let linear = run_with(name, source, RegAlloc::Linear)?;
+    assert_eq!(
+        (naive.status.code(), &naive.stdout, &naive.stderr),
+        (linear.status.code(), &linear.stdout, &linear.stderr),
+        "register allocators disagree on {}",
+        name
+    );
+    Some(linear)
+}
+
+fn run_with(name: &str, source: &str, regalloc: RegAlloc) -> Option<Output> {
+    let Some(toolchain) = available_toolchain() else {
+        eprintln!("skipping execution of {}: no assembler is available", name);
+        return None;
+    };
+    let asm_code = generate_with(source, regalloc, toolchain.needs_start_routine());
+    let dir: PathBuf = std::env::temp_dir().join(format!("likerustc_e2e_{}_{:?}", name, regalloc));
+    std::fs::create_dir_all(&dir).unwrap();
+    let exe_path = dir.join("out");
+    toolchain
+        .link(&asm_code, host_target(), &dir, None, &exe_path)
+        .unwrap();
+    let output = Command::new(&exe_path).output().unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+    Some(output)
+}
+
+const FACTORIAL: &str = "
+fn factorial(n: i32) -> i32 {
+    let mut result = 1;
+    let mut i = 2;
+    loop {
+        if i > n {
+            break;
+        }
+        result *= i;
+        i += 1;
+    }
+    return result;
+}
+
+fn main() -> i32 {
+    return factorial(5);
+}
+";
+
+const FIBONACCI: &str = "
+fn fib(n: i32) -> i32 {
+    let mut a = 0;
+    let mut b = 1;
+    let mut i = 0;
+    while i < n {
+        let next = a + b;
+        a = b;
+        b = next;
+        i += 1;
+    }
+    return a;
+}
+
+fn main() -> i32 {
+    return fib(10);
+}
+";
+
+#[test]
+fn test_factorial_loop() {
+    let asm = compile_to_asm(FACTORIAL);
+    assert!(asm.contains(".loop_start_0:"));
+    assert!(asm.contains("    jmp .loop_end_1"));
+    if let Some(code) = run("factorial", FACTORIAL) {
+        assert_eq!(code, 120);
+    }
+}
+
+#[test]
+fn test_fibonacci_loop() {
+    let asm = compile_to_asm(FIBONACCI);
+    assert!(asm.contains(".while_start_0:"));
+    assert!(asm.contains("    jge .while_end_1"));
+    if let Some(code) = run("fibonacci", FIBONACCI) {
+        assert_eq!(code, 55);
+    }
+}
+
+#[test]
+fn test_if_else_chain_and_logical_operators() {
+    let source = "
+fn classify(n: i32) -> i32 {
+    if n == 0 || n == 100 {
+        return 1;
+    } else if n >= 10 && !(n == 50) {
+        return 2;
+    } else {
+        return 3;
+    }
+}
+
+fn main() -> i32 {
+    return classify(20);
+}
+";
+    if let Some(code) = run("classify", source) {
+        assert_eq!(code, 2);
+    }
+}
+
+#[test]
+fn test_continue_skips_rest_of_body() {
+    let source = "
+fn main() -> i32 {
+    let mut count = 0;
+    let mut i = 0;
+    let mut odd = 0;
+    while i < 7 {
+        i += 1;
+        if odd == 0 {
+            odd = 1;
+        } else {
+            odd = 0;
+        }
+        if !(odd == 1) {
+            continue;
+        }
+        count += 1;
+    }
+    return count;
+}
+";
+    if let Some(code) = run("continue", source) {
+        assert_eq!(code, 4);
+    }
+}
+
+#[test]
+fn test_inferred_bool_local_in_condition() {
+    let source = "
+fn main() -> i32 {
+    let limit = 5;
+    let flag = 3 > limit;
+    if !flag && limit == 5 {
+        return 7;
+    }
+    return 1;
+}
+";
+    if let Some(code) = run("bool_local", source) {
+        assert_eq!(code, 7);
+    }
+}
+
+#[test]
+fn test_block_statement_shadowing() {
+    let source = "
+fn main() -> i32 {
+    let x = 1;
+    let mut sum = 0;
+    {
+        let x = 10;
+        sum += x;
+    }
+    sum += x;
+    return sum;
+}
+";
+    if let Some(code) = run("block", source) {
+        assert_eq!(code, 11);
+    }
+}
+
+/// 関数名はレジスタ名や疑似命令、実行時の処理の名前と重なっても別のシンボルになる
+const SYMBOL_NAMES: &str = "
+fn db() -> i32 {
+    return 4;
+}
+
+fn equ(x: i32) -> i32 {
+    return x + 1;
+}
+
+fn rax() -> i32 {
+    let x = db();
+    return equ(x);
+}
+
+fn _start() -> i32 {
+    return 1;
+}
+
+fn main() -> i32 {
+    return rax() + _start();
+}
+";
+
+#[test]
+fn test_function_names_like_registers_and_directives() {
+    let asm = compile_to_asm(SYMBOL_NAMES);
+    assert!(asm.contains("    call fn.rax\n"));
+    assert!(asm.contains("\nfn.db:\n"));
+    if let Some(code) = run("symbol_names", SYMBOL_NAMES) {
+        assert_eq!(code, 6);
+    }
+}
+
+#[test]
+fn test_spilled_locals_and_callee_saved_rsts() {
+    let source = "
+fn sum8(n: i32) -> i32 {
+    let a = n + 1;
+    let b = a + 1;
+    let c = b + 1;
+    let d = c + 1;
+    let e = d + 1;
+    let f = e + 1;
+    let g = f + 1;
+    let h = g + 1;
+    let deep = a + (b + (c + (d + (e + (f + (g + h))))));
+    if deep > 40 && h == n + 8 {
+        return deep;
+    }
+    return 0;
+}
+
+fn main() -> i32 {
+    let a = 1;
+    let b = 2;
+    let c = 3;
+    let d = sum8(1);
+    return c + d;
+}
+";
+    if let Some(code) = run("spill", source) {
+        assert_eq!(code, 47);
+    }
+}
+
+#[test]
+fn test_nested_calls_and_stack_arguments() {
+    let source = "
+fn add(a: i32, b: i32) -> i32 {
+    return a + b;
+}
+
+fn sum8(a: i32, b: i32, c: i32, d: i32, e: i32, f: i32, g: i32, h: i32) -> i32 {
+    return a + (b + (c + (d + (e + (f + (g + h))))));
+}
+
+fn sum7(a: i32, b: i32, c: i32, d: i32, e: i32, f: i32, g: i32) -> i32 {
+    return sum8(g, f, e, d, c, b, a, add(g, 1));
+}
+
+fn main() -> i32 {
+    let x = 2;
+    let nested = add(add(x, add(1, 1)), add(add(1, 2), x + 1));
+    let wide = sum8(1, add(1, 1), add(add(1, 2), 0), 4, 5, 6, 7, add(x, add(3, 3)));
+    return nested + wide + sum7(1, 2, 3, 4, 5, 6, add(10, x));
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("    push "));
+    if let Some(code) = run("nested_calls", source) {
+        assert_eq!(code, 92);
+    }
+}
+
+#[test]
+fn test_arithmetic_operators() {
+    let source = "
+fn calc(a: i32, b: i32) -> i32 {
+    let q = a / b;
+    let r = a % b;
+    return q * 10 + r - 3;
+}
+
+fn main() -> i32 {
+    let neg = 7 - 20;
+    let x = calc(47, 5);
+    let y = neg / 4;
+    let z = neg % 4;
+    return x + y * 2 + z;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("    cdq\n"));
+    if let Some(code) = run("arithmetic", source) {
+        // 割り算は 0 に向かって丸め、余りは割られる数と同じ符号になる
+        assert_eq!(code, 82);
+    }
+}
+
+#[test]
+fn test_divide_by_zero_panics_at_runtime() {
+    let source = "
+fn div(a: i32, b: i32) -> i32 {
+    return a / b;
+}
+
+fn main() -> i32 {
+    return div(1, 0);
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("attempt to divide by zero"));
+    if let Some(code) = run("divide_by_zero", source) {
+        assert_eq!(code, 101);
+    }
+}
+
+#[test]
+fn test_float_arithmetic_and_comparisons() {
+    let source = "
+const SCALE: f64 = 2.5e-1;
+
+fn area(w: f64, h: f64) -> f64 {
+    return w * h;
+}
+
+fn mix(a: i32, x: f64, b: i32, y: f64) -> f64 {
+    let s = x + y;
+    if a > b {
+        return s;
+    }
+    return -s;
+}
+
+fn many(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64, g: f64, h: f64, i: f64, j: i32, k: f64) -> f64 {
+    return a + b + c + d + e + f + g + h + i * 10.0 + k * 100.0;
+}
+
+fn main() -> i32 {
+    let mut total = 0.0;
+    let mut i = 0;
+    while i < 4 {
+        total = total + area(1.5, 2.0);
+        i += 1;
+    }
+    let nan = 0.0 / 0.0;
+    let mut code = 0;
+    if total == 12.0 {
+        code += 1;
+    }
+    if total > 11.5 && total <= 12.0 {
+        code += 2;
+    }
+    if nan == nan || nan < 1.0 || nan >= 1.0 {
+        code += 100;
+    }
+    if nan != nan {
+        code += 4;
+    }
+    if mix(1, 1.0, 2, 2.0) < -2.5 {
+        code += 8;
+    }
+    if many(1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 7, 3.0) == 328.0 {
+        code += 16;
+    }
+    if SCALE * 4.0 == 1.0 && -SCALE < 0.0 {
+        code += 32;
+    }
+    let zero = -0.0;
+    if 1.0 / zero < 0.0 {
+        code += 64;
+    }
+    return code;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("    ucomisd "));
+    if let Some(code) = run("float", source) {
+        // NaN との比較は != 以外偽になり、-0.0 で割ると負の無限大になる
+        assert_eq!(code, 127);
+    }
+}
+
+#[test]
+fn test_integer_types_and_casts() {
+    let source = "
+const MASK: u8 = 0xF0;
+const BIG: u64 = 18446744073709551615;
+
+fn widen(a: u8, b: i16) -> i64 {
+    return a as i64 * 1000 + b as i64;
+}
+
+fn half(x: u32) -> u32 {
+    return x / 2;
+}
+
+fn main() -> i32 {
+    let mut passed = 0;
+    if 300 as u8 == 44 {
+        passed += 1;
+    }
+    if -1i8 as u8 == 255 && 255u8 as i8 == -1 {
+        passed += 1;
+    }
+    let big: u32 = 3000000000;
+    if big > 5 && half(big) == 1500000000 && big % 7 == 4 {
+        passed += 1;
+    }
+    if -1i32 as u32 as u64 == 4294967295 && BIG > 0 && BIG as i64 == -1 {
+        passed += 1;
+    }
+    if -16i32 >> 2 == -4 && 0x80u8 >> 7 == 1 && 1u8 << 7 == 128 {
+        passed += 1;
+    }
+    if MASK | 0x0F == 255 && MASK & 0x3C == 0x30 && MASK ^ 0xFF == 0x0F && !0u8 == 255 && !5 == -6 {
+        passed += 1;
+    }
+    let small: i8 = -100;
+    if small / 3 == -33 && small % 3 == -1 && -small == 100 {
+        passed += 1;
+    }
+    if widen(200, -5) == 199995 && true as i32 + false as i32 == 1 {
+        passed += 1;
+    }
+    let nan = 0.0 / 0.0;
+    if 3.9 as i32 == 3 && -3.9 as u8 == 0 && 300.0 as u8 == 255 && nan as i32 == 0 && -2.5 as i16 == -2 {
+        passed += 1;
+    }
+    if 1e20 as i64 == 9223372036854775807 && -1e20 as i64 < 0 && 1e19 as u64 == 10000000000000000000 {
+        passed += 1;
+    }
+    if BIG as f64 == 18446744073709551615.0 && 18446744073709551615.0 as u64 == BIG && -1i32 as f64 == -1.0 {
+        passed += 1;
+    }
+    let x: usize = 7;
+    let y: isize = -7;
+    if x as isize + y == 0 && 10u8 as f64 / 4.0 == 2.5 {
+        passed += 1;
+    }
+    return passed;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("    movzx "));
+    assert!(asm.contains("    cvttsd2si "));
+    if let Some(code) = run("integer_types", source) {
+        // 整数同士の as は切り捨て、浮動小数点数からの as は飽和する
+        assert_eq!(code, 12);
+    }
+}
+
+#[test]
+fn test_overflow_panics_at_runtime() {
+    let source = "
+fn add(a: u8, b: u8) -> u8 {
+    return a + b;
+}
+
+fn main() -> i32 {
+    return add(200, 100) as i32;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("attempt to add with overflow"));
+    if let Some(code) = run("add_overflow", source) {
+        assert_eq!(code, 101);
+    }
+}
+
+#[test]
+fn test_wrapping_and_checked_methods() {
+    let source = "
+fn min_i32(one: i32) -> i32 {
+    return -2147483647 - one;
+}
+
+fn main() -> i32 {
+    let mut passed = 0;
+    let max: u8 = 255;
+    let one: u8 = 1;
+    if max.wrapping_add(one) == 0 && one.wrapping_sub(2) == 255 && 16u8.wrapping_mul(max) == 240 {
+        passed += 1;
+    }
+    let min = min_i32(1);
+    if min.wrapping_neg() == min && min.wrapping_div(-1) == min && min.wrapping_rem(-1) == 0 {
+        passed += 1;
+    }
+    if 1i32.wrapping_shl(33) == 2 && one.wrapping_neg() == 255 && -min.wrapping_sub(1) == -2147483647 {
+        passed += 1;
+    }
+    if max.checked_add(one).is_none() && max.checked_sub(one).unwrap() == 254 && one.checked_neg().is_none() {
+        passed += 1;
+    }
+    let big: u64 = 4294967296;
+    if big.checked_mul(big).unwrap_or(7) == 7 && big.checked_mul(3).is_some() && 3000000000u32.checked_mul(2).is_none() {
+        passed += 1;
+    }
+    if min.checked_div(-1).is_none() && min.checked_rem(0).is_none() && min.checked_div(2).unwrap() == -1073741824 {
+        passed += 1;
+    }
+    if one.checked_shl(8).is_none() && one.checked_shl(7).unwrap() == 128 && min.checked_mul(-1).unwrap_or(-1) == -1 {
+        passed += 1;
+    }
+    return passed;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("    jo "));
+    if let Some(code) = run("wrapping_and_checked", source) {
+        // `wrapping_*` は切り詰め、`checked_*` は桁あふれと 0 での割り算を `None` にする
+        assert_eq!(code, 7);
+    }
+}
+
+#[test]
+fn test_print_macros() {
+    let source = "
+fn square(x: i64) -> i64 {
+    return x * x;
+}
+
+fn main() -> i32 {
+    let answer = 42;
+    let big: u64 = 18446744073709551615;
+    let min: i64 = -9223372036854775808;
+    println!(\"answer = {}, negative = {}\", answer, -7);
+    println!(\"{} {} {}\", big, min, square(12));
+    print!(\"{{}} {} \", 0u8);
+    println!(\"{} and {}\", true, answer < 0);
+    println!(\"{}!\", \"str\");
+    eprintln!(\"error {}\", answer);
+    println!();
+    return 3;
+}
+";
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("call __likerustc_print_int"));
+    if let Some(output) = run_output("print_macros", source) {
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "answer = 42, negative = -7\n\
+             18446744073709551615 -9223372036854775808 144\n\
+             {} 0 true and false\n\
+             str!\n\
+             \n"
+        );
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "error 42\n");
+    }
+}
+
+#[test]
+fn test_string_and_char_literals() {
+    let source = r#"
+fn greet(name: &str) -> usize {
+    println!("hello, {}!", name);
+    return name.len();
+}
+
+fn upper(c: char) -> char {
+    if c >= 'a' && c <= 'z' {
+        return (c as u8 - 32) as char;
+    }
+    return c;
+}
+
+fn main() -> i32 {
+    let s = "tab\there \"quoted\"\\";
+    println!("{}", s);
+    let n = greet("wörld");
+    let bytes = b"hi\n\x00";
+    let smile = '\u{1F600}';
+    println!("{} {} {}{}{}", n, bytes.len(), upper('q'), 'é', smile);
+    return smile as i32 - 0x1F600 + "x".len() as i32;
+}
+"#;
+    let asm = compile_to_asm(source);
+    assert!(asm.contains("section .rodata"));
+    if let Some(output) = run_output("string_and_char_literals", source) {
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "tab\there \"quoted\"\\\nhello, wörld!\n6 4 Qé\u{1F600}\n"
+        );
+    }
+}
+
+#[test]
+fn test_comments_and_panic_locations() {
+    let source = "/// 2 つの数を足す
+fn add(a: u8, b: u8) -> u8 {
+    // 桁あふれするとパニックする
+    /* 複数行の
+       /* 入れ子の */ コメント */
+    return a + b; // ここ
+}
+
+fn main() -> i32 {
+    return add(200, 100) as i32;
+}
+";
+    let asm = compile_to_asm(source);
+    // 改行を残して読むので、パニックの位置は元の行と列になる
+    assert!(asm.contains("thread 'main' panicked at main.rs:6:12:"));
+    if let Some(output) = run_output("comments", source) {
+        assert_eq!(output.status.code(), Some(101));
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with(
+            "thread 'main' panicked at main.rs:6:12:\nattempt to add with overflow\n"
+        ));
+    }
+}
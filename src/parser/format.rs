@@ -0,0 +1,120 @@
+use crate::ast::program::Span;
+use crate::diagnostic::diagnostic::Diagnostic;
+
+/// `println!` などの書式文字列を分けたもの
+#[derive(Debug, PartialEq, Clone)]
+pub enum FormatPiece {
+    /// そのまま出力する文字列。`{{` と `}}` は1文字にしてある
+    Text(String),
+    /// `{}` を埋める、書式文字列の後の何番目の引数か
+    Arg(usize),
+}
+
+/// 書式文字列を文字列と `{}` に分ける。`{}` は前から順に引数を埋める。
+/// `{:?}` のような書式の指定はまだできない
+pub fn parse_format(format: &str, span: Span) -> Result<Vec<FormatPiece>, Diagnostic> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut arg_count = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => {
+                            return Err(Diagnostic::error(
+                                "invalid format string: expected `}` but string was terminated",
+                                span,
+                            )
+                            .with_label("expected `}` in format string")
+                            .with_note(
+                                "if you intended to print `{`, you can escape it using `{{`",
+                            ));
+                        }
+                    }
+                }
+                if !spec.is_empty() {
+                    return Err(Diagnostic::error(
+                        format!("invalid format string: `{{{}}}` is not supported yet", spec),
+                        span,
+                    )
+                    .with_label("only `{}` placeholders are supported"));
+                }
+                if !text.is_empty() {
+                    pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(FormatPiece::Arg(arg_count));
+                arg_count += 1;
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => {
+                return Err(
+                    Diagnostic::error("invalid format string: unmatched `}` found", span)
+                        .with_label("unmatched `}` in format string")
+                        .with_note("if you intended to print `}`, you can escape it using `}}`"),
+                );
+            }
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// 書式文字列の中の `{}` の数
+pub fn count_args(pieces: &[FormatPiece]) -> usize {
+    pieces
+        .iter()
+        .filter(|piece| matches!(piece, FormatPiece::Arg(_)))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: &str) -> Result<Vec<FormatPiece>, String> {
+        parse_format(format, Span::default()).map_err(|d| d.message)
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            parse("x = {}, {{y}} = {}!"),
+            Ok(vec![
+                FormatPiece::Text("x = ".to_string()),
+                FormatPiece::Arg(0),
+                FormatPiece::Text(", {y} = ".to_string()),
+                FormatPiece::Arg(1),
+                FormatPiece::Text("!".to_string()),
+            ])
+        );
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(count_args(&parse("{}{}").unwrap()), 2);
+        assert_eq!(
+            parse("{"),
+            Err("invalid format string: expected `}` but string was terminated".to_string())
+        );
+        assert_eq!(
+            parse("a } b"),
+            Err("invalid format string: unmatched `}` found".to_string())
+        );
+        assert_eq!(
+            parse("{:?}"),
+            Err("invalid format string: `{:?}` is not supported yet".to_string())
+        );
+    }
+}
@@ -1,64 +1,520 @@
 use super::token::*;
+use crate::diagnostic::diagnostic::Diagnostic;
 
-pub fn to_token_stream(token_chunks: Vec<String>) -> Vec<Token> {
-    let mut tokens = Vec::<Token>::new();
-    let mut chunks = token_chunks.into_iter().peekable();
-
-    while chunks.peek() != None {
-        let chunk = chunks.next().unwrap();
-        match chunk.as_str() {
-            " " => continue,
-            "{" => tokens.push(Token::LBrace),
-            "}" => tokens.push(Token::RBrace),
-            "(" => tokens.push(Token::LParentheses),
-            ")" => tokens.push(Token::RParentheses),
-            "[" => tokens.push(Token::LBracket),
-            "]" => tokens.push(Token::RBracket),
-            "<" => tokens.push(Token::LAngleBracket),
-            ">" => tokens.push(Token::RAngleBracket),
-            ":" => tokens.push(Token::Collon),
-            ";" => tokens.push(Token::Semicolon),
-            "," => tokens.push(Token::Comma),
-            "+" => tokens.push(Token::Operator(Operator::Plus)),
-            "-" => tokens.push(Token::Operator(Operator::Minus)),
-            "*" => tokens.push(Token::Operator(Operator::Asterisk)),
-            "/" => tokens.push(Token::Operator(Operator::Slash)),
-            "fn" => tokens.push(Token::Fn),
-            "let" => tokens.push(Token::Let),
-            "const" => tokens.push(Token::Const),
-            "i32" => tokens.push(Token::Type(Type::I32)),
-            "f64" => tokens.push(Token::Type(Type::F64)),
-            "return" => tokens.push(Token::Return),
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Lexer {
+            source,
+            pos: 0,
+            line: 1,
+            column: 1,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn span_from(&self, start: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end: self.pos,
+            line,
+            column,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn bump_while(&mut self, pred: impl Fn(char) -> bool) {
+        while let Some(c) = self.peek() {
+            if !pred(c) {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    /// 次のトークンを1つ読み進める。入力の終端では `None` を返す
+    fn next_token(&mut self) -> Option<SpannedToken> {
+        loop {
+            self.bump_while(char::is_whitespace);
+            let start = self.pos;
+            let line = self.line;
+            let column = self.column;
+            let c = self.bump()?;
+            match self.scan_token(c, start, line, column) {
+                Some(token) => {
+                    return Some(SpannedToken {
+                        token,
+                        span: self.span_from(start, line, column),
+                    });
+                }
+                None => continue,
+            }
+        }
+    }
+
+    /// 数値リテラルを読む。小数点か指数を含むか、`f64` のサフィックスが付いていれば浮動小数点数のリテラルにする
+    fn number_literal(&mut self, start: usize) -> Token {
+        // 数値の直後に続く英数字 (0x.. や 10u8 のサフィックス) もまとめて1つのリテラルにする
+        self.bump_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let is_hex = self.source[start..].starts_with("0x");
+        let mut rest = self.source[self.pos..].chars();
+        // `1.5` の小数部。`x.foo()` のようにドットの後が数字でなければ小数点ではない
+        if !is_hex && rest.next() == Some('.') && rest.next().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            self.bump_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        }
+        let text = &self.source[start..self.pos];
+        // `1e-3` の指数の符号
+        let mut rest = self.source[self.pos..].chars();
+        if !is_hex
+            && exponent_start(text) == Some(text.len() - 1)
+            && matches!(rest.next(), Some('+' | '-'))
+            && rest.next().is_some_and(|c| c.is_ascii_digit())
+        {
+            self.bump();
+            self.bump_while(|c| c.is_ascii_digit() || c == '_');
+        }
+        let text = self.source[start..self.pos].to_string();
+        if !is_hex
+            && (text.contains('.') || exponent_start(&text).is_some() || text.ends_with("f64"))
+        {
+            Token::FloatLiteral(text)
+        } else {
+            Token::Literal(text)
+        }
+    }
+
+    /// `c` から始まるトークンを読む。トークンにならない文字はエラーを記録して `None` を返す
+    fn scan_token(&mut self, c: char, start: usize, line: usize, column: usize) -> Option<Token> {
+        let token = match c {
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '(' => Token::LParentheses,
+            ')' => Token::RParentheses,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ':' => Token::Collon,
+            ';' => Token::Semicolon,
+            ',' => Token::Comma,
+            '.' => Token::Dot,
+            '+' => self.operator_or_assign(Operator::Plus),
+            '*' => self.operator_or_assign(Operator::Asterisk),
+            '/' => match self.peek() {
+                Some('/') => return self.line_comment(start),
+                Some('*') => {
+                    self.block_comment(start, line, column);
+                    return None;
+                }
+                _ => self.operator_or_assign(Operator::Slash),
+            },
+            '%' => self.operator_or_assign(Operator::Percent),
+            '^' => self.operator_or_assign(Operator::Caret),
+            '-' => match self.peek() {
+                Some('>') => {
+                    self.bump();
+                    Token::Arrow
+                }
+                _ => self.operator_or_assign(Operator::Minus),
+            },
+            '=' => self.either('=', Token::Operator(Operator::EqEq), Token::Eq),
+            '!' => self.either(
+                '=',
+                Token::Operator(Operator::NotEq),
+                Token::Operator(Operator::Bang),
+            ),
+            '&' => match self.peek() {
+                Some('&') => {
+                    self.bump();
+                    Token::Operator(Operator::AndAnd)
+                }
+                _ => self.operator_or_assign(Operator::Ampersand),
+            },
+            '|' => match self.peek() {
+                Some('|') => {
+                    self.bump();
+                    Token::Operator(Operator::OrOr)
+                }
+                _ => self.operator_or_assign(Operator::Pipe),
+            },
+            '<' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    Token::Operator(Operator::LtEq)
+                }
+                Some('<') => {
+                    self.bump();
+                    self.operator_or_assign(Operator::Shl)
+                }
+                _ => Token::LAngleBracket,
+            },
+            '>' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    Token::Operator(Operator::GtEq)
+                }
+                Some('>') => {
+                    self.bump();
+                    self.operator_or_assign(Operator::Shr)
+                }
+                _ => Token::RAngleBracket,
+            },
+            '"' => self.string_literal(false, start, line, column),
+            '\'' => self.char_literal(false, start, line, column),
+            'b' if self.peek() == Some('"') => {
+                self.bump();
+                self.string_literal(true, start, line, column)
+            }
+            'b' if self.peek() == Some('\'') => {
+                self.bump();
+                self.char_literal(true, start, line, column)
+            }
+            c if c.is_ascii_digit() => self.number_literal(start),
+            c if c.is_alphabetic() || c == '_' => {
+                self.bump_while(|c| c.is_alphanumeric() || c == '_');
+                keyword_or_identifier(&self.source[start..self.pos])
+            }
             other => {
-                if other.starts_with("\"") && other.ends_with("\"") {
-                    let literal_content = other.trim_matches('"').to_string();
-                    tokens.push(Token::Literal(literal_content));
-                } else if other.chars().all(|c| c.is_digit(10))
-                    || (other.starts_with("0x") && other[2..].chars().all(|c| c.is_digit(16)))
-                {
-                    tokens.push(Token::Literal(other.to_string()));
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!("unknown start of token: {}", other),
+                        self.span_from(start, line, column),
+                    )
+                    .with_label("unexpected character"),
+                );
+                return None;
+            }
+        };
+        Some(token)
+    }
+
+    /// `//` から行末までのコメント。`///` はドキュメントコメントのトークンにし、`////` は普通のコメントにする
+    fn line_comment(&mut self, start: usize) -> Option<Token> {
+        self.bump_while(|c| c != '\n');
+        let text = self.source[start..self.pos].trim_end_matches('\r');
+        match text.strip_prefix("///") {
+            Some(doc) if !doc.starts_with('/') => Some(Token::DocComment(doc.to_string())),
+            _ => None,
+        }
+    }
+
+    /// `/*` から対応する `*/` までのコメント。Rustと同じく入れ子にできる
+    fn block_comment(&mut self, start: usize, line: usize, column: usize) {
+        self.bump();
+        let mut depth = 1;
+        while depth > 0 {
+            match self.bump() {
+                Some('/') if self.peek() == Some('*') => {
+                    self.bump();
+                    depth += 1;
+                }
+                Some('*') if self.peek() == Some('/') => {
+                    self.bump();
+                    depth -= 1;
+                }
+                Some(_) => (),
+                None => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            "unterminated block comment",
+                            self.span_from(start, line, column),
+                        )
+                        .with_label("comment starts here"),
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    /// 次の文字が `expected` なら読み進めて `then` を、そうでなければ `otherwise` を返す
+    fn either(&mut self, expected: char, then: Token, otherwise: Token) -> Token {
+        if self.peek() == Some(expected) {
+            self.bump();
+            then
+        } else {
+            otherwise
+        }
+    }
+
+    /// 演算子の直後に `=` があれば `+=` のような複合代入演算子にする
+    fn operator_or_assign(&mut self, op: Operator) -> Token {
+        if self.peek() == Some('=') {
+            self.bump();
+            Token::OperatorEq(op)
+        } else {
+            Token::Operator(op)
+        }
+    }
+
+    /// `"..."` か `b"..."` の文字列リテラル。開きの `"` は読んである
+    fn string_literal(&mut self, byte: bool, start: usize, line: usize, column: usize) -> Token {
+        let content = self.quoted('"', byte, start, line, column);
+        if byte {
+            Token::ByteStrLiteral(content.chars().map(|c| c as u8).collect())
+        } else {
+            Token::StrLiteral(content)
+        }
+    }
+
+    /// `'a'` か `b'a'` の文字リテラル。バイトの文字リテラルは `u8` の整数リテラルにする
+    fn char_literal(&mut self, byte: bool, start: usize, line: usize, column: usize) -> Token {
+        let content = self.quoted('\'', byte, start, line, column);
+        let mut chars = content.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            (first, _) => {
+                let message = if first.is_none() {
+                    "empty character literal"
                 } else {
-                    match other {
-                        _ => tokens.push(Token::Identifier(other.to_string())),
+                    "character literal may only contain one codepoint"
+                };
+                self.diagnostics.push(Diagnostic::error(
+                    message,
+                    self.span_from(start, line, column),
+                ));
+                first.unwrap_or('\0')
+            }
+        };
+        if byte {
+            Token::Literal(format!("{}u8", c as u8))
+        } else {
+            Token::CharLiteral(c)
+        }
+    }
+
+    /// `quote` で閉じるまでを、エスケープを解いて読む。
+    /// `byte` ならバイト文字列として ASCII だけを許し、`\xFF` までのエスケープを `U+00FF` までの文字にする
+    fn quoted(
+        &mut self,
+        quote: char,
+        byte: bool,
+        start: usize,
+        line: usize,
+        column: usize,
+    ) -> String {
+        let mut content = String::new();
+        loop {
+            let (escape_start, escape_line, escape_column) = (self.pos, self.line, self.column);
+            match self.bump() {
+                Some(c) if c == quote => return content,
+                Some('\\') => {
+                    if let Some(c) = self.escape(byte, escape_start, escape_line, escape_column) {
+                        content.push(c);
+                    }
+                }
+                Some(c) => {
+                    if byte && !c.is_ascii() {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                "non-ASCII character in byte string literal",
+                                self.span_from(escape_start, escape_line, escape_column),
+                            )
+                            .with_label("must be ASCII"),
+                        );
+                    }
+                    content.push(c);
+                }
+                None => {
+                    let message = match (quote, byte) {
+                        ('"', false) => "unterminated double quote string",
+                        ('"', true) => "unterminated double quote byte string",
+                        _ => "unterminated character literal",
+                    };
+                    self.diagnostics.push(
+                        Diagnostic::error(message, self.span_from(start, line, column))
+                            .with_label("literal starts here"),
+                    );
+                    return content;
+                }
+            }
+        }
+    }
+
+    /// `\` の後のエスケープを読む。行末の `\` は次の行の先頭の空白と合わせて読み飛ばす
+    fn escape(&mut self, byte: bool, start: usize, line: usize, column: usize) -> Option<char> {
+        let c = match self.bump() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('0') => '\0',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('\n') => {
+                self.bump_while(char::is_whitespace);
+                return None;
+            }
+            Some('x') => {
+                let digits: String = (0..2).filter_map(|_| self.bump()).collect();
+                let value = u8::from_str_radix(&digits, 16).ok();
+                match value {
+                    Some(value) if byte || value <= 0x7F => value as char,
+                    Some(_) => {
+                        return self.escape_error(
+                            "out of range hex escape",
+                            "must be a character in the range [\\x00-\\x7f]",
+                            (start, line, column),
+                        );
+                    }
+                    None => {
+                        return self.escape_error(
+                            "invalid character in numeric character escape",
+                            "expected two hexadecimal digits",
+                            (start, line, column),
+                        );
                     }
                 }
             }
+            Some('u') if !byte => return self.unicode_escape(start, line, column),
+            Some('u') => {
+                return self.escape_error(
+                    "unicode escape in byte string",
+                    "unicode escape in byte string",
+                    (start, line, column),
+                );
+            }
+            Some(other) => {
+                return self.escape_error(
+                    &format!("unknown character escape: `{}`", other.escape_default()),
+                    "unknown character escape",
+                    (start, line, column),
+                );
+            }
+            None => return None,
+        };
+        Some(c)
+    }
+
+    /// `\u{1F600}` の `{` から読む
+    fn unicode_escape(&mut self, start: usize, line: usize, column: usize) -> Option<char> {
+        if self.peek() != Some('{') {
+            return self.escape_error(
+                "incorrect unicode escape sequence",
+                "incorrect unicode escape sequence",
+                (start, line, column),
+            );
         }
+        self.bump();
+        let digits_start = self.pos;
+        self.bump_while(|c| c.is_ascii_hexdigit() || c == '_');
+        let digits = self.source[digits_start..self.pos].replace('_', "");
+        if self.peek() != Some('}') || digits.is_empty() || digits.len() > 6 {
+            return self.escape_error(
+                "invalid unicode character escape",
+                "invalid escape",
+                (start, line, column),
+            );
+        }
+        self.bump();
+        let value = u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32);
+        if value.is_none() {
+            return self.escape_error(
+                "invalid unicode character escape",
+                "must be a valid unicode scalar value",
+                (start, line, column),
+            );
+        }
+        value
+    }
+
+    fn escape_error(
+        &mut self,
+        message: &str,
+        label: &str,
+        (start, line, column): (usize, usize, usize),
+    ) -> Option<char> {
+        self.diagnostics.push(
+            Diagnostic::error(message, self.span_from(start, line, column)).with_label(label),
+        );
+        None
+    }
+}
+
+/// 数値リテラル中の指数を表す `e` の位置。`10usize` の `e` のように数字の後に無いものは含めない
+fn exponent_start(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (1..bytes.len()).find(|&i| {
+        matches!(bytes[i], b'e' | b'E') && (bytes[i - 1].is_ascii_digit() || bytes[i - 1] == b'_')
+    })
+}
+
+fn keyword_or_identifier(word: &str) -> Token {
+    match word {
+        "fn" => Token::Fn,
+        "let" => Token::Let,
+        "const" => Token::Const,
+        "return" => Token::Return,
+        "if" => Token::If,
+        "else" => Token::Else,
+        "while" => Token::While,
+        "loop" => Token::Loop,
+        "break" => Token::Break,
+        "continue" => Token::Continue,
+        "mut" => Token::Mut,
+        "as" => Token::As,
+        "true" => Token::True,
+        "false" => Token::False,
+        other => match Type::from_keyword(other) {
+            Some(ty) => Token::Type(ty),
+            None => Token::Identifier(other.to_string()),
+        },
+    }
+}
+
+pub fn to_token_stream(source: &str) -> Result<Vec<SpannedToken>, Vec<Diagnostic>> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::<SpannedToken>::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    if lexer.diagnostics.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(lexer.diagnostics)
     }
-    tokens
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::chunker;
     use crate::parser::token::{Token, Type};
 
+    fn lex(source: &str) -> Vec<Token> {
+        to_token_stream(source)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
     #[test]
     fn test_lex() {
         let source = "fn main() { let x: i32 = 10; }";
-        let chunks = chunker::to_token_chunks(source);
-        let tokens = to_token_stream(chunks);
+        let tokens = lex(source);
         let expected_tokens = vec![
             Token::Fn,
             Token::Identifier("main".to_string()),
@@ -69,11 +525,249 @@ mod tests {
             Token::Identifier("x".to_string()),
             Token::Collon,
             Token::Type(Type::I32),
-            Token::Identifier("=".to_string()),
+            Token::Eq,
             Token::Literal("10".to_string()),
             Token::Semicolon,
             Token::RBrace,
         ];
         assert_eq!(tokens, expected_tokens);
     }
+
+    #[test]
+    fn test_lex_glued_operators() {
+        assert_eq!(
+            lex("x+1"),
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::Operator(Operator::Plus),
+                Token::Literal("1".to_string()),
+            ]
+        );
+        assert_eq!(
+            lex("a=b==c!=d->e<=f>>g&&!h"),
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Eq,
+                Token::Identifier("b".to_string()),
+                Token::Operator(Operator::EqEq),
+                Token::Identifier("c".to_string()),
+                Token::Operator(Operator::NotEq),
+                Token::Identifier("d".to_string()),
+                Token::Arrow,
+                Token::Identifier("e".to_string()),
+                Token::Operator(Operator::LtEq),
+                Token::Identifier("f".to_string()),
+                Token::Operator(Operator::Shr),
+                Token::Identifier("g".to_string()),
+                Token::Operator(Operator::AndAnd),
+                Token::Operator(Operator::Bang),
+                Token::Identifier("h".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_control_flow_and_assignment() {
+        assert_eq!(
+            lex("while i<=n{i+=1;x<<=2;}else"),
+            vec![
+                Token::While,
+                Token::Identifier("i".to_string()),
+                Token::Operator(Operator::LtEq),
+                Token::Identifier("n".to_string()),
+                Token::LBrace,
+                Token::Identifier("i".to_string()),
+                Token::OperatorEq(Operator::Plus),
+                Token::Literal("1".to_string()),
+                Token::Semicolon,
+                Token::Identifier("x".to_string()),
+                Token::OperatorEq(Operator::Shl),
+                Token::Literal("2".to_string()),
+                Token::Semicolon,
+                Token::RBrace,
+                Token::Else,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_float_literals() {
+        let float = |lit: &str| Token::FloatLiteral(lit.to_string());
+        assert_eq!(
+            lex("1.5 2e10 1e-3 2.5E+2 0x1e 10usize-1"),
+            vec![
+                float("1.5"),
+                float("2e10"),
+                float("1e-3"),
+                float("2.5E+2"),
+                Token::Literal("0x1e".to_string()),
+                Token::Literal("10usize".to_string()),
+                Token::Operator(Operator::Minus),
+                Token::Literal("1".to_string()),
+            ]
+        );
+        // ドットの後が数字でなければ小数点ではない
+        assert_eq!(
+            lex("1.x"),
+            vec![
+                Token::Literal("1".to_string()),
+                Token::Dot,
+                Token::Identifier("x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_integer_types_and_casts() {
+        assert_eq!(
+            lex("x as u8 + 10u8 * 2f64 != true"),
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::As,
+                Token::Type(Type::U8),
+                Token::Operator(Operator::Plus),
+                Token::Literal("10u8".to_string()),
+                Token::Operator(Operator::Asterisk),
+                Token::FloatLiteral("2f64".to_string()),
+                Token::Operator(Operator::NotEq),
+                Token::True,
+            ]
+        );
+        assert_eq!(
+            lex("i8 i16 i64 isize u16 u32 u64 usize bool false"),
+            vec![
+                Token::Type(Type::I8),
+                Token::Type(Type::I16),
+                Token::Type(Type::I64),
+                Token::Type(Type::Isize),
+                Token::Type(Type::U16),
+                Token::Type(Type::U32),
+                Token::Type(Type::U64),
+                Token::Type(Type::Usize),
+                Token::Type(Type::Bool),
+                Token::False,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_string_with_spaces() {
+        assert_eq!(
+            lex("println!(\"hello world\");"),
+            vec![
+                Token::Identifier("println".to_string()),
+                Token::Operator(Operator::Bang),
+                Token::LParentheses,
+                Token::StrLiteral("hello world".to_string()),
+                Token::RParentheses,
+                Token::Semicolon,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_escapes() {
+        assert_eq!(
+            lex(r#""a\tb\n\\\"\u{1F600}\x41" 'c' '\'' '\u{e9}' b"\x00\xFF\n" b'A'"#),
+            vec![
+                Token::StrLiteral("a\tb\n\\\"\u{1F600}A".to_string()),
+                Token::CharLiteral('c'),
+                Token::CharLiteral('\''),
+                Token::CharLiteral('é'),
+                Token::ByteStrLiteral(vec![0x00, 0xFF, b'\n']),
+                Token::Literal("65u8".to_string()),
+            ]
+        );
+        // 行末の `\` は次の行の先頭の空白ごと読み飛ばす
+        assert_eq!(
+            lex("\"one \\\n      two\""),
+            vec![Token::StrLiteral("one two".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_lex_escape_errors() {
+        let diagnostics =
+            to_token_stream(r#""\q" '' 'ab' "\x80" b"é" b"\u{41}" "\u{D800}" 'x"#).unwrap_err();
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown character escape: `q`",
+                "empty character literal",
+                "character literal may only contain one codepoint",
+                "out of range hex escape",
+                "non-ASCII character in byte string literal",
+                "unicode escape in byte string",
+                "invalid unicode character escape",
+                "unterminated character literal",
+            ]
+        );
+        assert_eq!(diagnostics[0].span.column, 2);
+    }
+
+    #[test]
+    fn test_lex_comments() {
+        let tokens = to_token_stream(
+            "/// doc\n//// not doc\nfn /* a /* nested */ comment */ f // end\n/*\n*/x",
+        )
+        .unwrap();
+        let kinds: Vec<&Token> = tokens.iter().map(|t| &t.token).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &Token::DocComment(" doc".to_string()),
+                &Token::Fn,
+                &Token::Identifier("f".to_string()),
+                &Token::Identifier("x".to_string()),
+            ]
+        );
+        // コメントの中の改行も行番号に数える
+        assert_eq!((tokens[3].span.line, tokens[3].span.column), (5, 3));
+        let diagnostics = to_token_stream("fn /* /* */").unwrap_err();
+        assert_eq!(diagnostics[0].message, "unterminated block comment");
+        assert_eq!(diagnostics[0].span.column, 4);
+    }
+
+    #[test]
+    fn test_lex_spans() {
+        let tokens = to_token_stream("fn f() -> i32 {\n    x+10\n}").unwrap();
+        let arrow = &tokens[4];
+        assert_eq!(arrow.token, Token::Arrow);
+        assert_eq!(
+            arrow.span,
+            Span {
+                start: 7,
+                end: 9,
+                line: 1,
+                column: 8
+            }
+        );
+        let ten = &tokens[9];
+        assert_eq!(ten.token, Token::Literal("10".to_string()));
+        assert_eq!(
+            ten.span,
+            Span {
+                start: 22,
+                end: 24,
+                line: 2,
+                column: 7
+            }
+        );
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let diagnostics = to_token_stream("let a = 1 @ 2;\nlet s = \"oops").unwrap_err();
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown start of token: @",
+                "unterminated double quote string"
+            ]
+        );
+        assert_eq!(diagnostics[0].span.column, 11);
+        assert_eq!(diagnostics[1].span.line, 2);
+    }
 }
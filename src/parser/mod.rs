@@ -1,4 +1,4 @@
-mod chunker;
-mod lexer;
+pub mod format;
+pub mod lexer;
 pub mod parser;
 pub mod token;
@@ -1,287 +1,1192 @@
-use super::chunker;
 use super::lexer;
-use super::token::Token;
-use crate::{
-    ast::program::{Expr, FnCall, FnParams, FnSignature, Item, ItemFn, Local, Program, Statement},
-    parser::token::Operator,
+use super::token::{Operator, Span, SpannedToken, Token, Type};
+use crate::ast::program::{
+    Assign, Expr, ExprKind, FnCall, FnParams, FnSignature, If, Item, ItemConst, ItemFn, Local,
+    MethodCall, Program, Statement, Ty, While,
 };
+use crate::diagnostic::diagnostic::Diagnostic;
 
-pub fn parse(source_code: &String) -> Program {
-    let chunks = chunker::to_token_chunks(source_code);
-    let token_iter = lexer::to_token_stream(chunks);
-    parse_to_program(token_iter)
+type ParseResult<T> = Result<T, Diagnostic>;
+
+pub fn parse(source_code: &str) -> Result<Program, Vec<Diagnostic>> {
+    let tokens = lexer::to_token_stream(source_code)?;
+    parse_to_program(tokens)
 }
 
-fn parse_to_program(tokens: Vec<Token>) -> Program {
-    let mut token_iter = tokens.into_iter().peekable();
+fn parse_to_program(tokens: Vec<SpannedToken>) -> Result<Program, Vec<Diagnostic>> {
+    let mut parser = Parser::new(tokens);
     let mut items = Vec::<Item>::new();
-    while token_iter.peek() != None {
-        items.push(parse_item(&mut token_iter));
+    while parser.peek().is_some() {
+        match parser.parse_item() {
+            Ok(item) => items.push(item),
+            Err(diagnostic) => {
+                parser.diagnostics.push(diagnostic);
+                parser.synchronize_item();
+            }
+        }
     }
-    Program { items }
-}
-
-fn parse_item(token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>) -> Item {
-    match token_iter.next().unwrap() {
-        Token::Fn => return parse_item_fn(token_iter),
-        _ => panic!("Unexpected token"),
+    if parser.diagnostics.is_empty() {
+        Ok(Program {
+            items,
+            end: parser.eof_span(),
+        })
+    } else {
+        Err(parser.diagnostics)
     }
 }
 
-fn parse_item_fn(token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>) -> Item {
-    let signature = parse_fn_signature(token_iter);
-    let block = parse_block(token_iter);
-    Item::ItemFn(ItemFn { signature, block })
+/// 再帰下降パーサー。
+/// エラーが起きても `diagnostics` に記録して文や関数の区切りまで読み飛ばし、解析を続ける
+struct Parser {
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// 解析中のループの深さ。ループの外の `break` と `continue` を検出する
+    loop_depth: usize,
 }
 
-fn parse_block(
-    token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>,
-) -> Vec<Statement> {
-    match token_iter.next().unwrap() {
-        Token::LBrace => (),
-        _ => panic!("Expected '{{'"),
-    };
-    let mut statements = Vec::<Statement>::new();
-    while token_iter.peek() != None {
-        match token_iter.peek().unwrap() {
-            Token::RBrace => {
-                token_iter.next();
-                break;
+impl Parser {
+    fn new(tokens: Vec<SpannedToken>) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            diagnostics: Vec::new(),
+            loop_depth: 0,
+        }
+    }
+
+    fn at_item_start(&self) -> bool {
+        matches!(self.peek(), Some(Token::Fn) | Some(Token::Const))
+    }
+
+    /// 文の途中でエラーになった時に、次の文の先頭まで読み飛ばす。
+    /// `;` と、`if` や `while` の本体のように文を終える `}` は読み進め、
+    /// ブロックを閉じる `}` と次の関数・定数の先頭では止まる
+    fn synchronize_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Semicolon if depth == 0 => {
+                    self.next();
+                    return;
+                }
+                Token::RBrace if depth == 0 => return,
+                Token::Fn | Token::Const => return,
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 1 => {
+                    self.next();
+                    match self.peek() {
+                        // `else` の続く `if` や、`let x = { ... };` はまだ終わっていない
+                        Some(Token::Else) => {
+                            depth = 0;
+                            continue;
+                        }
+                        Some(Token::Semicolon) => {
+                            self.next();
+                        }
+                        _ => (),
+                    }
+                    return;
+                }
+                Token::RBrace => depth -= 1,
+                _ => (),
             }
-            _ => statements.push(parse_statement(token_iter)),
+            self.next();
+        }
+    }
+
+    /// 関数や定数の宣言でエラーになった時に、次の `fn` か `const` まで読み飛ばす
+    fn synchronize_item(&mut self) {
+        self.next();
+        while self.peek().is_some() && !self.at_item_start() {
+            self.next();
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    /// 次のトークンのSpan。入力の終端では最後のトークンの直後を指す
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(t) => t.span,
+            None => self.eof_span(),
+        }
+    }
+
+    fn eof_span(&self) -> Span {
+        match self.tokens.last() {
+            Some(last) => Span {
+                start: last.span.end,
+                end: last.span.end,
+                line: last.span.line,
+                column: last.span.column + (last.span.end - last.span.start),
+            },
+            None => Span {
+                line: 1,
+                column: 1,
+                ..Span::default()
+            },
+        }
+    }
+
+    /// 直前に読んだトークンのSpan
+    fn prev_span(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].span
+    }
+
+    fn next(&mut self) -> Option<SpannedToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// 次のトークンが `expected` であれば読み進め、そうでなければエラーを返す
+    fn expect(&mut self, expected: Token) -> ParseResult<Span> {
+        if self.peek() == Some(&expected) {
+            Ok(self.next().unwrap().span)
+        } else {
+            Err(self
+                .unexpected(&format!("`{}`", expected))
+                .with_label(format!("expected `{}`", expected)))
         }
     }
-    statements
-}
 
-fn parse_statement(
-    token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>,
-) -> Statement {
-    match token_iter.next().unwrap() {
-        Token::Let => parse_let_statement(token_iter),
-        Token::Identifier(indent) => {
-            let _indent = indent.clone();
-            match token_iter.peek().unwrap() {
-                Token::LParentheses => {
-                    let fn_call: FnCall = parse_fn_call(token_iter, &_indent);
-                    match token_iter.next().unwrap() {
-                        Token::Semicolon => (),
-                        other => panic!("Expected ';' found: {:?}", other),
-                    };
-                    Statement::FnCall(fn_call)
+    fn expect_identifier(&mut self, what: &str) -> ParseResult<(String, Span)> {
+        match self.peek() {
+            Some(Token::Identifier(_)) => {
+                let token = self.next().unwrap();
+                match token.token {
+                    Token::Identifier(name) => Ok((name, token.span)),
+                    _ => unreachable!(),
                 }
-                _ => panic!("Unexpected token: {:?}", token_iter.peek().unwrap()),
             }
+            _ => Err(self
+                .unexpected(what)
+                .with_label(format!("expected {}", what))),
         }
-        Token::Return => {
-            let expr = parse_expr(token_iter);
-            match token_iter.next().unwrap() {
-                Token::Semicolon => (),
-                other => panic!("Expected ';' found: {:?}", other),
-            };
-            Statement::Return(expr)
+    }
+
+    /// 次のトークンが期待と違う場合のエラー
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        let found = match self.peek() {
+            Some(token) => format!("`{}`", token),
+            None => "end of file".to_string(),
+        };
+        Diagnostic::error(
+            format!("expected {}, found {}", expected, found),
+            self.current_span(),
+        )
+    }
+
+    fn parse_item(&mut self) -> ParseResult<Item> {
+        let doc = self.parse_doc_comments();
+        match self.peek() {
+            Some(Token::Fn) => {
+                self.next();
+                self.parse_item_fn(doc.map(|(doc, _)| doc))
+            }
+            Some(Token::Const) => {
+                self.next();
+                self.parse_item_const(doc.map(|(doc, _)| doc))
+            }
+            _ if doc.is_some() => Err(Diagnostic::error(
+                "expected item after doc comment",
+                doc.unwrap().1,
+            )
+            .with_label("this doc comment doesn't document anything")),
+            _ => Err(self
+                .unexpected("item")
+                .with_label("expected item")
+                .with_note("only `fn` and `const` items are supported at the top level")),
         }
-        other => panic!("Unexpected token in statement found: {:?}", other),
     }
-}
 
-fn parse_fn_call(
-    token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>,
-    ident: &String,
-) -> FnCall {
-    let args = parse_fn_arg(token_iter);
-    FnCall {
-        name: ident.clone(),
-        args: args,
+    /// 続いている `///` をまとめて読み、1行ずつ先頭の空白1つを除いて改行でつなぐ。
+    /// 最後のドキュメントコメントまでのSpanも返す
+    fn parse_doc_comments(&mut self) -> Option<(String, Span)> {
+        let mut lines = Vec::new();
+        let start = self.current_span();
+        while let Some(Token::DocComment(line)) = self.peek() {
+            lines.push(line.strip_prefix(' ').unwrap_or(line).to_string());
+            self.next();
+        }
+        (!lines.is_empty()).then(|| (lines.join("\n"), start.to(self.prev_span())))
     }
-}
 
-fn parse_fn_arg(token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>) -> Vec<Expr> {
-    match token_iter.next().unwrap() {
-        Token::LParentheses => (),
-        _ => panic!("Expected '(': {:?}", token_iter.peek().unwrap()),
-    };
-    let mut args = Vec::<Expr>::new();
-    while token_iter.peek() != None {
-        match token_iter.next().unwrap() {
-            Token::RParentheses => {
-                break;
+    fn parse_item_const(&mut self, doc: Option<String>) -> ParseResult<Item> {
+        let (name, span) = self.expect_identifier("identifier")?;
+        if let Some(Token::Eq) = self.peek() {
+            return Err(Diagnostic::error(
+                "missing type for `const` item",
+                span.to(self.current_span()),
+            )
+            .with_label(format!("provide a type for the constant: `{}: i32`", name)));
+        }
+        self.expect(Token::Collon)?;
+        let const_type = self.parse_type()?;
+        self.expect(Token::Eq)?;
+        let value = self.parse_expr()?;
+        self.expect(Token::Semicolon)?;
+        Ok(Item::ItemConst(ItemConst {
+            doc,
+            name,
+            const_type,
+            value,
+            span,
+        }))
+    }
+
+    fn parse_item_fn(&mut self, doc: Option<String>) -> ParseResult<Item> {
+        let signature = self.parse_fn_signature()?;
+        let block = self.parse_block()?;
+        Ok(Item::ItemFn(ItemFn {
+            doc,
+            signature,
+            block,
+        }))
+    }
+
+    fn parse_block(&mut self) -> ParseResult<Vec<Statement>> {
+        self.expect(Token::LBrace)?;
+        let mut statements = Vec::<Statement>::new();
+        loop {
+            match self.peek() {
+                Some(Token::RBrace) => {
+                    self.next();
+                    break;
+                }
+                // 文に付いたドキュメントコメントは普通のコメントとして読み飛ばす
+                Some(Token::DocComment(_)) => {
+                    if let Some((_, span)) = self.parse_doc_comments()
+                        && self.peek() == Some(&Token::RBrace)
+                    {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                "found a documentation comment that doesn't document anything",
+                                span,
+                            )
+                            .with_help("doc comments must come before what they document, if a comment was intended use `//`"),
+                        );
+                    }
+                }
+                Some(Token::Fn) | Some(Token::Const) => {
+                    // 閉じ括弧が抜けている。ここまでをブロックとして次の関数の解析に進む
+                    let diagnostic = self.unexpected("`}`").with_label("expected `}`");
+                    self.diagnostics.push(diagnostic);
+                    break;
+                }
+                Some(_) => match self.parse_statement() {
+                    Ok(statement) => statements.push(statement),
+                    Err(diagnostic) => {
+                        self.diagnostics.push(diagnostic);
+                        self.synchronize_statement();
+                    }
+                },
+                None => return Err(self.unexpected("`}`").with_label("expected `}`")),
             }
-            Token::Identifier(lit) => {
-                args.push(Expr::ExprVariable(lit.clone()));
-                match token_iter.peek().unwrap() {
-                    Token::Comma => (token_iter.next(),),
-                    Token::RParentheses => continue,
-                    other => panic!("Expected ',' or ) after argument: {:?}", other),
-                };
+        }
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> ParseResult<Statement> {
+        match self.peek() {
+            Some(Token::Let) => {
+                self.next();
+                self.parse_let_statement()
             }
-            Token::Literal(lit) => {
-                args.push(Expr::ExprLit(lit.clone()));
-                match token_iter.peek().unwrap() {
-                    Token::Comma => (token_iter.next(),),
-                    Token::RParentheses => continue,
-                    other => panic!("Expected ',' or ) after argument: {:?}", other),
-                };
+            Some(Token::Identifier(_)) => {
+                let (mut ident, span) = self.expect_identifier("identifier")?;
+                match self.peek() {
+                    Some(Token::Eq) | Some(Token::OperatorEq(_)) => {
+                        return self.parse_assign_statement(ident, span);
+                    }
+                    // println! などのマクロ呼び出しは `!` 付きの名前の関数呼び出しとして扱う
+                    Some(Token::Operator(Operator::Bang)) => {
+                        self.next();
+                        ident.push('!');
+                    }
+                    _ => (),
+                }
+                let fn_call = self.parse_fn_call(&ident, span)?;
+                self.expect(Token::Semicolon)?;
+                Ok(Statement::FnCall(fn_call))
+            }
+            Some(Token::Return) => {
+                self.next();
+                let expr = self.parse_expr()?;
+                self.expect(Token::Semicolon)?;
+                Ok(Statement::Return(expr))
+            }
+            Some(Token::If) => {
+                self.next();
+                Ok(Statement::If(self.parse_if()?))
+            }
+            Some(Token::While) => {
+                self.next();
+                let cond = self.parse_expr()?;
+                let body = self.parse_loop_body()?;
+                Ok(Statement::While(While { cond, body }))
             }
-            _ => panic!(
-                "Unexpected token in function arguments: {:?}",
-                token_iter.peek().unwrap()
-            ),
+            Some(Token::Loop) => {
+                self.next();
+                Ok(Statement::Loop(self.parse_loop_body()?))
+            }
+            Some(Token::LBrace) => Ok(Statement::Block(self.parse_block()?)),
+            Some(Token::Break) | Some(Token::Continue) => {
+                let token = self.next().unwrap();
+                if self.loop_depth == 0 {
+                    return Err(Diagnostic::error(
+                        format!("`{}` outside of a loop", token.token),
+                        token.span,
+                    )
+                    .with_label(format!("cannot `{}` outside of a loop", token.token)));
+                }
+                self.expect(Token::Semicolon)?;
+                match token.token {
+                    Token::Break => Ok(Statement::Break(token.span)),
+                    _ => Ok(Statement::Continue(token.span)),
+                }
+            }
+            _ => Err(self
+                .unexpected("statement")
+                .with_label("expected statement")),
         }
     }
-    args
-}
 
-fn parse_let_statement(
-    token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>,
-) -> Statement {
-    let name = match token_iter.next().unwrap() {
-        Token::Identifier(name) => name,
-        _ => panic!("Expected identifier"),
-    };
-    let mut var_type = String::new();
-
-    if token_iter.peek().unwrap() == &Token::Collon {
-        token_iter.next();
-        var_type = match token_iter.next().unwrap() {
-            Token::Type(t) => format!("{:?}", t),
-            _ => panic!("Expected type"),
+    /// `if` の後ろから読む。`else if` は `else` ブロックの中の `If` になる
+    fn parse_if(&mut self) -> ParseResult<If> {
+        let cond = self.parse_expr()?;
+        let then_block = self.parse_block()?;
+        let else_block = match self.peek() {
+            Some(Token::Else) => {
+                self.next();
+                if let Some(Token::If) = self.peek() {
+                    self.next();
+                    Some(vec![Statement::If(self.parse_if()?)])
+                } else {
+                    Some(self.parse_block()?)
+                }
+            }
+            _ => None,
         };
+        Ok(If {
+            cond,
+            then_block,
+            else_block,
+        })
     }
-    match token_iter.next().unwrap() {
-        Token::Identifier(op) if op == "=" => (),
-        _ => panic!("Expected '='"),
-    };
-    let expr = parse_expr(token_iter);
-
-    match token_iter.next().unwrap() {
-        Token::Semicolon => (),
-        _ => panic!("Expected ';'"),
-    };
-    Statement::Local(Local {
-        name,
-        var_type,
-        value: expr,
-    })
-}
 
-fn parse_expr(token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>) -> Expr {
-    let _next_token = token_iter.next().unwrap();
-    let _next_next_token = token_iter.peek().unwrap().clone();
-    match _next_next_token {
-        Token::Operator(_) => {
-            let left = match _next_token {
-                Token::Identifier(lit) => Expr::ExprVariable(lit),
-                Token::Literal(lit) => Expr::ExprLit(lit),
-                _ => panic!("Expected literal"),
-            };
-            let op = match token_iter.next().unwrap() {
-                Token::Operator(op) => op,
-                _ => panic!("Expected operator: {:?} found", token_iter.peek().unwrap()),
+    fn parse_loop_body(&mut self) -> ParseResult<Vec<Statement>> {
+        self.loop_depth += 1;
+        let body = self.parse_block();
+        self.loop_depth -= 1;
+        body
+    }
+
+    /// `name = expr;` と `name += expr;` などの代入文
+    fn parse_assign_statement(&mut self, name: String, span: Span) -> ParseResult<Statement> {
+        let op = match self.next().map(|t| t.token) {
+            Some(Token::OperatorEq(op)) => Some(op),
+            _ => None,
+        };
+        let rhs = self.parse_expr()?;
+        self.expect(Token::Semicolon)?;
+        let value = match op {
+            Some(op) => Expr {
+                span: span.to(rhs.span),
+                kind: ExprKind::ExprBinaryOp {
+                    left: Box::new(Expr {
+                        kind: ExprKind::ExprVariable(name.clone()),
+                        span,
+                    }),
+                    op,
+                    right: Box::new(rhs),
+                },
+            },
+            None => rhs,
+        };
+        Ok(Statement::Assign(Assign { name, value, span }))
+    }
+
+    fn parse_fn_call(&mut self, ident: &str, span: Span) -> ParseResult<FnCall> {
+        let args = self.parse_fn_arg()?;
+        Ok(FnCall {
+            name: ident.to_string(),
+            args,
+            span,
+        })
+    }
+
+    fn parse_fn_arg(&mut self) -> ParseResult<Vec<Expr>> {
+        self.expect(Token::LParentheses)?;
+        let mut args = Vec::<Expr>::new();
+        loop {
+            if let Some(Token::RParentheses) = self.peek() {
+                self.next();
+                break;
+            }
+            args.push(self.parse_expr()?);
+            match self.peek() {
+                Some(Token::Comma) => {
+                    self.next();
+                }
+                Some(Token::RParentheses) => continue,
+                _ => {
+                    return Err(self
+                        .unexpected("`,` or `)`")
+                        .with_label("expected `,` or `)` after argument"));
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    fn parse_let_statement(&mut self) -> ParseResult<Statement> {
+        let mutable = matches!(self.peek(), Some(Token::Mut));
+        if mutable {
+            self.next();
+        }
+        let (name, span) = self.expect_identifier("identifier")?;
+        let mut var_type = None;
+
+        if let Some(Token::Collon) = self.peek() {
+            self.next();
+            var_type = Some(self.parse_type()?);
+        }
+        self.expect(Token::Eq)?;
+        let expr = self.parse_expr()?;
+        self.expect(Token::Semicolon)?;
+        Ok(Statement::Local(Local {
+            name,
+            mutable,
+            var_type,
+            value: expr,
+            span,
+        }))
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.parse_binary_expr(0)
+    }
+
+    /// 優先順位法 (precedence climbing) で `min_precedence` 以上の二項演算子を読む
+    fn parse_binary_expr(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let mut left = self.parse_cast_expr()?;
+        let mut left_is_comparison = false;
+        while let Some(op) = self.peek_binary_operator() {
+            let precedence = binary_precedence(&op);
+            if precedence < min_precedence {
+                break;
+            }
+            let op_span = self.next().unwrap().span;
+            if left_is_comparison && is_comparison(&op) {
+                return Err(
+                    Diagnostic::error("comparison operators cannot be chained", op_span)
+                        .with_label("cannot chain this comparison")
+                        .with_note("use parentheses to clarify the intended order"),
+                );
+            }
+            // 全ての二項演算子は左結合なので、右辺は1つ高い優先順位から読む
+            let right = self.parse_binary_expr(precedence + 1)?;
+            left_is_comparison = is_comparison(&op);
+            left = Expr {
+                span: left.span.to(right.span),
+                kind: ExprKind::ExprBinaryOp {
+                    left: Box::new(left),
+                    op,
+                    right: Box::new(right),
+                },
             };
-            let right = match token_iter.next().unwrap() {
-                Token::Identifier(lit) => Expr::ExprVariable(lit),
-                Token::Literal(lit) => Expr::ExprLit(lit),
-                _ => panic!("Expected literal"),
+        }
+        Ok(left)
+    }
+
+    fn peek_binary_operator(&self) -> Option<Operator> {
+        match self.peek()? {
+            Token::LAngleBracket => Some(Operator::Lt),
+            Token::RAngleBracket => Some(Operator::Gt),
+            Token::Operator(Operator::Bang) => None,
+            Token::Operator(op) => Some(op.clone()),
+            _ => None,
+        }
+    }
+
+    /// `x as u8 as i32`。`as` は単項演算子より弱く、どの二項演算子よりも強く結合する
+    fn parse_cast_expr(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_unary_expr()?;
+        while let Some(Token::As) = self.peek() {
+            self.next();
+            let ty = self.parse_type()?;
+            expr = Expr {
+                span: expr.span.to(self.prev_span()),
+                kind: ExprKind::ExprCast {
+                    expr: Box::new(expr),
+                    ty,
+                },
             };
-            return Expr::ExprBinaryOp {
-                left: Box::new(left),
-                op: op,
-                right: Box::new(right),
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary_expr(&mut self) -> ParseResult<Expr> {
+        let op = match self.peek() {
+            Some(Token::Operator(Operator::Minus)) => Operator::Minus,
+            Some(Token::Operator(Operator::Bang)) => Operator::Bang,
+            _ => return self.parse_method_call_expr(),
+        };
+        let op_span = self.next().unwrap().span;
+        let operand = self.parse_unary_expr()?;
+        Ok(Expr {
+            span: op_span.to(operand.span),
+            kind: ExprKind::ExprUnary {
+                op,
+                operand: Box::new(operand),
+            },
+        })
+    }
+
+    /// `x.wrapping_add(1).unwrap()` のようなメソッド呼び出し。単項演算子より強く結びつく
+    fn parse_method_call_expr(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_atom()?;
+        while let Some(Token::Dot) = self.peek() {
+            self.next();
+            let (name, span) = self.expect_identifier("method name")?;
+            let args = self.parse_fn_arg()?;
+            expr = Expr {
+                span: expr.span.to(self.prev_span()),
+                kind: ExprKind::ExprMethodCall(MethodCall {
+                    receiver: Box::new(expr),
+                    name,
+                    args,
+                    span,
+                }),
             };
         }
-        Token::Semicolon => match _next_token {
-            Token::Identifier(lit) => return Expr::ExprVariable(lit),
-            Token::Literal(lit) => return Expr::ExprLit(lit),
-            _ => panic!("Expected literal"),
-        },
-        Token::LParentheses => match _next_token {
-            Token::Identifier(ident) => {
-                return Expr::ExprFnCall(parse_fn_call(token_iter, &ident));
-            }
-            _ => panic!("Expected function call"),
-        },
-        _ => panic!("Unexpected token in expression: {:?}", _next_next_token),
+        Ok(expr)
     }
-}
 
-fn parse_fn_signature(
-    token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>,
-) -> FnSignature {
-    let ident = match token_iter.next().unwrap() {
-        Token::Identifier(name) => name,
-        _ => panic!("Expected function name"),
-    };
-    match token_iter.next().unwrap() {
-        Token::LParentheses => (),
-        _ => panic!("Expected '('"),
-    };
-    let mut args = Vec::<FnParams>::new();
-    while token_iter.peek() != None {
-        match token_iter.peek().unwrap() {
-            Token::RParentheses => {
-                token_iter.next();
-                break;
+    /// 変数、リテラル、関数呼び出し、括弧で囲まれた式のいずれか
+    fn parse_atom(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some(Token::Identifier(_)) => {
+                let (ident, span) = self.expect_identifier("identifier")?;
+                if let Some(Token::LParentheses) = self.peek() {
+                    let fn_call = self.parse_fn_call(&ident, span)?;
+                    Ok(Expr {
+                        kind: ExprKind::ExprFnCall(fn_call),
+                        span: span.to(self.prev_span()),
+                    })
+                } else {
+                    Ok(Expr {
+                        kind: ExprKind::ExprVariable(ident),
+                        span,
+                    })
+                }
+            }
+            Some(
+                Token::Literal(_)
+                | Token::FloatLiteral(_)
+                | Token::StrLiteral(_)
+                | Token::CharLiteral(_)
+                | Token::ByteStrLiteral(_),
+            ) => {
+                let token = self.next().unwrap();
+                let kind = match token.token {
+                    Token::Literal(lit) => ExprKind::ExprLit(lit),
+                    Token::FloatLiteral(lit) => ExprKind::ExprFloatLit(lit),
+                    Token::StrLiteral(lit) => ExprKind::ExprStrLit(lit),
+                    Token::CharLiteral(c) => ExprKind::ExprCharLit(c),
+                    Token::ByteStrLiteral(bytes) => ExprKind::ExprByteStrLit(bytes),
+                    _ => unreachable!(),
+                };
+                Ok(Expr {
+                    kind,
+                    span: token.span,
+                })
             }
-            _ => args.push(parse_fn_params(token_iter)),
+            Some(Token::True) | Some(Token::False) => {
+                let token = self.next().unwrap();
+                Ok(Expr {
+                    kind: ExprKind::ExprBoolLit(token.token == Token::True),
+                    span: token.span,
+                })
+            }
+            Some(Token::LParentheses) => {
+                let open_span = self.next().unwrap().span;
+                let inner = self.parse_expr()?;
+                let close_span = self.expect(Token::RParentheses)?;
+                Ok(Expr {
+                    kind: inner.kind,
+                    span: open_span.to(close_span),
+                })
+            }
+            _ => Err(self
+                .unexpected("expression")
+                .with_label("expected expression")),
         }
     }
-    // TODO: コロンじゃなくて、-> であるべき
-    let output = if let Some(Token::Collon) = token_iter.peek() {
-        token_iter.next();
-        match token_iter.next().unwrap() {
-            Token::Type(t) => Some(format!("{:?}", t)),
-            _ => panic!("Expected return type"),
+
+    fn parse_type(&mut self) -> ParseResult<Ty> {
+        if let Some(Token::Operator(Operator::Ampersand)) = self.peek() {
+            self.next();
+            return self.parse_reference_type();
+        }
+        match self.peek() {
+            Some(Token::Type(t)) => {
+                let ty = match t {
+                    Type::I8 => Ty::I8,
+                    Type::I16 => Ty::I16,
+                    Type::I32 => Ty::I32,
+                    Type::I64 => Ty::I64,
+                    Type::Isize => Ty::Isize,
+                    Type::U8 => Ty::U8,
+                    Type::U16 => Ty::U16,
+                    Type::U32 => Ty::U32,
+                    Type::U64 => Ty::U64,
+                    Type::Usize => Ty::Usize,
+                    Type::F64 => Ty::F64,
+                    Type::Bool => Ty::Bool,
+                    Type::Char => Ty::Char,
+                    Type::Str => return Err(self.unsized_str_error()),
+                };
+                self.next();
+                Ok(ty)
+            }
+            _ => Err(self.unexpected_type()),
         }
-    } else {
-        None
-    };
-    FnSignature {
-        ident,
-        args,
-        output,
+    }
+
+    /// `&` の後の `str` か `[u8; N]`
+    fn parse_reference_type(&mut self) -> ParseResult<Ty> {
+        match self.peek() {
+            Some(Token::Type(Type::Str)) => {
+                self.next();
+                Ok(Ty::Str)
+            }
+            Some(Token::LBracket) => {
+                self.next();
+                if self.peek() != Some(&Token::Type(Type::U8)) {
+                    return Err(self
+                        .unexpected("`u8`")
+                        .with_label("expected `u8`")
+                        .with_note("only byte arrays `&[u8; N]` are supported"));
+                }
+                self.next();
+                self.expect(Token::Semicolon)?;
+                let len = match self.peek() {
+                    Some(Token::Literal(lit)) => lit.parse::<usize>().ok(),
+                    _ => None,
+                };
+                let Some(len) = len else {
+                    return Err(self
+                        .unexpected("array length")
+                        .with_label("expected length"));
+                };
+                self.next();
+                self.expect(Token::RBracket)?;
+                Ok(Ty::ByteStr(len))
+            }
+            _ => Err(self.unexpected_type()),
+        }
+    }
+
+    fn unexpected_type(&self) -> Diagnostic {
+        self.unexpected("type").with_label("expected type").with_note(
+            "supported types are the integer types, `f64`, `bool`, `char`, `&str` and `&[u8; N]`",
+        )
+    }
+
+    fn unsized_str_error(&self) -> Diagnostic {
+        self.unexpected("type")
+            .with_label("`str` can only be used behind a reference")
+            .with_note("use `&str` instead")
+    }
+
+    fn parse_fn_signature(&mut self) -> ParseResult<FnSignature> {
+        let (ident, span) = self.expect_identifier("function name")?;
+        self.expect(Token::LParentheses)?;
+        let mut args = Vec::<FnParams>::new();
+        loop {
+            match self.peek() {
+                Some(Token::RParentheses) => {
+                    self.next();
+                    break;
+                }
+                _ => args.push(self.parse_fn_params()?),
+            }
+        }
+        let output = match self.peek() {
+            Some(Token::Arrow) => {
+                self.next();
+                Some(self.parse_type()?)
+            }
+            Some(Token::Collon) => {
+                return Err(self
+                    .unexpected("`->` or `{`")
+                    .with_label("expected `->`")
+                    .with_note("return types are written as `fn name() -> i32`"));
+            }
+            _ => None,
+        };
+        Ok(FnSignature {
+            ident,
+            args,
+            output,
+            span,
+        })
+    }
+
+    fn parse_fn_params(&mut self) -> ParseResult<FnParams> {
+        let (name, span) = self.expect_identifier("argument name")?;
+        self.expect(Token::Collon)?;
+        let arg_type = self.parse_type()?;
+        match self.peek() {
+            Some(Token::Comma) => {
+                self.next();
+            }
+            Some(Token::RParentheses) => (),
+            _ => {
+                return Err(self
+                    .unexpected("`,` or `)`")
+                    .with_label("expected `,` or `)` after parameter"));
+            }
+        }
+        Ok(FnParams {
+            name,
+            arg_type,
+            span,
+        })
+    }
+}
+
+/// Rustと同じ二項演算子の優先順位。大きいほど強く結合する
+fn binary_precedence(op: &Operator) -> u8 {
+    match op {
+        Operator::Asterisk | Operator::Slash | Operator::Percent => 10,
+        Operator::Plus | Operator::Minus => 9,
+        Operator::Shl | Operator::Shr => 8,
+        Operator::Ampersand => 7,
+        Operator::Caret => 6,
+        Operator::Pipe => 5,
+        Operator::EqEq
+        | Operator::NotEq
+        | Operator::Lt
+        | Operator::Gt
+        | Operator::LtEq
+        | Operator::GtEq => 4,
+        Operator::AndAnd => 3,
+        Operator::OrOr => 2,
+        Operator::Bang => unreachable!("`!` is not a binary operator"),
     }
 }
 
-fn parse_fn_params(token_iter: &mut core::iter::Peekable<impl Iterator<Item = Token>>) -> FnParams {
-    let name = match token_iter.next().unwrap() {
-        Token::Identifier(name) => name,
-        _ => panic!("Expected argument name"),
-    };
-    match token_iter.next().unwrap() {
-        Token::Collon => (),
-        _ => panic!("Expected ':'"),
-    };
-    let arg_type = match token_iter.next().unwrap() {
-        Token::Type(t) => format!("{:?}", t),
-        _ => panic!("Expected argument type"),
-    };
-    if let Some(Token::Comma) = token_iter.peek() {
-        token_iter.next();
-    }
-    FnParams { name, arg_type }
+fn is_comparison(op: &Operator) -> bool {
+    binary_precedence(op) == 4
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::libs;
-    use crate::parser::token::{Token, Type};
+    use crate::parser::token::Token;
+
+    fn spanned(tokens: Vec<Token>) -> Vec<SpannedToken> {
+        tokens
+            .into_iter()
+            .map(|token| SpannedToken {
+                token,
+                span: Span::default(),
+            })
+            .collect()
+    }
+
+    fn expr(kind: ExprKind) -> Expr {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    /// 式をS式の文字列にする。`a + b * c` は `(+ a (* b c))` になる
+    fn sexpr(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::ExprLit(lit) | ExprKind::ExprFloatLit(lit) | ExprKind::ExprVariable(lit) => {
+                lit.clone()
+            }
+            ExprKind::ExprStrLit(lit) => format!("{:?}", lit),
+            ExprKind::ExprCharLit(c) => format!("{:?}", c),
+            ExprKind::ExprByteStrLit(bytes) => format!("b\"{}\"", bytes.escape_ascii()),
+            ExprKind::ExprBinaryOp { left, op, right } => {
+                format!("({} {} {})", op, sexpr(left), sexpr(right))
+            }
+            ExprKind::ExprUnary { op, operand } => format!("({} {})", op, sexpr(operand)),
+            ExprKind::ExprBoolLit(value) => value.to_string(),
+            ExprKind::ExprCast { expr, ty } => format!("(as {} {})", sexpr(expr), ty),
+            ExprKind::ExprFnCall(fn_call) => {
+                let args: Vec<String> = fn_call.args.iter().map(sexpr).collect();
+                format!("({} {})", fn_call.name, args.join(" "))
+            }
+            ExprKind::ExprMethodCall(call) => {
+                let mut parts = vec![format!(".{}", call.name), sexpr(&call.receiver)];
+                parts.extend(call.args.iter().map(sexpr));
+                format!("({})", parts.join(" "))
+            }
+        }
+    }
+
+    /// `fn main() { return <source>; }` をパースしてreturnする式をS式にする
+    fn parse_expr_source(source: &str) -> String {
+        let program = parse(&format!("fn main() {{ return {}; }}", source)).unwrap();
+        match &program.items[0] {
+            Item::ItemFn(item_fn) => match &item_fn.block[0] {
+                Statement::Return(expr) => sexpr(expr),
+                other => panic!("unexpected statement: {:?}", other),
+            },
+            other => panic!("unexpected item: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(parse_expr_source("a + b * c"), "(+ a (* b c))");
+        assert_eq!(parse_expr_source("(a + b) * c"), "(* (+ a b) c)");
+        assert_eq!(
+            parse_expr_source("a * b % c - d / e"),
+            "(- (% (* a b) c) (/ d e))"
+        );
+        assert_eq!(parse_expr_source("1 << 2 + 3"), "(<< 1 (+ 2 3))");
+        assert_eq!(parse_expr_source("a & b ^ c | d"), "(| (^ (& a b) c) d)");
+        assert_eq!(
+            parse_expr_source("a < b && c >= d || !e"),
+            "(|| (&& (< a b) (>= c d)) (! e))"
+        );
+        assert_eq!(parse_expr_source("a == b + 1"), "(== a (+ b 1))");
+        assert_eq!(
+            parse_expr_source("-a as u8 * b as i64 as f64"),
+            "(* (as (- a) u8) (as (as b i64) f64))"
+        );
+        assert_eq!(parse_expr_source("true && !false"), "(&& true (! false))");
+        assert_eq!(
+            parse_expr_source("-a.checked_neg().unwrap_or(b + 1) as u8"),
+            "(as (- (.unwrap_or (.checked_neg a) (+ b 1))) u8)"
+        );
+        assert_eq!(
+            parse_expr_source("a >> 1 != b << 2"),
+            "(!= (>> a 1) (<< b 2))"
+        );
+    }
+
+    #[test]
+    fn test_left_associativity() {
+        assert_eq!(parse_expr_source("a - b - c"), "(- (- a b) c)");
+        assert_eq!(parse_expr_source("a / b * c"), "(* (/ a b) c)");
+        assert_eq!(parse_expr_source("a || b || c"), "(|| (|| a b) c)");
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        assert_eq!(parse_expr_source("-a * b"), "(* (- a) b)");
+        assert_eq!(parse_expr_source("- -a"), "(- (- a))");
+        assert_eq!(parse_expr_source("!(a == b)"), "(! (== a b))");
+        assert_eq!(parse_expr_source("a - -1"), "(- a (- 1))");
+    }
+
+    #[test]
+    fn test_call_arguments_are_expressions() {
+        assert_eq!(
+            parse_expr_source("sum(a + 1, f(x) * 2)"),
+            "(sum (+ a 1) (* (f x) 2))"
+        );
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        let program = parse("fn main() { return (a + b) * c; }").unwrap();
+        let Item::ItemFn(item_fn) = &program.items[0] else {
+            panic!("expected fn item");
+        };
+        let Statement::Return(expr) = &item_fn.block[0] else {
+            panic!("expected return statement");
+        };
+        assert_eq!((expr.span.start, expr.span.end), (19, 30));
+        let ExprKind::ExprBinaryOp { left, .. } = &expr.kind else {
+            panic!("expected binary operation");
+        };
+        assert_eq!((left.span.start, left.span.end), (19, 26));
+    }
+
+    #[test]
+    fn test_chained_comparison() {
+        let diagnostics = parse_error("fn main() { return a < b < c; }");
+        assert_eq!(
+            diagnostics[0].message,
+            "comparison operators cannot be chained"
+        );
+        assert_eq!(parse_expr_source("(a < b) == c"), "(== (< a b) c)");
+    }
+
+    #[test]
+    fn test_parse_control_flow() {
+        let program = parse(
+            "fn main() {
+                let mut i = 0;
+                while i < 10 {
+                    if i == 3 { continue; } else if i > 8 { break; } else { i += 2; }
+                    loop { break; }
+                    i = i + 1;
+                }
+            }",
+        )
+        .unwrap();
+        let Item::ItemFn(item_fn) = &program.items[0] else {
+            panic!("expected fn item");
+        };
+        let Statement::Local(local) = &item_fn.block[0] else {
+            panic!("expected let statement");
+        };
+        assert!(local.mutable);
+        let Statement::While(while_stmt) = &item_fn.block[1] else {
+            panic!("expected while statement");
+        };
+        assert_eq!(sexpr(&while_stmt.cond), "(< i 10)");
+        let Statement::If(if_stmt) = &while_stmt.body[0] else {
+            panic!("expected if statement");
+        };
+        assert!(matches!(if_stmt.then_block[..], [Statement::Continue(_)]));
+        let Some([Statement::If(else_if)]) = if_stmt.else_block.as_deref() else {
+            panic!("expected else if");
+        };
+        assert_eq!(sexpr(&else_if.cond), "(> i 8)");
+        let Some([Statement::Assign(assign)]) = else_if.else_block.as_deref() else {
+            panic!("expected compound assignment in else block");
+        };
+        assert_eq!(
+            (assign.name.as_str(), sexpr(&assign.value).as_str()),
+            ("i", "(+ i 2)")
+        );
+        assert!(matches!(
+            &while_stmt.body[1],
+            Statement::Loop(body) if matches!(body[..], [Statement::Break(_)])
+        ));
+        assert!(matches!(&while_stmt.body[2], Statement::Assign(_)));
+    }
+
+    #[test]
+    fn test_break_outside_of_loop() {
+        let diagnostics = parse_error(
+            "fn main() {\n    if true { break; }\n    loop { continue; }\n    continue;\n}",
+        );
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (2, "`break` outside of a loop".to_string()),
+                (4, "`continue` outside of a loop".to_string()),
+            ]
+        );
+    }
+
+    fn parse_error(source: &str) -> Vec<Diagnostic> {
+        parse(source).expect_err("expected a parse error")
+    }
 
     #[test]
     fn for_test() {
         let filename = "./src/parser/test/sample.txt";
-        let source_code = libs::readfile(filename);
-        let chunks = chunker::to_token_chunks(source_code.as_str())
-            .into_iter()
-            .peekable();
-        println!("chunks: {:?}", chunks.clone().collect::<Vec<String>>());
-        let tokens = lexer::to_token_stream(chunks.collect());
+        let source_code = libs::readfile(filename).unwrap();
+        let tokens = lexer::to_token_stream(source_code.as_str()).unwrap();
         println!("tokens: {:?}", tokens);
-        let ast = parse_to_program(tokens);
+        let ast = parse_to_program(tokens).unwrap();
         println!("ast: {:?}", ast);
     }
 
+    /// sample.txt の一部を書き換えて壊したソースを返す
+    fn broken_sample(replacements: &[(&str, &str)]) -> String {
+        let mut source = std::fs::read_to_string("./src/parser/test/sample.txt").unwrap();
+        for (from, to) in replacements {
+            assert!(
+                source.contains(from),
+                "sample.txt does not contain {:?}",
+                from
+            );
+            source = source.replacen(from, to, 1);
+        }
+        source
+    }
+
+    fn messages_with_lines(diagnostics: &[Diagnostic]) -> Vec<(usize, String)> {
+        diagnostics
+            .iter()
+            .map(|d| (d.span.line, d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_recover_from_errors_in_multiple_functions() {
+        let source = broken_sample(&[
+            ("sum(1, 2);", "sum(1, 2)"),
+            ("let result = int1 + int2;", "let = int1 + int2;"),
+        ]);
+        let diagnostics = parse_error(&source);
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (3, "expected `;`, found `}`".to_string()),
+                (6, "expected identifier, found `=`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_from_multiple_errors_in_one_block() {
+        let source = broken_sample(&[
+            ("let result = int1 + int2;", "let result int1 + int2;"),
+            ("return result;", "return ;"),
+        ]);
+        let diagnostics = parse_error(&source);
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (6, "expected `=`, found `int1`".to_string()),
+                (7, "expected expression, found `;`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_from_broken_signature() {
+        let source = broken_sample(&[("fn main() {", "fn main( {"), ("int2: i32)", "int2 i32)")]);
+        let diagnostics = parse_error(&source);
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (1, "expected argument name, found `{`".to_string()),
+                (5, "expected `:`, found `i32`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_from_stray_tokens_between_items() {
+        let source = broken_sample(&[("\nfn sum", "\nlet x = 1;\nfn sum")]);
+        let diagnostics = parse_error(&source);
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![(5, "expected item, found `let`".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_recover_from_unclosed_block() {
+        let source = broken_sample(&[("}\n", ""), ("return result;", "return result")]);
+        let diagnostics = parse_error(&source);
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (4, "expected `}`, found `fn`".to_string()),
+                (7, "expected `;`, found `}`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_from_broken_block_statements() {
+        let diagnostics = parse_error(
+            "fn main() {\n    if x > { let y = 1; }\n    while true { let z = ; }\n    let ok = 1;\n    let = 2;\n}",
+        );
+        assert_eq!(
+            messages_with_lines(&diagnostics),
+            vec![
+                (2, "expected expression, found `{`".to_string()),
+                (3, "expected expression, found `;`".to_string()),
+                (5, "expected identifier, found `=`".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_semicolon() {
+        let diagnostics = parse_error("fn main() {\n    let x = 1\n}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "expected `;`, found `}`");
+        assert_eq!(
+            (diagnostics[0].span.line, diagnostics[0].span.column),
+            (3, 1)
+        );
+    }
+
+    #[test]
+    fn test_unexpected_item() {
+        let diagnostics = parse_error("let x = 1;");
+        assert_eq!(diagnostics[0].message, "expected item, found `let`");
+    }
+
+    #[test]
+    fn test_unexpected_end_of_file() {
+        let diagnostics = parse_error("fn main() {");
+        assert_eq!(diagnostics[0].message, "expected `}`, found end of file");
+        assert_eq!(diagnostics[0].span.column, 12);
+    }
+
+    #[test]
+    fn test_invalid_type() {
+        let diagnostics = parse_error("fn f(a: x) {}");
+        assert_eq!(diagnostics[0].message, "expected type, found `x`");
+        assert_eq!(diagnostics[0].span.column, 9);
+    }
+
+    #[test]
+    fn test_reference_types() {
+        let program = parse("fn f(s: &str, b: &[u8; 3], c: char) {}").unwrap();
+        let Item::ItemFn(item_fn) = &program.items[0] else {
+            panic!("expected a function");
+        };
+        let types: Vec<Ty> = item_fn.signature.args.iter().map(|a| a.arg_type).collect();
+        assert_eq!(types, vec![Ty::Str, Ty::ByteStr(3), Ty::Char]);
+        let diagnostics = parse_error("fn f(s: str) {}");
+        assert_eq!(
+            diagnostics[0].label.as_deref(),
+            Some("`str` can only be used behind a reference")
+        );
+    }
+
+    #[test]
+    fn test_doc_comments_are_attached_to_items() {
+        let program = parse(
+            "/// The answer.
+             ///
+             /// Always 42.
+             const A: i32 = 42;
+             // not a doc comment
+             fn f() {
+                 /// ignored like a normal comment
+                 let x = 1;
+             }",
+        )
+        .unwrap();
+        let docs: Vec<Option<&str>> = program
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::ItemConst(item_const) => item_const.doc.as_deref(),
+                Item::ItemFn(item_fn) => item_fn.doc.as_deref(),
+            })
+            .collect();
+        assert_eq!(docs, vec![Some("The answer.\n\nAlways 42."), None]);
+
+        let diagnostics = parse_error("fn f() {}\n/// dangling");
+        assert_eq!(diagnostics[0].message, "expected item after doc comment");
+        assert_eq!(diagnostics[0].span.line, 2);
+        let diagnostics = parse_error("fn f() {\n    let x = 1;\n    /// dangling\n}");
+        assert_eq!(
+            diagnostics[0].message,
+            "found a documentation comment that doesn't document anything"
+        );
+    }
+
     #[test]
     fn test_parse() {
         let tokens = vec![
@@ -294,25 +1199,30 @@ mod tests {
             Token::Identifier("x".to_string()),
             Token::Collon,
             Token::Type(Type::I32),
-            Token::Identifier("=".to_string()),
+            Token::Eq,
             Token::Literal("10".to_string()),
             Token::Semicolon,
             Token::RBrace,
         ];
-        let ast = parse_to_program(tokens);
+        let ast = parse_to_program(spanned(tokens)).unwrap();
         let expected_ast = Program {
             items: vec![Item::ItemFn(ItemFn {
+                doc: None,
                 signature: FnSignature {
                     ident: "main".to_string(),
                     args: vec![],
                     output: None,
+                    span: Span::default(),
                 },
                 block: vec![Statement::Local(Local {
                     name: "x".to_string(),
-                    var_type: "I32".to_string(),
-                    value: Expr::ExprLit("10".to_string()),
+                    mutable: false,
+                    var_type: Some(Ty::I32),
+                    value: expr(ExprKind::ExprLit("10".to_string())),
+                    span: Span::default(),
                 })],
             })],
+            end: Span::default(),
         };
         assert_eq!(format!("{:?}", ast), format!("{:?}", expected_ast));
     }
@@ -329,31 +1239,36 @@ mod tests {
             Token::Identifier("x".to_string()),
             Token::Collon,
             Token::Type(Type::I32),
-            Token::Identifier("=".to_string()),
+            Token::Eq,
             Token::Literal("10".to_string()),
             Token::Operator(Operator::Plus),
             Token::Literal("20".to_string()),
             Token::Semicolon,
             Token::RBrace,
         ];
-        let ast = parse_to_program(tokens);
+        let ast = parse_to_program(spanned(tokens)).unwrap();
         let expected_ast = Program {
             items: vec![Item::ItemFn(ItemFn {
+                doc: None,
                 signature: FnSignature {
                     ident: "main".to_string(),
                     args: vec![],
                     output: None,
+                    span: Span::default(),
                 },
                 block: vec![Statement::Local(Local {
                     name: "x".to_string(),
-                    var_type: "I32".to_string(),
-                    value: Expr::ExprBinaryOp {
-                        left: Box::new(Expr::ExprLit("10".to_string())),
+                    mutable: false,
+                    var_type: Some(Ty::I32),
+                    value: expr(ExprKind::ExprBinaryOp {
+                        left: Box::new(expr(ExprKind::ExprLit("10".to_string()))),
                         op: Operator::Plus,
-                        right: Box::new(Expr::ExprLit("20".to_string())),
-                    },
+                        right: Box::new(expr(ExprKind::ExprLit("20".to_string()))),
+                    }),
+                    span: Span::default(),
                 })],
             })],
+            end: Span::default(),
         };
         assert_eq!(format!("{:?}", ast), format!("{:?}", expected_ast));
     }
@@ -369,7 +1284,7 @@ mod tests {
             // let result = sum(1, 2);
             Token::Let,
             Token::Identifier("result".to_string()),
-            Token::Identifier("=".to_string()), // 代入演算子をIdentifierやOperatorで表現
+            Token::Eq,
             Token::Identifier("sum".to_string()),
             Token::LParentheses,
             Token::Literal("1".to_string()), // 数値リテラル
@@ -392,13 +1307,13 @@ mod tests {
             Token::Collon,
             Token::Type(Type::I32),
             Token::RParentheses,
-            Token::Collon,          // 戻り値の型注釈前の ':'
+            Token::Arrow,
             Token::Type(Type::I32), // 戻り値の型
             Token::LBrace,
             // let result = int1 + int2;
             Token::Let,
             Token::Identifier("result".to_string()),
-            Token::Identifier("=".to_string()),
+            Token::Eq,
             Token::Identifier("int1".to_string()),
             Token::Operator(Operator::Plus),
             Token::Identifier("int2".to_string()),
@@ -409,56 +1324,68 @@ mod tests {
             Token::Semicolon,
             Token::RBrace,
         ];
-        let ast = parse_to_program(tokens);
+        let ast = parse_to_program(spanned(tokens)).unwrap();
         let expected_ast = Program {
             items: vec![
                 Item::ItemFn(ItemFn {
+                    doc: None,
                     signature: FnSignature {
                         ident: "main".to_string(),
                         args: vec![],
                         output: None,
+                        span: Span::default(),
                     },
                     block: vec![Statement::Local(Local {
                         name: "result".to_string(),
-                        var_type: "".to_string(),
-                        value: Expr::ExprFnCall(FnCall {
+                        mutable: false,
+                        var_type: None,
+                        value: expr(ExprKind::ExprFnCall(FnCall {
                             name: "sum".to_string(),
                             args: vec![
-                                Expr::ExprLit("1".to_string()),
-                                Expr::ExprLit("2".to_string()),
+                                expr(ExprKind::ExprLit("1".to_string())),
+                                expr(ExprKind::ExprLit("2".to_string())),
                             ],
-                        }),
+                            span: Span::default(),
+                        })),
+                        span: Span::default(),
                     })],
                 }),
                 Item::ItemFn(ItemFn {
+                    doc: None,
                     signature: FnSignature {
                         ident: "sum".to_string(),
                         args: vec![
                             FnParams {
                                 name: "int1".to_string(),
-                                arg_type: "I32".to_string(),
+                                arg_type: Ty::I32,
+                                span: Span::default(),
                             },
                             FnParams {
                                 name: "int2".to_string(),
-                                arg_type: "I32".to_string(),
+                                arg_type: Ty::I32,
+                                span: Span::default(),
                             },
                         ],
-                        output: Some("I32".to_string()),
+                        output: Some(Ty::I32),
+                        span: Span::default(),
                     },
                     block: vec![
                         Statement::Local(Local {
                             name: "result".to_string(),
-                            var_type: "".to_string(),
-                            value: Expr::ExprBinaryOp {
-                                left: Box::new(Expr::ExprVariable("int1".to_string())),
+                            mutable: false,
+                            var_type: None,
+                            value: expr(ExprKind::ExprBinaryOp {
+                                left: Box::new(expr(ExprKind::ExprVariable("int1".to_string()))),
                                 op: Operator::Plus,
-                                right: Box::new(Expr::ExprVariable("int2".to_string())),
-                            },
+                                right: Box::new(expr(ExprKind::ExprVariable("int2".to_string()))),
+                            }),
+                            span: Span::default(),
                         }),
-                        Statement::Return(Expr::ExprVariable("result".to_string())),
+                        Statement::Return(expr(ExprKind::ExprVariable("result".to_string()))),
                     ],
                 }),
             ],
+            end: Span::default(),
         };
         assert_eq!(format!("{:?}", ast), format!("{:?}", expected_ast));
     }
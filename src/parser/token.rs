@@ -1,3 +1,30 @@
+/// ソースコード上の位置。`start`/`end` はバイトオフセット、`line`/`column` は1始まり。
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// `self` の先頭から `other` の末尾までを覆うSpanを返す
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     RBrace,
@@ -12,13 +39,39 @@ pub enum Token {
     Collon,
     Semicolon,
     Comma,
+    Dot,
+    Eq,
+    Arrow,
     Identifier(String),
     Let,
     Literal(String),
+    /// `1.5` や `2e10` のような浮動小数点数のリテラル
+    FloatLiteral(String),
+    /// エスケープを解いた文字列リテラルの中身
+    StrLiteral(String),
+    /// `'a'` や `'\n'`
+    CharLiteral(char),
+    /// `b"abc"`
+    ByteStrLiteral(Vec<u8>),
+    /// `/// ...` の `///` より後ろ。普通のコメントはトークンにしない
+    DocComment(String),
     Const,
     Type(Type),
     Operator(Operator),
+    /// `+=` や `<<=` などの複合代入演算子
+    OperatorEq(Operator),
     Return,
+    /// `x as u8` のキャスト
+    As,
+    True,
+    False,
+    If,
+    Else,
+    While,
+    Loop,
+    Break,
+    Continue,
+    Mut,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -27,10 +80,154 @@ pub enum Operator {
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    EqEq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    AndAnd,
+    OrOr,
+    Ampersand,
+    Pipe,
+    Caret,
+    Shl,
+    Shr,
+    Bang,
 }
 
+/// 型名のキーワード
 #[derive(Debug, PartialEq, Clone)]
 pub enum Type {
+    I8,
+    I16,
     I32,
+    I64,
+    Isize,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
     F64,
+    Bool,
+    Char,
+    /// `&str` の `str`
+    Str,
+}
+
+impl Type {
+    pub fn from_keyword(word: &str) -> Option<Type> {
+        let ty = match word {
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "isize" => Type::Isize,
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "usize" => Type::Usize,
+            "f64" => Type::F64,
+            "bool" => Type::Bool,
+            "char" => Type::Char,
+            "str" => Type::Str,
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::RBrace => f.write_str("}"),
+            Token::LBrace => f.write_str("{"),
+            Token::RParentheses => f.write_str(")"),
+            Token::LParentheses => f.write_str("("),
+            Token::RBracket => f.write_str("]"),
+            Token::LBracket => f.write_str("["),
+            Token::RAngleBracket => f.write_str(">"),
+            Token::LAngleBracket => f.write_str("<"),
+            Token::Fn => f.write_str("fn"),
+            Token::Collon => f.write_str(":"),
+            Token::Semicolon => f.write_str(";"),
+            Token::Comma => f.write_str(","),
+            Token::Dot => f.write_str("."),
+            Token::Eq => f.write_str("="),
+            Token::Arrow => f.write_str("->"),
+            Token::Identifier(name) => f.write_str(name),
+            Token::Let => f.write_str("let"),
+            Token::Literal(lit) | Token::FloatLiteral(lit) => f.write_str(lit),
+            Token::StrLiteral(lit) => write!(f, "{:?}", lit),
+            Token::CharLiteral(c) => write!(f, "{:?}", c),
+            Token::ByteStrLiteral(bytes) => write!(f, "b\"{}\"", bytes.escape_ascii()),
+            Token::DocComment(doc) => write!(f, "///{}", doc),
+            Token::Const => f.write_str("const"),
+            Token::Type(t) => write!(f, "{}", t),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::OperatorEq(op) => write!(f, "{}=", op),
+            Token::Return => f.write_str("return"),
+            Token::As => f.write_str("as"),
+            Token::True => f.write_str("true"),
+            Token::False => f.write_str("false"),
+            Token::If => f.write_str("if"),
+            Token::Else => f.write_str("else"),
+            Token::While => f.write_str("while"),
+            Token::Loop => f.write_str("loop"),
+            Token::Break => f.write_str("break"),
+            Token::Continue => f.write_str("continue"),
+            Token::Mut => f.write_str("mut"),
+        }
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Asterisk => "*",
+            Operator::Slash => "/",
+            Operator::Percent => "%",
+            Operator::EqEq => "==",
+            Operator::NotEq => "!=",
+            Operator::Lt => "<",
+            Operator::Gt => ">",
+            Operator::LtEq => "<=",
+            Operator::GtEq => ">=",
+            Operator::AndAnd => "&&",
+            Operator::OrOr => "||",
+            Operator::Ampersand => "&",
+            Operator::Pipe => "|",
+            Operator::Caret => "^",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::Bang => "!",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::I8 => f.write_str("i8"),
+            Type::I16 => f.write_str("i16"),
+            Type::I32 => f.write_str("i32"),
+            Type::I64 => f.write_str("i64"),
+            Type::Isize => f.write_str("isize"),
+            Type::U8 => f.write_str("u8"),
+            Type::U16 => f.write_str("u16"),
+            Type::U32 => f.write_str("u32"),
+            Type::U64 => f.write_str("u64"),
+            Type::Usize => f.write_str("usize"),
+            Type::F64 => f.write_str("f64"),
+            Type::Bool => f.write_str("bool"),
+            Type::Char => f.write_str("char"),
+            Type::Str => f.write_str("str"),
+        }
+    }
 }
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+
+use crate::ast::program::{Expr, ExprKind, FnCall, Item, ItemFn, Program, Span, Statement};
+use crate::diagnostic::diagnostic::Diagnostic;
+
+/// 関数、定数、引数、ローカル変数の定義ごとに振る番号
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct DefId(usize);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DefKind {
+    Fn,
+    Const,
+    Param,
+    Local { mutable: bool },
+}
+
+impl DefKind {
+    fn descr(self) -> &'static str {
+        match self {
+            DefKind::Fn => "function",
+            DefKind::Const => "constant",
+            DefKind::Param | DefKind::Local { .. } => "local variable",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Def {
+    pub name: String,
+    pub kind: DefKind,
+}
+
+/// 名前解決の結果。参照している箇所のSpanから定義を引ける
+#[derive(Debug, Default)]
+pub struct Resolutions {
+    defs: Vec<Def>,
+    /// 引数と `let` の変数名のSpanから、その定義への表
+    decls: HashMap<Span, DefId>,
+    /// 変数の参照、代入先、関数呼び出しのSpanから、参照している定義への表
+    uses: HashMap<Span, DefId>,
+}
+
+impl Resolutions {
+    pub fn def(&self, id: DefId) -> &Def {
+        &self.defs[id.0]
+    }
+
+    /// 引数か `let` で宣言された変数の定義
+    pub fn decl(&self, span: Span) -> DefId {
+        self.decls[&span]
+    }
+
+    /// 名前を参照している箇所が指す定義。名前解決に成功したプログラムでは必ず見つかる
+    pub fn res(&self, span: Span) -> DefId {
+        self.uses[&span]
+    }
+
+    fn add_def(&mut self, name: &str, kind: DefKind) -> DefId {
+        let id = DefId(self.defs.len());
+        self.defs.push(Def {
+            name: name.to_string(),
+            kind,
+        });
+        id
+    }
+}
+
+/// プログラム中の全ての名前を定義に結びつける。
+/// ブロックごとにスコープを作り、同じ名前の `let` は外側の変数を隠す
+pub fn resolve_program(program: &Program) -> Result<Resolutions, Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        resolutions: Resolutions::default(),
+        items: HashMap::new(),
+        scopes: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    // 関数と定数は宣言より前からでも参照できる
+    for item in &program.items {
+        let (name, kind, span) = match item {
+            Item::ItemFn(item_fn) => (
+                &item_fn.signature.ident,
+                DefKind::Fn,
+                item_fn.signature.span,
+            ),
+            Item::ItemConst(item_const) => (&item_const.name, DefKind::Const, item_const.span),
+        };
+        if resolver.items.contains_key(name) {
+            resolver.diagnostics.push(
+                Diagnostic::error(
+                    format!("the name `{}` is defined multiple times", name),
+                    span,
+                )
+                .with_label(format!("`{}` redefined here", name))
+                .with_note(format!(
+                    "`{}` must be defined only once in the value namespace of this module",
+                    name
+                )),
+            );
+            continue;
+        }
+        let id = resolver.resolutions.add_def(name, kind);
+        resolver.items.insert(name.clone(), id);
+    }
+    for item in &program.items {
+        match item {
+            Item::ItemFn(item_fn) => resolver.resolve_fn(item_fn),
+            Item::ItemConst(item_const) => resolver.resolve_expr(&item_const.value),
+        }
+    }
+    if resolver.diagnostics.is_empty() {
+        Ok(resolver.resolutions)
+    } else {
+        Err(resolver.diagnostics)
+    }
+}
+
+struct Resolver {
+    resolutions: Resolutions,
+    /// 関数と定数
+    items: HashMap<String, DefId>,
+    /// ブロックごとのローカル変数。後ろほど内側のスコープ
+    scopes: Vec<HashMap<String, DefId>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn resolve_fn(&mut self, item_fn: &ItemFn) {
+        let mut params = HashMap::new();
+        for param in &item_fn.signature.args {
+            if params.contains_key(&param.name) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "identifier `{}` is bound more than once in this parameter list",
+                            param.name
+                        ),
+                        param.span,
+                    )
+                    .with_label("used as parameter more than once"),
+                );
+                continue;
+            }
+            let id = self.resolutions.add_def(&param.name, DefKind::Param);
+            self.resolutions.decls.insert(param.span, id);
+            params.insert(param.name.clone(), id);
+        }
+        self.scopes = vec![params];
+        self.resolve_block(&item_fn.block);
+        self.scopes.clear();
+    }
+
+    fn resolve_block(&mut self, block: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for statement in block {
+            self.resolve_statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Local(local) => {
+                // `let x = x + 1;` の右辺の `x` は外側の `x` を指す
+                self.resolve_expr(&local.value);
+                let kind = DefKind::Local {
+                    mutable: local.mutable,
+                };
+                let id = self.resolutions.add_def(&local.name, kind);
+                self.resolutions.decls.insert(local.span, id);
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(local.name.clone(), id);
+            }
+            Statement::Assign(assign) => {
+                self.resolve_expr(&assign.value);
+                self.resolve_value(&assign.name, assign.span);
+            }
+            Statement::FnCall(fn_call) => self.resolve_fn_call(fn_call),
+            Statement::Return(expr) => self.resolve_expr(expr),
+            Statement::If(if_statement) => {
+                self.resolve_expr(&if_statement.cond);
+                self.resolve_block(&if_statement.then_block);
+                if let Some(else_block) = &if_statement.else_block {
+                    self.resolve_block(else_block);
+                }
+            }
+            Statement::While(while_statement) => {
+                self.resolve_expr(&while_statement.cond);
+                self.resolve_block(&while_statement.body);
+            }
+            Statement::Loop(block) | Statement::Block(block) => self.resolve_block(block),
+            Statement::Break(_) | Statement::Continue(_) => (),
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::ExprLit(_)
+            | ExprKind::ExprFloatLit(_)
+            | ExprKind::ExprStrLit(_)
+            | ExprKind::ExprCharLit(_)
+            | ExprKind::ExprByteStrLit(_)
+            | ExprKind::ExprBoolLit(_) => (),
+            ExprKind::ExprVariable(name) => self.resolve_value(name, expr.span),
+            ExprKind::ExprFnCall(fn_call) => self.resolve_fn_call(fn_call),
+            ExprKind::ExprUnary { operand, .. } | ExprKind::ExprCast { expr: operand, .. } => {
+                self.resolve_expr(operand)
+            }
+            ExprKind::ExprBinaryOp { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            // メソッドは型で決まるので、名前は型検査で確かめる
+            ExprKind::ExprMethodCall(call) => {
+                self.resolve_expr(&call.receiver);
+                for arg in &call.args {
+                    self.resolve_expr(arg);
+                }
+            }
+        }
+    }
+
+    fn resolve_fn_call(&mut self, fn_call: &FnCall) {
+        for arg in &fn_call.args {
+            self.resolve_expr(arg);
+        }
+        // マクロの名前は型検査で確かめる
+        if fn_call.name.ends_with('!') {
+            return;
+        }
+        match self.items.get(&fn_call.name) {
+            Some(id) if self.resolutions.def(*id).kind == DefKind::Fn => {
+                self.resolutions.uses.insert(fn_call.span, *id);
+            }
+            _ => {
+                let candidates = self.visible_names(|kind| kind == DefKind::Fn);
+                let diagnostic = Diagnostic::error(
+                    format!("cannot find function `{}` in this scope", fn_call.name),
+                    fn_call.span,
+                )
+                .with_label("not found in this scope");
+                self.diagnostics
+                    .push(with_suggestion(diagnostic, &fn_call.name, candidates));
+            }
+        }
+    }
+
+    /// 変数か定数を参照している名前を解決する
+    fn resolve_value(&mut self, name: &str, span: Span) {
+        let local = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+        let id = local.or_else(|| {
+            self.items
+                .get(name)
+                .filter(|id| self.resolutions.def(**id).kind == DefKind::Const)
+        });
+        if let Some(id) = id {
+            self.resolutions.uses.insert(span, *id);
+            return;
+        }
+        let mut diagnostic =
+            Diagnostic::error(format!("cannot find value `{}` in this scope", name), span)
+                .with_label("not found in this scope");
+        if self
+            .items
+            .get(name)
+            .is_some_and(|id| self.resolutions.def(*id).kind == DefKind::Fn)
+        {
+            diagnostic = diagnostic.with_note(format!(
+                "`{}` is a function, and functions cannot be used as values yet",
+                name
+            ));
+        }
+        let candidates = self.visible_names(|kind| kind != DefKind::Fn);
+        self.diagnostics
+            .push(with_suggestion(diagnostic, name, candidates));
+    }
+
+    /// 今のスコープから見える定義のうち `filter` を満たすもの。内側のスコープから順に並べる
+    fn visible_names(&self, filter: impl Fn(DefKind) -> bool) -> Vec<(&str, DefKind)> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(in_definition_order)
+            .chain(in_definition_order(&self.items))
+            .map(|id| self.resolutions.def(id))
+            .filter(|def| filter(def.kind))
+            .map(|def| (def.name.as_str(), def.kind))
+            .collect()
+    }
+}
+
+/// HashMapの順番で候補が変わらないように、スコープ内の定義を定義順に並べる
+fn in_definition_order(scope: &HashMap<String, DefId>) -> Vec<DefId> {
+    let mut ids: Vec<DefId> = scope.values().copied().collect();
+    ids.sort_by_key(|id| id.0);
+    ids
+}
+
+/// 似た名前の定義があれば提案を付ける
+fn with_suggestion(
+    diagnostic: Diagnostic,
+    name: &str,
+    candidates: Vec<(&str, DefKind)>,
+) -> Diagnostic {
+    // rustcと同じく、名前の長さの1/3までの編集距離なら打ち間違いとみなす
+    let max_distance = name.chars().count().max(3) / 3;
+    let best = candidates
+        .into_iter()
+        .map(|(candidate, kind)| (edit_distance(name, candidate), candidate, kind))
+        .filter(|(distance, _, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _, _)| *distance);
+    match best {
+        Some((_, candidate, kind)) => diagnostic.with_help(format!(
+            "a {} with a similar name exists: `{}`",
+            kind.descr(),
+            candidate
+        )),
+        None => diagnostic,
+    }
+}
+
+/// 2つの文字列のレーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            current.push(substitution.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::diagnostic::SubDiagnostic;
+    use crate::parser::parser::parse;
+
+    /// 各エラーを `行: メッセージ` にし、提案があれば後ろに付ける
+    fn errors(source: &str) -> Vec<String> {
+        resolve_program(&parse(source).unwrap())
+            .expect_err("expected a resolution error")
+            .iter()
+            .map(|d| {
+                let mut message = format!("{}: {}", d.span.line, d.message);
+                for child in &d.children {
+                    if let SubDiagnostic::Help(help) = child {
+                        message.push_str(&format!(" (help: {})", help));
+                    }
+                }
+                message
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shadowing_resolves_to_nearest_definition() {
+        let source = "fn main() {
+    let x = 1;
+    let x = x + 1;
+    if x == 2 {
+        let x = 10;
+        let y = x;
+    }
+    let z = x;
+}";
+        let program = parse(source).unwrap();
+        let resolutions = resolve_program(&program).unwrap();
+        let Item::ItemFn(item_fn) = &program.items[0] else {
+            panic!("expected a function");
+        };
+        let local = |statement: &Statement| match statement {
+            Statement::Local(local) => (local.span, local.value.span),
+            other => panic!("unexpected statement: {:?}", other),
+        };
+        let (first_x, _) = local(&item_fn.block[0]);
+        let (second_x, _) = local(&item_fn.block[1]);
+        let Statement::If(if_statement) = &item_fn.block[2] else {
+            panic!("expected if");
+        };
+        let (inner_x, _) = local(&if_statement.then_block[0]);
+        let (_, y_value) = local(&if_statement.then_block[1]);
+        let (_, z_value) = local(&item_fn.block[3]);
+
+        // `let x = x + 1;` の右辺は1つ目の `x`
+        let Statement::Local(second) = &item_fn.block[1] else {
+            panic!("expected let");
+        };
+        let ExprKind::ExprBinaryOp { left, .. } = &second.value.kind else {
+            panic!("expected a binary operation");
+        };
+        assert_eq!(resolutions.res(left.span), resolutions.decl(first_x));
+        assert_eq!(resolutions.res(y_value), resolutions.decl(inner_x));
+        // ブロックを抜けると内側の `x` は見えなくなる
+        assert_eq!(resolutions.res(z_value), resolutions.decl(second_x));
+        assert_eq!(
+            resolutions.def(resolutions.res(z_value)).kind,
+            DefKind::Local { mutable: false }
+        );
+    }
+
+    #[test]
+    fn test_unresolved_names_with_suggestions() {
+        assert_eq!(
+            errors(
+                "const LIMIT: i32 = 10;
+fn compute(count: i32) -> i32 {
+    let total = cont + LIMT;
+    while total > 0 {
+        let inner = 1;
+    }
+    return inner + computed(total);
+}"
+            ),
+            vec![
+                "3: cannot find value `cont` in this scope (help: a local variable with a similar name exists: `count`)",
+                "3: cannot find value `LIMT` in this scope (help: a constant with a similar name exists: `LIMIT`)",
+                "7: cannot find value `inner` in this scope",
+                "7: cannot find function `computed` in this scope (help: a function with a similar name exists: `compute`)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_statement_does_not_leak_locals() {
+        let source = "fn main() {
+    let x = 1;
+    {
+        let x = 2;
+        let y = x;
+    }
+    let z = x;
+}";
+        let program = parse(source).unwrap();
+        let resolutions = resolve_program(&program).unwrap();
+        let Item::ItemFn(item_fn) = &program.items[0] else {
+            panic!("expected a function");
+        };
+        let local = |statement: &Statement| match statement {
+            Statement::Local(local) => (local.span, local.value.span),
+            other => panic!("unexpected statement: {:?}", other),
+        };
+        let Statement::Block(block) = &item_fn.block[1] else {
+            panic!("expected a block");
+        };
+        let (outer_x, _) = local(&item_fn.block[0]);
+        let (inner_x, _) = local(&block[0]);
+        let (_, y_value) = local(&block[1]);
+        let (_, z_value) = local(&item_fn.block[2]);
+        assert_eq!(resolutions.res(y_value), resolutions.decl(inner_x));
+        assert_eq!(resolutions.res(z_value), resolutions.decl(outer_x));
+        assert_eq!(
+            errors("fn main() {\n    {\n        let inner = 1;\n    }\n    let x = inner;\n}"),
+            vec!["5: cannot find value `inner` in this scope"]
+        );
+    }
+
+    #[test]
+    fn test_suggestion_ties_prefer_earlier_definition() {
+        // 同じ距離の候補が複数あれば、先に定義された方を提案する
+        for _ in 0..10 {
+            assert_eq!(
+                errors("fn main() {\n    let ab = 1;\n    let ac = 2;\n    let y = ax;\n}"),
+                vec![
+                    "4: cannot find value `ax` in this scope (help: a local variable with a similar name exists: `ab`)"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        assert_eq!(
+            errors(
+                "fn f(a: i32, a: i32) {
+}
+const f: i32 = 1;"
+            ),
+            vec![
+                "3: the name `f` is defined multiple times",
+                "1: identifier `a` is bound more than once in this parameter list",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("count", "cuont"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::ast::program::Ty;
+
+/// 推論中の型。まだ決まっていない型は型変数で表す
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InferTy {
+    Known(Ty),
+    Var(TyVid),
+}
+
+/// 型変数の番号
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TyVid(usize);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum VarValue {
+    /// 整数リテラルの型。整数型とだけ単一化できる
+    Integer,
+    Known(Ty),
+}
+
+/// Union-Findで型変数を管理する単一化の表
+#[derive(Default)]
+pub struct InferCtxt {
+    /// 型変数ごとの親。根は自分自身を指す
+    parents: Vec<usize>,
+    /// 根の型変数が表す型
+    values: Vec<VarValue>,
+}
+
+impl InferCtxt {
+    pub fn new() -> Self {
+        InferCtxt {
+            parents: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// 整数リテラル用の型変数を作る
+    pub fn new_int_var(&mut self) -> InferTy {
+        let vid = self.parents.len();
+        self.parents.push(vid);
+        self.values.push(VarValue::Integer);
+        InferTy::Var(TyVid(vid))
+    }
+
+    fn find(&self, vid: TyVid) -> usize {
+        let mut root = vid.0;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        root
+    }
+
+    /// 型変数が既に具体的な型に決まっていればその型にする
+    pub fn shallow_resolve(&self, ty: InferTy) -> InferTy {
+        match ty {
+            InferTy::Known(_) => ty,
+            InferTy::Var(vid) => {
+                let root = self.find(vid);
+                match self.values[root] {
+                    VarValue::Known(ty) => InferTy::Known(ty),
+                    VarValue::Integer => InferTy::Var(TyVid(root)),
+                }
+            }
+        }
+    }
+
+    /// 2つの型が等しくなるように型変数を決める。矛盾する場合は `Err`
+    pub fn unify(&mut self, a: InferTy, b: InferTy) -> Result<(), ()> {
+        match (self.shallow_resolve(a), self.shallow_resolve(b)) {
+            (InferTy::Known(a), InferTy::Known(b)) => {
+                if a == b {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            }
+            (InferTy::Var(vid), InferTy::Known(ty)) | (InferTy::Known(ty), InferTy::Var(vid)) => {
+                if !ty.is_integer() {
+                    return Err(());
+                }
+                self.values[vid.0] = VarValue::Known(ty);
+                Ok(())
+            }
+            (InferTy::Var(a), InferTy::Var(b)) => {
+                // どちらも整数リテラルの型なので、片方をもう片方に繋げるだけでよい
+                if a != b {
+                    self.parents[a.0] = b.0;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_integer(&self, ty: InferTy) -> bool {
+        match self.shallow_resolve(ty) {
+            InferTy::Known(ty) => ty.is_integer(),
+            InferTy::Var(_) => true,
+        }
+    }
+
+    pub fn is_numeric(&self, ty: InferTy) -> bool {
+        match self.shallow_resolve(ty) {
+            InferTy::Known(ty) => ty.is_numeric(),
+            InferTy::Var(_) => true,
+        }
+    }
+
+    /// 推論を終えた型を返す。何にも制約されなかった整数リテラルはrustcと同じく `i32` にする
+    pub fn resolve(&self, ty: InferTy) -> Ty {
+        match self.shallow_resolve(ty) {
+            InferTy::Known(ty) => ty,
+            InferTy::Var(_) => Ty::I32,
+        }
+    }
+
+    /// エラーメッセージ用の表現。未確定の整数リテラルは rustc と同じく `integer` と書く
+    pub fn describe(&self, ty: InferTy) -> String {
+        match self.shallow_resolve(ty) {
+            InferTy::Known(ty) => format!("`{}`", ty),
+            InferTy::Var(_) => "integer".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unify_integer_vars() {
+        let mut infcx = InferCtxt::new();
+        let a = infcx.new_int_var();
+        let b = infcx.new_int_var();
+        let c = infcx.new_int_var();
+        assert_eq!(infcx.unify(a, b), Ok(()));
+        assert_eq!(infcx.describe(a), "integer");
+        assert_eq!(infcx.unify(b, InferTy::Known(Ty::I32)), Ok(()));
+        assert_eq!(infcx.shallow_resolve(a), InferTy::Known(Ty::I32));
+        assert_eq!(infcx.unify(a, InferTy::Known(Ty::Bool)), Err(()));
+        // 制約の無い整数リテラルは i32 になる
+        assert_eq!(infcx.resolve(c), Ty::I32);
+        assert_eq!(infcx.unify(c, InferTy::Known(Ty::F64)), Err(()));
+    }
+}
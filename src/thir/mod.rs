@@ -0,0 +1,2 @@
+pub mod infer;
+pub mod typeck;
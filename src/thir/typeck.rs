@@ -0,0 +1,1178 @@
+use std::collections::HashMap;
+
+use super::infer::{InferCtxt, InferTy};
+use crate::ast::program::{
+    Expr, ExprKind, FnCall, FnSignature, If, IntMethod, Item, ItemFn, MethodCall, OPTION_METHODS,
+    Operator, Program, Span, Statement, Ty,
+};
+use crate::code_gen::const_eval::{int_literal_suffix, parse_float_literal, parse_int_literal};
+use crate::diagnostic::diagnostic::Diagnostic;
+use crate::parser::format::{count_args, parse_format};
+use crate::resolve::resolve::{DefId, DefKind, Resolutions};
+
+/// 組み込みのマクロ。最初の引数はフォーマット文字列
+const MACROS: [&str; 4] = ["println!", "print!", "eprintln!", "eprint!"];
+
+/// 型検査と型推論の結果。関数本体の全ての式の型を持つ
+#[derive(Debug, Default)]
+pub struct TypeckResults {
+    /// 式のSpanから型への表
+    expr_types: HashMap<Span, Ty>,
+}
+
+impl TypeckResults {
+    pub fn expr_ty(&self, expr: &Expr) -> Option<Ty> {
+        self.expr_types.get(&expr.span).copied()
+    }
+}
+
+/// プログラム全体の型を検査し、型注釈の無い変数や整数リテラルの型を推論する。
+/// 名前解決は済んでいるものとし、見つかった型エラーは全てまとめて返す
+pub fn check_program(
+    program: &Program,
+    resolutions: &Resolutions,
+) -> Result<TypeckResults, Vec<Diagnostic>> {
+    let mut checker = TypeChecker {
+        resolutions,
+        fns: HashMap::new(),
+        consts: HashMap::new(),
+        locals: HashMap::new(),
+        return_ty: Ty::Unit,
+        infcx: InferCtxt::new(),
+        expr_types: Vec::new(),
+        int_literals: Vec::new(),
+        results: TypeckResults::default(),
+        diagnostics: Vec::new(),
+    };
+    for item in &program.items {
+        match item {
+            Item::ItemFn(item_fn) => {
+                let signature = &item_fn.signature;
+                checker.fns.insert(signature.ident.clone(), signature);
+            }
+            Item::ItemConst(item_const) => {
+                checker
+                    .consts
+                    .insert(item_const.name.clone(), item_const.const_type);
+            }
+        }
+    }
+    if !checker.fns.contains_key("main") {
+        checker.diagnostics.push(
+            Diagnostic::error("`main` function not found in crate", program.end)
+                .with_label("consider adding a `main` function"),
+        );
+    }
+    // const の初期化式は const_eval が評価しながら検査する
+    for item in &program.items {
+        if let Item::ItemFn(item_fn) = item {
+            checker.check_fn(item_fn);
+        }
+    }
+    if checker.diagnostics.is_empty() {
+        Ok(checker.results)
+    } else {
+        // リテラルの範囲は関数の最後に確かめるので、ソースコード上の順に並べ直す
+        checker.diagnostics.sort_by_key(|d| d.span.start);
+        Err(checker.diagnostics)
+    }
+}
+
+struct TypeChecker<'a> {
+    resolutions: &'a Resolutions,
+    fns: HashMap<String, &'a FnSignature>,
+    consts: HashMap<String, Ty>,
+    /// 引数とローカル変数の型。初期化式の型エラーで型が決まらなかった変数は `None`
+    locals: HashMap<DefId, Option<InferTy>>,
+    /// 検査中の関数の戻り値の型
+    return_ty: Ty,
+    /// 型推論は関数ごとに行う
+    infcx: InferCtxt,
+    /// 検査中の関数の式の型。関数の検査を終えてから確定させる
+    expr_types: Vec<(Span, InferTy)>,
+    /// 型が決まってから範囲を確かめる整数リテラルとその値
+    int_literals: Vec<(Span, String, i128, InferTy)>,
+    results: TypeckResults,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn check_fn(&mut self, item_fn: &ItemFn) {
+        let signature = &item_fn.signature;
+        if signature.ident == "main" {
+            self.check_main_signature(signature);
+        }
+        self.return_ty = signature.output.unwrap_or(Ty::Unit);
+        self.infcx = InferCtxt::new();
+        for param in &signature.args {
+            let id = self.resolutions.decl(param.span);
+            self.locals.insert(id, Some(InferTy::Known(param.arg_type)));
+        }
+        self.check_block(&item_fn.block);
+        self.resolve_fn_types();
+        if self.return_ty != Ty::Unit && !block_diverges(&item_fn.block) {
+            self.diagnostics.push(
+                Diagnostic::error("mismatched types", signature.span)
+                    .with_label(format!("expected `{}`, found `()`", self.return_ty))
+                    .with_note(
+                        "the function implicitly returns `()` because not every path ends with `return`",
+                    ),
+            );
+        }
+    }
+
+    /// `main` は引数を取らず、`()` か終了コードにする整数を返す
+    fn check_main_signature(&mut self, signature: &FnSignature) {
+        if !signature.args.is_empty() {
+            let args: Vec<String> = signature
+                .args
+                .iter()
+                .map(|arg| arg.arg_type.to_string())
+                .collect();
+            self.diagnostics.push(
+                Diagnostic::error("`main` function has wrong type", signature.span).with_label(
+                    format!(
+                        "expected signature `fn()`, found signature `fn({})`",
+                        args.join(", ")
+                    ),
+                ),
+            );
+        }
+        if let Some(output) = signature.output
+            && !output.is_integer()
+        {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("`main` has invalid return type `{}`", output),
+                    signature.span,
+                )
+                .with_label("`main` can only return `()` or an integer exit code"),
+            );
+        }
+    }
+
+    /// 関数内の式の型を確定させ、整数リテラルが確定した型に収まるか確かめる
+    fn resolve_fn_types(&mut self) {
+        for (span, ty) in std::mem::take(&mut self.expr_types) {
+            let ty = self.infcx.resolve(ty);
+            self.results.expr_types.insert(span, ty);
+        }
+        for (span, lit, value, ty) in std::mem::take(&mut self.int_literals) {
+            let ty = self.infcx.resolve(ty);
+            let (min, max) = ty.int_range();
+            if !(min..=max).contains(&value) {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("literal out of range for `{}`", ty), span)
+                        .with_note(format!(
+                            "the literal `{}` does not fit into the type `{}` whose range is `{}..={}`",
+                            lit, ty, min, max
+                        )),
+                );
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &[Statement]) {
+        for statement in block {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Local(local) => {
+                let found = self.check_expr(&local.value);
+                let annotated = local.var_type.map(InferTy::Known);
+                if let (Some(expected), Some(found)) = (annotated, found) {
+                    self.expect_ty(expected, found, local.value.span);
+                }
+                let id = self.resolutions.decl(local.span);
+                self.locals.insert(id, annotated.or(found));
+            }
+            Statement::Assign(assign) => {
+                let found = self.check_expr(&assign.value);
+                let id = self.resolutions.res(assign.span);
+                let message = match self.resolutions.def(id).kind {
+                    DefKind::Local { mutable: true } => None,
+                    DefKind::Local { mutable: false } => Some((
+                        format!(
+                            "cannot assign twice to immutable variable `{}`",
+                            assign.name
+                        ),
+                        "cannot assign twice to immutable variable",
+                    )),
+                    DefKind::Param => Some((
+                        format!("cannot assign to immutable argument `{}`", assign.name),
+                        "cannot assign to immutable argument",
+                    )),
+                    DefKind::Const | DefKind::Fn => {
+                        self.diagnostics.push(
+                            Diagnostic::error("invalid left-hand side of assignment", assign.span)
+                                .with_label("cannot assign to this expression"),
+                        );
+                        return;
+                    }
+                };
+                if let Some((message, label)) = message {
+                    self.diagnostics.push(
+                        Diagnostic::error(message, assign.span)
+                            .with_label(label)
+                            .with_note(format!(
+                                "consider making this binding mutable: `mut {}`",
+                                assign.name
+                            )),
+                    );
+                }
+                if let (Some(expected), Some(found)) = (self.locals[&id], found) {
+                    self.expect_ty(expected, found, assign.value.span);
+                }
+            }
+            Statement::FnCall(fn_call) => {
+                self.check_fn_call(fn_call);
+            }
+            Statement::Return(expr) => {
+                if let Some(found) = self.check_expr(expr) {
+                    self.expect_ty(InferTy::Known(self.return_ty), found, expr.span);
+                }
+            }
+            Statement::If(if_statement) => self.check_if(if_statement),
+            Statement::While(while_statement) => {
+                self.check_cond(&while_statement.cond);
+                self.check_block(&while_statement.body);
+            }
+            Statement::Loop(block) | Statement::Block(block) => self.check_block(block),
+            Statement::Break(_) | Statement::Continue(_) => (),
+        }
+    }
+
+    fn check_if(&mut self, if_statement: &If) {
+        self.check_cond(&if_statement.cond);
+        self.check_block(&if_statement.then_block);
+        if let Some(else_block) = &if_statement.else_block {
+            self.check_block(else_block);
+        }
+    }
+
+    /// `if` と `while` の条件式は `bool` でなければならない
+    fn check_cond(&mut self, cond: &Expr) {
+        if let Some(found) = self.check_expr(cond) {
+            self.expect_ty(InferTy::Known(Ty::Bool), found, cond.span);
+        }
+    }
+
+    /// 式の型を返す。型エラーを報告済みで型が決まらない場合は `None`
+    fn check_expr(&mut self, expr: &Expr) -> Option<InferTy> {
+        let ty = self.check_expr_kind(expr)?;
+        self.expr_types.push((expr.span, ty));
+        Some(ty)
+    }
+
+    fn check_expr_kind(&mut self, expr: &Expr) -> Option<InferTy> {
+        match &expr.kind {
+            ExprKind::ExprLit(lit) => self.check_int_literal(lit, expr.span, false),
+            ExprKind::ExprFloatLit(lit) => self.check_float_literal(lit, expr.span),
+            ExprKind::ExprStrLit(_) => Some(InferTy::Known(Ty::Str)),
+            ExprKind::ExprCharLit(_) => Some(InferTy::Known(Ty::Char)),
+            ExprKind::ExprByteStrLit(bytes) => Some(InferTy::Known(Ty::ByteStr(bytes.len()))),
+            ExprKind::ExprBoolLit(_) => Some(InferTy::Known(Ty::Bool)),
+            ExprKind::ExprCast { expr: operand, ty } => {
+                let from = self.check_expr(operand)?;
+                self.check_cast(from, *ty, expr.span)
+            }
+            ExprKind::ExprVariable(name) => {
+                let id = self.resolutions.res(expr.span);
+                match self.resolutions.def(id).kind {
+                    DefKind::Const => Some(InferTy::Known(self.consts[name])),
+                    _ => self.locals[&id],
+                }
+            }
+            ExprKind::ExprFnCall(fn_call) => self.check_fn_call(fn_call),
+            ExprKind::ExprMethodCall(call) => self.check_method_call(call),
+            ExprKind::ExprUnary { op, operand } => {
+                // `-2147483648` は符号を含めて1つのリテラルとして範囲を確かめる
+                let ty = match (&operand.kind, op) {
+                    (ExprKind::ExprLit(lit), Operator::Minus) => {
+                        self.check_int_literal(lit, expr.span, true)?
+                    }
+                    _ => self.check_expr(operand)?,
+                };
+                let unsigned = matches!(
+                    self.infcx.shallow_resolve(ty),
+                    InferTy::Known(ty) if !ty.is_signed()
+                );
+                let supported = match op {
+                    Operator::Minus => self.infcx.is_numeric(ty) && !unsigned,
+                    _ => self.infcx.is_integer(ty) || self.is_bool(ty),
+                };
+                if !supported {
+                    let mut diagnostic = Diagnostic::error(
+                        format!(
+                            "cannot apply unary operator `{}` to type {}",
+                            op,
+                            self.infcx.describe(ty)
+                        ),
+                        expr.span,
+                    )
+                    .with_label(format!("cannot apply unary operator `{}`", op));
+                    if unsigned && self.infcx.is_integer(ty) {
+                        diagnostic = diagnostic.with_note("unsigned values cannot be negated");
+                    }
+                    self.diagnostics.push(diagnostic);
+                    return None;
+                }
+                Some(ty)
+            }
+            ExprKind::ExprBinaryOp { left, op, right } => {
+                let left_ty = self.check_expr(left);
+                let right_ty = self.check_expr(right);
+                self.check_binary_op(op, left_ty?, right_ty?, expr.span, right.span)
+            }
+        }
+    }
+
+    fn check_binary_op(
+        &mut self,
+        op: &Operator,
+        left: InferTy,
+        right: InferTy,
+        span: Span,
+        right_span: Span,
+    ) -> Option<InferTy> {
+        let bool_ty = InferTy::Known(Ty::Bool);
+        let (supported, result) = match op {
+            Operator::AndAnd | Operator::OrOr => (self.is_bool(left), bool_ty),
+            Operator::EqEq
+            | Operator::NotEq
+            | Operator::Lt
+            | Operator::Gt
+            | Operator::LtEq
+            | Operator::GtEq => (
+                self.infcx.is_numeric(left)
+                    || self.is_bool(left)
+                    || left == InferTy::Known(Ty::Char),
+                bool_ty,
+            ),
+            Operator::Plus
+            | Operator::Minus
+            | Operator::Asterisk
+            | Operator::Slash
+            | Operator::Percent => (self.infcx.is_numeric(left), left),
+            Operator::Ampersand | Operator::Pipe | Operator::Caret => {
+                (self.infcx.is_integer(left) || self.is_bool(left), left)
+            }
+            Operator::Shl | Operator::Shr => (self.infcx.is_integer(left), left),
+            Operator::Bang => unreachable!("`!` is not a binary operator"),
+        };
+        if !supported {
+            let left = self.infcx.describe(left);
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "binary operation `{}` cannot be applied to type {}",
+                        op, left
+                    ),
+                    span,
+                )
+                .with_label(format!("{} does not support `{}`", left, op)),
+            );
+            return None;
+        }
+        // シフト量は左辺と違う整数型でもよいので、整数であることだけ確かめる
+        if matches!(op, Operator::Shl | Operator::Shr) {
+            if self.infcx.is_integer(right) {
+                return Some(result);
+            }
+            self.diagnostics.push(
+                Diagnostic::error("mismatched types", right_span).with_label(format!(
+                    "expected integer, found {}",
+                    self.infcx.describe(right)
+                )),
+            );
+            return None;
+        }
+        if self.expect_ty(left, right, right_span) {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    fn check_fn_call(&mut self, fn_call: &FnCall) -> Option<InferTy> {
+        let arg_tys: Vec<Option<InferTy>> = fn_call
+            .args
+            .iter()
+            .map(|arg| self.check_expr(arg))
+            .collect();
+        if fn_call.name.ends_with('!') {
+            return self.check_macro_call(fn_call, &arg_tys);
+        }
+        let signature = self.fns[&fn_call.name];
+        let output = InferTy::Known(signature.output.unwrap_or(Ty::Unit));
+        if fn_call.args.len() != signature.args.len() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "this function takes {} but {} {} supplied",
+                        plural(signature.args.len(), "argument"),
+                        plural(fn_call.args.len(), "argument"),
+                        if fn_call.args.len() == 1 {
+                            "was"
+                        } else {
+                            "were"
+                        }
+                    ),
+                    fn_call.span,
+                )
+                .with_label(format!(
+                    "expected {}",
+                    plural(signature.args.len(), "argument")
+                ))
+                .with_note(format!(
+                    "function `{}` is defined on line {}",
+                    signature.ident, signature.span.line
+                )),
+            );
+            return Some(output);
+        }
+        for ((arg, found), param) in fn_call.args.iter().zip(arg_tys).zip(&signature.args) {
+            if let Some(found) = found {
+                self.expect_ty(InferTy::Known(param.arg_type), found, arg.span);
+            }
+        }
+        Some(output)
+    }
+
+    /// メソッド呼び出しを検査する。`checked_*` の結果は `Option` のメソッドで
+    /// すぐに取り出す場合だけ使える
+    fn check_method_call(&mut self, call: &MethodCall) -> Option<InferTy> {
+        if let ExprKind::ExprMethodCall(inner) = &call.receiver.kind
+            && OPTION_METHODS.contains(&call.name.as_str())
+            && IntMethod::from_name(&inner.name).is_some_and(|method| method.checked)
+        {
+            let ty = self.check_int_method(inner);
+            let arg_tys: Vec<Option<InferTy>> =
+                call.args.iter().map(|arg| self.check_expr(arg)).collect();
+            let ty = ty?;
+            let expected = if call.name == "unwrap_or" { 1 } else { 0 };
+            if !self.check_method_arity(call, expected) {
+                return None;
+            }
+            // `unwrap_or` の引数は取り出す値と同じ型
+            if let (Some(arg), Some(Some(found))) = (call.args.first(), arg_tys.first()) {
+                self.expect_ty(ty, *found, arg.span);
+            }
+            return Some(match call.name.as_str() {
+                "is_some" | "is_none" => InferTy::Known(Ty::Bool),
+                _ => ty,
+            });
+        }
+        if call.name == "len" {
+            return self.check_len(call);
+        }
+        match IntMethod::from_name(&call.name) {
+            Some(method) if method.checked => {
+                self.check_int_method(call)?;
+                self.diagnostics.push(
+                    Diagnostic::error("`Option` values are not supported yet", call.span)
+                        .with_label(
+                            "use `.unwrap()`, `.unwrap_or(..)`, `.is_some()` or `.is_none()` on the result",
+                        ),
+                );
+                None
+            }
+            Some(_) => self.check_int_method(call),
+            None => {
+                let receiver = self.check_expr(&call.receiver)?;
+                self.no_method_error(call, receiver);
+                None
+            }
+        }
+    }
+
+    /// `wrapping_add` などの整数のメソッドを検査し、演算結果の型を返す。
+    /// シフト量は `u32`、それ以外の引数は受け取る値と同じ型
+    fn check_int_method(&mut self, call: &MethodCall) -> Option<InferTy> {
+        let receiver = self.check_expr(&call.receiver);
+        let args: Vec<Option<InferTy>> = call.args.iter().map(|arg| self.check_expr(arg)).collect();
+        let receiver = receiver?;
+        if !self.infcx.is_integer(receiver) {
+            self.no_method_error(call, receiver);
+            return None;
+        }
+        let op = IntMethod::from_name(&call.name)?.op;
+        if !self.check_method_arity(call, if op.is_some() { 1 } else { 0 }) {
+            return None;
+        }
+        if let (Some(arg), Some(Some(found))) = (call.args.first(), args.first()) {
+            let expected = match op {
+                Some(Operator::Shl | Operator::Shr) => InferTy::Known(Ty::U32),
+                _ => receiver,
+            };
+            self.expect_ty(expected, *found, arg.span);
+        }
+        Some(receiver)
+    }
+
+    /// 文字列とバイト文字列の `len()`。長さはバイト数で `usize`
+    fn check_len(&mut self, call: &MethodCall) -> Option<InferTy> {
+        let receiver = self.check_expr(&call.receiver)?;
+        if !matches!(receiver, InferTy::Known(Ty::Str | Ty::ByteStr(_))) {
+            self.no_method_error(call, receiver);
+            return None;
+        }
+        if !self.check_method_arity(call, 0) {
+            return None;
+        }
+        Some(InferTy::Known(Ty::Usize))
+    }
+
+    fn no_method_error(&mut self, call: &MethodCall, receiver: InferTy) {
+        let receiver = self.infcx.describe(receiver);
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!(
+                    "no method named `{}` found for {} in the current scope",
+                    call.name, receiver
+                ),
+                call.span,
+            )
+            .with_label(format!("method not found in {}", receiver)),
+        );
+    }
+
+    /// メソッドの引数の数が `expected` か確かめる
+    fn check_method_arity(&mut self, call: &MethodCall, expected: usize) -> bool {
+        if call.args.len() == expected {
+            return true;
+        }
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!(
+                    "this method takes {} but {} {} supplied",
+                    plural(expected, "argument"),
+                    plural(call.args.len(), "argument"),
+                    if call.args.len() == 1 { "was" } else { "were" }
+                ),
+                call.span,
+            )
+            .with_label(format!("expected {}", plural(expected, "argument"))),
+        );
+        false
+    }
+
+    fn check_macro_call(
+        &mut self,
+        fn_call: &FnCall,
+        arg_tys: &[Option<InferTy>],
+    ) -> Option<InferTy> {
+        if !MACROS.contains(&fn_call.name.as_str()) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "cannot find macro `{}` in this scope",
+                        fn_call.name.trim_end_matches('!')
+                    ),
+                    fn_call.span,
+                )
+                .with_label("not found in this scope"),
+            );
+            return None;
+        }
+        let unit = Some(InferTy::Known(Ty::Unit));
+        let format = match fn_call.args.first() {
+            Some(Expr {
+                kind: ExprKind::ExprStrLit(lit),
+                span,
+            }) => (lit, *span),
+            Some(arg) => {
+                self.diagnostics.push(
+                    Diagnostic::error("format argument must be a string literal", arg.span)
+                        .with_label("expected a string literal"),
+                );
+                return unit;
+            }
+            // `println!()` は改行だけを出力する
+            None if fn_call.name.ends_with("ln!") => return unit,
+            None => {
+                self.diagnostics.push(
+                    Diagnostic::error("requires at least a format string argument", fn_call.span)
+                        .with_label(format!("`{}` needs a format string", fn_call.name)),
+                );
+                return unit;
+            }
+        };
+        let pieces = match parse_format(format.0, format.1) {
+            Ok(pieces) => pieces,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                return unit;
+            }
+        };
+        let placeholders = count_args(&pieces);
+        let args = &fn_call.args[1..];
+        if placeholders > args.len() {
+            let given = match args.len() {
+                0 => "no arguments were given".to_string(),
+                1 => "there is 1 argument".to_string(),
+                n => format!("there are {} arguments", n),
+            };
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "{} in format string, but {}",
+                    plural(placeholders, "positional argument"),
+                    given
+                ),
+                format.1,
+            ));
+        }
+        for arg in args.iter().skip(placeholders) {
+            self.diagnostics.push(
+                Diagnostic::error("argument never used", arg.span)
+                    .with_label("argument never used"),
+            );
+        }
+        for (arg, ty) in args.iter().zip(&arg_tys[1..]) {
+            self.check_format_arg(arg, *ty);
+        }
+        unit
+    }
+
+    /// 書式の引数が出力できる値か確かめる。整数と `bool`、`char`、`&str` だけが出力できる
+    fn check_format_arg(&mut self, arg: &Expr, ty: Option<InferTy>) {
+        let Some(InferTy::Known(ty)) = ty.map(|ty| self.infcx.shallow_resolve(ty)) else {
+            return;
+        };
+        if !(ty.is_integer() || matches!(ty, Ty::Bool | Ty::Char | Ty::Str)) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("cannot format `{}` values yet", ty), arg.span)
+                    .with_label("only integers, `bool`, `char` and `&str` can be formatted"),
+            );
+        }
+    }
+
+    /// `as` で変換できるか確かめる。数値同士と、`bool` と `char` から整数、`u8` から `char` への変換だけができる
+    fn check_cast(&mut self, from: InferTy, to: Ty, span: Span) -> Option<InferTy> {
+        // `97 as char` のリテラルは `u8` にする
+        if to == Ty::Char && matches!(self.infcx.shallow_resolve(from), InferTy::Var(_)) {
+            self.infcx.unify(from, InferTy::Known(Ty::U8)).ok()?;
+        }
+        let valid = match self.infcx.shallow_resolve(from) {
+            InferTy::Var(_) => to.is_numeric(),
+            InferTy::Known(from) => {
+                (from.is_numeric() && to.is_numeric())
+                    || (matches!(from, Ty::Bool | Ty::Char) && to.is_integer())
+                    || (from == Ty::U8 && to == Ty::Char)
+                    || (from == to && matches!(from, Ty::Bool | Ty::Char))
+            }
+        };
+        if valid {
+            return Some(InferTy::Known(to));
+        }
+        let diagnostic = if to == Ty::Char && self.infcx.is_integer(from) {
+            Diagnostic::error(
+                format!(
+                    "only `u8` can be cast as `char`, not {}",
+                    self.infcx.describe(from)
+                ),
+                span,
+            )
+            .with_label("invalid cast")
+            .with_note("use `char::from_u32` instead")
+        } else if to == Ty::Bool && self.infcx.is_numeric(from) {
+            Diagnostic::error(
+                format!("cannot cast {} as `bool`", self.infcx.describe(from)),
+                span,
+            )
+            .with_label("compare with zero instead: `x != 0`")
+        } else {
+            Diagnostic::error(
+                format!(
+                    "casting {} as `{}` is invalid",
+                    self.infcx.describe(from),
+                    to
+                ),
+                span,
+            )
+        };
+        self.diagnostics.push(diagnostic);
+        None
+    }
+
+    /// 整数リテラルの型は型変数にして、使われ方から推論する。`10u8` のようにサフィックスがあればその型にする
+    fn check_int_literal(&mut self, lit: &str, span: Span, negated: bool) -> Option<InferTy> {
+        let Some(value) = parse_int_literal(lit) else {
+            self.diagnostics.push(
+                Diagnostic::error(format!("invalid integer literal `{}`", lit), span)
+                    .with_label("invalid integer literal"),
+            );
+            return None;
+        };
+        let value = if negated { -value } else { value };
+        let ty = match int_literal_suffix(lit) {
+            Some(ty) => InferTy::Known(ty),
+            None => self.infcx.new_int_var(),
+        };
+        self.int_literals.push((span, lit.to_string(), value, ty));
+        Some(ty)
+    }
+
+    fn check_float_literal(&mut self, lit: &str, span: Span) -> Option<InferTy> {
+        if parse_float_literal(lit).is_none() {
+            self.diagnostics.push(
+                Diagnostic::error(format!("invalid float literal `{}`", lit), span)
+                    .with_label("invalid float literal"),
+            );
+            return None;
+        }
+        Some(InferTy::Known(Ty::F64))
+    }
+
+    fn is_bool(&self, ty: InferTy) -> bool {
+        self.infcx.shallow_resolve(ty) == InferTy::Known(Ty::Bool)
+    }
+
+    /// 2つの型を単一化する。一致しなければ `mismatched types` を報告して `false` を返す
+    fn expect_ty(&mut self, expected: InferTy, found: InferTy, span: Span) -> bool {
+        if self.infcx.unify(expected, found).is_ok() {
+            return true;
+        }
+        self.diagnostics.push(
+            Diagnostic::error("mismatched types", span).with_label(format!(
+                "expected {}, found {}",
+                self.infcx.describe(expected),
+                self.infcx.describe(found)
+            )),
+        );
+        false
+    }
+}
+
+/// ブロックの最後まで実行が到達しないか。`return` や `break` の無い `loop` で終わる場合など
+fn block_diverges(block: &[Statement]) -> bool {
+    block.iter().any(|statement| match statement {
+        Statement::Return(_) => true,
+        Statement::If(If {
+            then_block,
+            else_block: Some(else_block),
+            ..
+        }) => block_diverges(then_block) && block_diverges(else_block),
+        Statement::Loop(body) => !contains_break(body),
+        Statement::Block(block) => block_diverges(block),
+        _ => false,
+    })
+}
+
+/// このループを抜ける `break` があるか。内側のループの `break` は数えない
+fn contains_break(block: &[Statement]) -> bool {
+    block.iter().any(|statement| match statement {
+        Statement::Break(_) => true,
+        Statement::If(if_statement) => {
+            contains_break(&if_statement.then_block)
+                || if_statement
+                    .else_block
+                    .as_ref()
+                    .is_some_and(|else_block| contains_break(else_block))
+        }
+        Statement::Block(block) => contains_break(block),
+        _ => false,
+    })
+}
+
+/// `1 argument` や `2 arguments` のように数と名詞をつなげる
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse;
+    use crate::resolve::resolve::resolve_program;
+
+    fn check(source: &str) -> Result<TypeckResults, Vec<Diagnostic>> {
+        let program = parse(source).unwrap();
+        check_program(&program, &resolve_program(&program).unwrap())
+    }
+
+    /// 最後の関数の本体で宣言された変数と、推論された型の組
+    fn inferred_local_types(source: &str) -> Vec<(String, Ty)> {
+        let program = parse(source).unwrap();
+        let results = check_program(&program, &resolve_program(&program).unwrap()).unwrap();
+        let Some(Item::ItemFn(item_fn)) = program.items.last() else {
+            panic!("expected a function");
+        };
+        item_fn
+            .block
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Local(local) => {
+                    Some((local.name.clone(), results.expr_ty(&local.value).unwrap()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 各エラーを `行: メッセージ: ラベル` の形にする
+    fn errors(source: &str) -> Vec<String> {
+        check(source)
+            .expect_err("expected a type error")
+            .iter()
+            .map(|d| match &d.label {
+                Some(label) => format!("{}: {}: {}", d.span.line, d.message, label),
+                None => format!("{}: {}", d.span.line, d.message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_well_typed_program() {
+        check(
+            "
+const LIMIT: i32 = 10;
+
+fn clamp(n: i32) -> i32 {
+    let small = n < LIMIT;
+    if small && !(n == 0) {
+        return n;
+    } else if n >= LIMIT {
+        return LIMIT;
+    }
+    loop {
+        return 0;
+    }
+}
+
+fn main() {
+    let mut x = clamp(3) + -1;
+    x = x << 2 | 1;
+    println!(\"done\");
+}
+",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_fn_call_errors() {
+        assert_eq!(
+            errors(
+                "fn sum(a: i32, b: i32) -> i32 {
+    return a + b;
+}
+fn main() {
+    let x = sum(1);
+    let y = sum(1, 2 == 2);
+    missing!(x, y);
+}"
+            ),
+            vec![
+                "5: this function takes 2 arguments but 1 argument was supplied: expected 2 arguments",
+                "6: mismatched types: expected `i32`, found `bool`",
+                "7: cannot find macro `missing` in this scope: not found in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_return_type_errors() {
+        assert_eq!(
+            errors(
+                "fn f(n: i32) -> i32 {
+    return n == 1;
+}
+fn g(n: i32) {
+    return n;
+}
+fn h(n: i32) -> i32 {
+    if n > 0 {
+        return 1;
+    }
+}
+fn main() {}"
+            ),
+            vec![
+                "2: mismatched types: expected `i32`, found `bool`",
+                "5: mismatched types: expected `()`, found `i32`",
+                "7: mismatched types: expected `i32`, found `()`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_main_signature_errors() {
+        assert_eq!(
+            errors("fn main(x: i32, b: bool) {\n}"),
+            vec![
+                "1: `main` function has wrong type: expected signature `fn()`, found signature `fn(i32, bool)`"
+            ]
+        );
+        assert_eq!(
+            errors("fn main() -> bool {\n    return true;\n}"),
+            vec![
+                "1: `main` has invalid return type `bool`: `main` can only return `()` or an integer exit code"
+            ]
+        );
+        check("fn main() -> u8 {\n    return 0;\n}").unwrap();
+    }
+
+    #[test]
+    fn test_missing_main() {
+        assert_eq!(
+            errors("fn f() {\n}\n"),
+            vec!["2: `main` function not found in crate: consider adding a `main` function"]
+        );
+        assert_eq!(
+            errors(""),
+            vec!["1: `main` function not found in crate: consider adding a `main` function"]
+        );
+    }
+
+    #[test]
+    fn test_binary_and_condition_errors() {
+        assert_eq!(
+            errors(
+                "fn main() {
+    let b = 1 < 2;
+    let x = 1 + b;
+    let y = b + 1;
+    if 1 {
+    }
+    let z = -b;
+}"
+            ),
+            vec![
+                "3: mismatched types: expected integer, found `bool`",
+                "4: binary operation `+` cannot be applied to type `bool`: `bool` does not support `+`",
+                "5: mismatched types: expected `bool`, found integer",
+                "7: cannot apply unary operator `-` to type `bool`: cannot apply unary operator `-`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assignment_errors() {
+        assert_eq!(
+            errors(
+                "const C: i32 = 1;
+fn main() {
+    let x = 1;
+    x = 2;
+    let mut y = 1;
+    y = 1 == 1;
+    C = 3;
+}
+fn f(n: i32) {
+    n += 1;
+}"
+            ),
+            vec![
+                "4: cannot assign twice to immutable variable `x`: cannot assign twice to immutable variable",
+                "6: mismatched types: expected integer, found `bool`",
+                "7: invalid left-hand side of assignment: cannot assign to this expression",
+                "10: cannot assign to immutable argument `n`: cannot assign to immutable argument",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_infer_untyped_locals() {
+        let types = inferred_local_types(
+            "fn twice(n: i32) -> i32 {
+    return n + n;
+}
+fn main() {
+    let a = 1;
+    let b = twice(a);
+    let c = a < b;
+    let d = !c;
+    let e = 1 << 2;
+}",
+        );
+        assert_eq!(
+            types,
+            vec![
+                ("a".to_string(), Ty::I32),
+                ("b".to_string(), Ty::I32),
+                ("c".to_string(), Ty::Bool),
+                ("d".to_string(), Ty::Bool),
+                ("e".to_string(), Ty::I32),
+            ]
+        );
+        assert_eq!(
+            errors(
+                "fn main() {
+    let x = 1;
+    let y = x + 2;
+    let flag = 1 == y;
+    let z = flag + x;
+}"
+            ),
+            vec![
+                "5: binary operation `+` cannot be applied to type `bool`: `bool` does not support `+`"
+            ]
+        );
+        assert_eq!(
+            errors(
+                "fn f(b: i32) -> i32 {
+    let x = 3;
+    if x {
+        return 1;
+    }
+    return b;
+}
+fn main() {}"
+            ),
+            vec!["3: mismatched types: expected `bool`, found integer"]
+        );
+    }
+
+    #[test]
+    fn test_literal_range_and_shadowing() {
+        check("fn main() { let x = -2147483648; }").unwrap();
+        assert_eq!(
+            errors(
+                "fn main() {
+    let x = 2147483648;
+    let y = 1 < 2;
+    let y = 1;
+    let z = -y;
+}"
+            ),
+            vec!["2: literal out of range for `i32`"]
+        );
+    }
+
+    #[test]
+    fn test_integer_types_and_casts() {
+        check(
+            "fn f(a: u8, b: i64) -> f64 {
+    let c: u64 = 18446744073709551615;
+    let d = a as i64 + b + 255u8 as i64 + true as i64;
+    let e = -128i8;
+    return d as f64 + c as f64 + e as f64;
+}
+fn main() {}",
+        )
+        .unwrap();
+        assert_eq!(
+            errors(
+                "fn f(a: u8, s: f64) {
+    let x = 256u8;
+    let y = -a;
+    let z = a + 1i32;
+    let w = s as bool;
+    let v = s as u8 as bool;
+}
+fn main() {}"
+            ),
+            vec![
+                "2: literal out of range for `u8`",
+                "3: cannot apply unary operator `-` to type `u8`: cannot apply unary operator `-`",
+                "4: mismatched types: expected `u8`, found `i32`",
+                "5: cannot cast `f64` as `bool`: compare with zero instead: `x != 0`",
+                "6: cannot cast `u8` as `bool`: compare with zero instead: `x != 0`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_methods() {
+        check(
+            "fn f(a: u8, b: i64) -> bool {
+    let c = a.wrapping_add(1).wrapping_shl(9);
+    let d = b.checked_div(0).unwrap_or(-1) + b.wrapping_neg();
+    return a.checked_mul(c).is_some() && d.checked_sub(1).unwrap() > 0;
+}
+fn main() {}",
+        )
+        .unwrap();
+        assert_eq!(
+            errors(
+                "fn f(a: u8, s: f64) {
+    let x = a.checked_add(1);
+    let y = a.wrapping_add(1, 2);
+    let z = s.wrapping_add(1.0);
+    let w = a.len();
+    let v = a.wrapping_add(1i32);
+}
+fn main() {}"
+            ),
+            vec![
+                "2: `Option` values are not supported yet: use `.unwrap()`, `.unwrap_or(..)`, `.is_some()` or `.is_none()` on the result",
+                "3: this method takes 1 argument but 2 arguments were supplied: expected 1 argument",
+                "4: no method named `wrapping_add` found for `f64` in the current scope: method not found in `f64`",
+                "5: no method named `len` found for `u8` in the current scope: method not found in `u8`",
+                "6: mismatched types: expected `u8`, found `i32`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_macro_errors() {
+        check(
+            "fn main() {
+    let x = 1;
+    println!(\"{} {} {}\", x, true, \"s\");
+    eprint!(\"{{}}\");
+    println!();
+}",
+        )
+        .unwrap();
+        assert_eq!(
+            errors(
+                "fn main() {
+    println!(\"{} {}\", 1);
+    print!(\"{}\", 1, 2);
+    println!(\"{\");
+    println!(\"{}\", 1.5);
+    print!();
+}"
+            ),
+            vec![
+                "2: 2 positional arguments in format string, but there is 1 argument",
+                "3: argument never used: argument never used",
+                "4: invalid format string: expected `}` but string was terminated: expected `}` in format string",
+                "5: cannot format `f64` values yet: only integers, `bool`, `char` and `&str` can be formatted",
+                "6: requires at least a format string argument: `print!` needs a format string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_and_char_literals() {
+        check(
+            "fn f(s: &str, c: char) -> usize {
+    let b: &[u8; 2] = b\"hi\";
+    let n = 65u8 as char;
+    let m = 'a' as u32 + n as u32;
+    return s.len() + b.len() + \"abc\".len() + (c < 'z') as usize + m as usize;
+}
+fn main() {}",
+        )
+        .unwrap();
+        assert_eq!(
+            errors(
+                "fn f(c: char, x: i32) {
+    let a = 300 as char;
+    let b = x as char;
+    let d = c + 'a';
+    let e: &str = b\"x\";
+    let g = c.len();
+}
+fn main() {}"
+            ),
+            vec![
+                "2: literal out of range for `u8`",
+                "3: only `u8` can be cast as `char`, not `i32`: invalid cast",
+                "4: binary operation `+` cannot be applied to type `char`: `char` does not support `+`",
+                "5: mismatched types: expected `&str`, found `&[u8; 1]`",
+                "6: no method named `len` found for `char` in the current scope: method not found in `char`",
+            ]
+        );
+    }
+}